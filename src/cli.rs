@@ -1,15 +1,31 @@
 use byte_unit::{Byte, Unit};
-use clap::Parser;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Args, CommandFactory, Parser, Subcommand};
 use log::info;
 use regex::Regex;
 use sysinfo::{ProcessRefreshKind, RefreshKind, System};
 
-use ssubmit::SlurmTime;
+use std::path::PathBuf;
+
+use ssubmit::{
+    job_path, parse_array_commands, resolve_dependency, slurm_time_seconds, ArrayTask, Container,
+    Dependency, DependencyKind, Environment, JobSpec, Resources, SampleSheet, SlurmTime,
+    SubmissionError,
+};
+
+use crate::config::{find_profile, Config, Layer, Setting, Source};
 
 const SSUBMIT_SHEBANG: &str = "SSUBMIT_SHEBANG";
 const SSUBMIT_MEMORY: &str = "SSUBMIT_MEMORY";
 const SSUBMIT_TIME: &str = "SSUBMIT_TIME";
 const SSUBMIT_SET: &str = "SSUBMIT_SET";
+const SSUBMIT_PARTITION: &str = "SSUBMIT_PARTITION";
+const SSUBMIT_ACCOUNT: &str = "SSUBMIT_ACCOUNT";
+const SSUBMIT_QOS: &str = "SSUBMIT_QOS";
+
+/// Default log patterns for job arrays, so that each task writes its own files
+const ARRAY_OUTPUT: &str = "%x_%A_%a.out";
+const ARRAY_ERROR: &str = "%x_%A_%a.err";
 
 /// Submit sbatch jobs without having to create a submission script
 ///
@@ -24,7 +40,7 @@ const SSUBMIT_SET: &str = "SSUBMIT_SET";
 /// Submit a command that involves piping the output into another command. sbatch options
 /// are passed after a `--`.
 ///
-/// $ ssubmit -m 4G align "minimap2 -t 8 ref.fa reads.fq | samtools sort -o sorted.bam" -- -c 8
+/// $ ssubmit -m 4G -c 8 align "minimap2 -t 8 ref.fa reads.fq | samtools sort -o sorted.bam" -- --constraint=avx2
 ///
 /// Start an interactive session with 5GB memory for 8 hours.
 ///
@@ -33,13 +49,50 @@ const SSUBMIT_SET: &str = "SSUBMIT_SET";
 /// Start an interactive session with custom shell and additional SLURM options.
 ///
 /// $ ssubmit --interactive -m 16G -t 4h DevSession --shell bash -- --partition=general --qos=normal
+///
+/// Submit the jobs described in a pipeline file, in dependency order.
+///
+/// $ ssubmit run pipeline.toml
+///
+/// Check on jobs by ID or by the name they were submitted with.
+///
+/// $ ssubmit status 123456 align
+///
+/// List the jobs submitted today, or show the script of a past job.
+///
+/// $ ssubmit history --since 2024-05-01
+///
+/// $ ssubmit history --show align
+///
+/// Submit a job that ran out of memory again, with more memory.
+///
+/// $ ssubmit resubmit align --mem 32G
+///
+/// Cancel every job whose name starts with align_.
+///
+/// $ ssubmit cancel --regex 'align_.*'
+///
+/// Submit a job named after a subcommand, by starting the command line with `--`.
+///
+/// $ ssubmit -- run "make"
 #[derive(Parser, Debug)]
-#[clap(author, version, about, verbatim_doc_comment)]
+#[clap(
+    author,
+    version,
+    about,
+    verbatim_doc_comment,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    disable_help_subcommand = true,
+    subcommand_value_name = "SUBCOMMAND",
+    subcommand_help_heading = "Subcommands"
+)]
 pub struct Cli {
     /// Name of the job
     ///
     /// See `man sbatch | grep -A 2 'job-name='` for more details.
-    pub name: String,
+    #[arg(required_unless_present = "show_config")]
+    pub name: Option<String>,
     /// Command to be executed by the job
     ///
     /// For batch jobs, this is required. For interactive jobs (--interactive),
@@ -48,6 +101,14 @@ pub struct Cli {
     /// Options to be passed on to sbatch or salloc (for interactive jobs)
     #[arg(raw = true, last = true, allow_hyphen_values = true)]
     pub remainder: Vec<String>,
+    /// The options whose values come from the environment or a configuration file, which the
+    /// passthrough arguments override
+    #[arg(skip)]
+    pub configured: Vec<&'static str>,
+    /// The sbatch-args of the configuration file or profile that sets them, if any, which are
+    /// passed on before the remainder
+    #[arg(skip)]
+    pub configured_sbatch_args: Option<(Vec<String>, Source)>,
     /// File to write job stdout to. (See `man sbatch | grep -A 3 'output='`)
     ///
     /// Run `man sbatch | grep -A 37 '^filename pattern'` to see available patterns.
@@ -58,6 +119,20 @@ pub struct Cli {
     /// Run `man sbatch | grep -A 37 '^filename pattern'` to see available patterns.
     #[arg(short, long, default_value = "%x.err")]
     pub error: String,
+    /// Create the directories of the output and error files if they do not exist
+    ///
+    /// Slurm fails a job without writing anything when they are missing, so ssubmit checks they
+    /// exist and are writable before submitting. A dry run reports missing directories, unless
+    /// --mkdir is given, but never creates them.
+    #[arg(long)]
+    pub mkdir: bool,
+    /// Do not check the job against the limits of its partition before submitting
+    ///
+    /// The memory, time limit, CPUs and GPUs of a job are checked against the partition's limits
+    /// and largest node, from scontrol and sinfo. These are cached for a day in
+    /// $XDG_CACHE_HOME/ssubmit/partitions.json, and queried again before a job is rejected.
+    #[arg(long)]
+    pub no_check_limits: bool,
     /// Specify the real memory required per node. e.g., 4.3kb, 7 Gb, 9000, 4.1MB become 5KB, 7000M,
     /// 9000M, and 5M, respectively.
     ///
@@ -74,6 +149,184 @@ pub struct Cli {
     /// 5 seconds.
     #[arg(short, long, value_parser = parse_time, default_value = "1d", env = SSUBMIT_TIME)]
     pub time: String,
+    /// Partition (queue) to submit the job to. [sbatch --partition]
+    ///
+    /// When not set, or set to '', the cluster's default partition is used.
+    #[arg(short, long, env = SSUBMIT_PARTITION)]
+    pub partition: Option<String>,
+    /// Choose the partition with the shortest time limit that fits the job
+    ///
+    /// The job's memory, time limit, CPUs and GPUs, and those of any retries, are compared with
+    /// the limits of every partition, cached as described for --no-check-limits. The choice, and
+    /// why it was made, are logged and recorded in the plan. A partition set in the environment or
+    /// configuration is ignored.
+    #[arg(long, conflicts_with = "interactive")]
+    pub auto_partition: bool,
+    /// Account to charge the job's resources to. [sbatch --account]
+    #[arg(short = 'A', long, env = SSUBMIT_ACCOUNT)]
+    pub account: Option<String>,
+    /// Quality of service to request for the job. [sbatch --qos]
+    #[arg(short, long, env = SSUBMIT_QOS)]
+    pub qos: Option<String>,
+    /// Number of CPUs required per task. [sbatch --cpus-per-task]
+    #[arg(short, long, value_name = "ncpus", value_parser = clap::value_parser!(u32).range(1..))]
+    pub cpus_per_task: Option<u32>,
+    /// Number of tasks to launch. [sbatch --ntasks]
+    #[arg(long, value_name = "number", value_parser = clap::value_parser!(u32).range(1..))]
+    pub ntasks: Option<u32>,
+    /// Number of nodes to allocate. [sbatch --nodes]
+    #[arg(short = 'N', long, value_name = "number", value_parser = clap::value_parser!(u32).range(1..))]
+    pub nodes: Option<u32>,
+    /// Number of tasks to launch on each node. [sbatch --ntasks-per-node]
+    #[arg(long, value_name = "ntasks", value_parser = clap::value_parser!(u32).range(1..))]
+    pub ntasks_per_node: Option<u32>,
+    /// Total number of GPUs required for the job. e.g., 2 or a100:2 [sbatch --gpus]
+    #[arg(short = 'G', long, value_name = "[type:]number", value_parser = parse_gpus)]
+    pub gpus: Option<String>,
+    /// Generic resources required per node. e.g., 2, a100:2, gpu:a100:2,shard:4 [sbatch --gres]
+    ///
+    /// A bare number, type or `type:number` is taken to be a GPU request, so 2, a100 and a100:2
+    /// become gpu:2, gpu:a100 and gpu:a100:2, respectively. Other generic resources must be
+    /// named, e.g. shard:4. Without a number, one of the resource is requested.
+    /// See `man sbatch | grep -A 12 'gres=<'` for the full details.
+    #[arg(long, value_name = "list", value_parser = parse_gres)]
+    pub gres: Option<String>,
+    /// Submit a job array that runs one command per line of this file [sbatch --array]
+    ///
+    /// Task N runs the command on line N, ignoring blank lines and lines starting with #, and the
+    /// command argument must be omitted. Unless they are set elsewhere, output and error default to
+    /// %x_%A_%a.out and %x_%A_%a.err so that each task writes its own logs.
+    #[arg(long, value_name = "path", conflicts_with_all = ["command", "interactive"])]
+    pub array_file: Option<PathBuf>,
+    /// Submit a job array with one task per row of this TSV or CSV file [sbatch --array]
+    ///
+    /// The first row names the columns, and the command becomes a template in which {column} is
+    /// replaced by the task's value, quoted if the shell would split or interpret it. Every column
+    /// is also exported as a variable, so it can be used as $column too. Files ending in .csv are
+    /// comma-separated, any others tab-separated. Output and error default to the same patterns
+    /// as for --array-file.
+    #[arg(long, value_name = "path", conflicts_with_all = ["array_file", "interactive"], requires = "command")]
+    pub sample_sheet: Option<PathBuf>,
+    /// Maximum number of array tasks to run at the same time
+    #[arg(long, value_name = "ntasks", value_parser = clap::value_parser!(u32).range(1..))]
+    pub array_throttle: Option<u32>,
+    /// Start the job once these jobs have started [sbatch --dependency=after]
+    ///
+    /// Jobs are given as IDs or names, separated by commas or by repeating the option. A name
+    /// refers to your queued and running jobs with that name or, if there are none, to your most
+    /// recent job with that name in the last week.
+    #[arg(long, value_name = "job", value_delimiter = ',')]
+    pub after: Vec<String>,
+    /// Start the job once these jobs have completed successfully [sbatch --dependency=afterok]
+    ///
+    /// Jobs are given as IDs or names, as for --after.
+    #[arg(long, value_name = "job", value_delimiter = ',')]
+    pub after_ok: Vec<String>,
+    /// Start the job once these jobs have finished, whatever their state
+    /// [sbatch --dependency=afterany]
+    ///
+    /// Jobs are given as IDs or names, as for --after.
+    #[arg(long, value_name = "job", value_delimiter = ',')]
+    pub after_any: Vec<String>,
+    /// Start the job only if these jobs fail [sbatch --dependency=afternotok]
+    ///
+    /// Jobs are given as IDs or names, as for --after.
+    #[arg(long, value_name = "job", value_delimiter = ',')]
+    pub after_notok: Vec<String>,
+    /// Submit the job again, up to this many times, if it runs out of memory or time
+    ///
+    /// Every retry is submitted straight away, depending on all of the earlier attempts failing
+    /// [sbatch --dependency=afternotok]. A retry only runs the command if sacct reports that the
+    /// previous attempt ended as OUT_OF_MEMORY or TIMEOUT, and Slurm removes retries that can no
+    /// longer run [sbatch --kill-on-invalid-dep=yes].
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..), conflicts_with_all = ["interactive", "array_file", "sample_sheet"])]
+    pub retry: Option<u32>,
+    /// Multiply the memory of each retry by this factor, e.g. 2x
+    #[arg(long, value_name = "factor", value_parser = parse_factor)]
+    pub escalate_mem: Option<f64>,
+    /// Multiply the time limit of each retry by this factor, e.g. 1.5x
+    #[arg(long, value_name = "factor", value_parser = parse_factor)]
+    pub escalate_time: Option<f64>,
+    /// Wait for the job to finish, logging its state changes, and exit with its exit code
+    ///
+    /// The job's state is checked with sacct, every second at first and backing off to every
+    /// minute, and with squeue until sacct has a record of it. Waiting fails if neither has the
+    /// job for three checks in a row. With --retry, a retry that runs is waited for too. A job
+    /// that ends without an exit code, e.g. because it was killed by a signal, exits with 128
+    /// plus the signal number, or 1.
+    #[arg(long, conflicts_with = "interactive")]
+    pub wait: bool,
+    /// Stream the job's output and error files until it finishes, as with --wait
+    ///
+    /// The %x, %j, %A, %a and %u patterns in the file names are expanded once the job ID is known.
+    /// Each line is printed to stdout prefixed with the file it came from, e.g. [out] or [err 2]
+    /// for the error file of array task 2.
+    #[arg(long, conflicts_with_all = ["interactive", "json"])]
+    pub follow: bool,
+    /// Run the command in this container image, with Apptainer or Singularity
+    ///
+    /// The image is a file, e.g. tools.sif, or a URI, e.g. docker://ubuntu:24.04. The command is
+    /// run in the container by the shell of --shebang, with the --set options, and with the
+    /// working directory and any absolute directories of the output and error files bound into
+    /// it. Apptainer is used if it is installed on the node the job runs on, otherwise Singularity.
+    #[arg(long, value_name = "image", conflicts_with = "interactive")]
+    pub container: Option<String>,
+    /// Bind these paths into the container as well, e.g. /data or /scratch:/tmp:ro
+    ///
+    /// Paths are given as src[:dest[:opts]], separated by commas or by repeating the option.
+    #[arg(long, value_name = "path", value_delimiter = ',')]
+    pub bind: Vec<String>,
+    /// Make the node's NVIDIA GPUs available in the container [apptainer exec --nv]
+    #[arg(long)]
+    pub nv: bool,
+    /// Load these environment modules before running the command, e.g. samtools/1.17
+    ///
+    /// Modules are given separated by commas or by repeating the option. The script runs
+    /// `module purge`, then `module load` with the modules, in order. Before the job is submitted,
+    /// the modules are checked against `module -t avail`, if the module command is available.
+    #[arg(
+        long,
+        value_name = "name[/version]",
+        value_delimiter = ',',
+        conflicts_with = "interactive"
+    )]
+    pub module: Vec<String>,
+    /// Activate this conda environment, given by name or path, before running the command
+    ///
+    /// Conda's shell hook for the shell in the shebang is sourced in the script, then the
+    /// environment is activated, with micromamba if conda is not installed on the node. The u and
+    /// x shell options are turned off while this runs.
+    #[arg(long, value_name = "name|path", conflicts_with = "interactive")]
+    pub conda_env: Option<String>,
+    /// Activate this Python virtual environment, e.g. one made by venv or uv, before running
+    /// the command
+    ///
+    /// The environment's bin/activate script is sourced. A relative path is relative to the
+    /// directory the job runs in.
+    #[arg(long, value_name = "path", conflicts_with = "interactive")]
+    pub venv: Option<String>,
+    /// Activate the default environment of the pixi project with this manifest before running the
+    /// command
+    ///
+    /// The manifest is a pixi.toml or pyproject.toml file, and the environment is activated with
+    /// `pixi shell-hook`. A relative path is relative to the directory the job runs in.
+    #[arg(long, value_name = "manifest", conflicts_with = "interactive")]
+    pub pixi: Option<String>,
+    /// Commands to run before the command, given inline or as the path of a file containing them
+    ///
+    /// They run once the environment, e.g. modules or a conda environment, is set up, and
+    /// outside of any container. A value that names an existing file is replaced by its contents,
+    /// and a relative path is relative to the directory the job runs in.
+    #[arg(long, value_name = "commands|path", conflicts_with = "interactive")]
+    pub prologue: Option<String>,
+    /// Commands to run when the job's script exits, given inline or as the path of a file
+    ///
+    /// They run from a `trap ... EXIT`, so also run when the command fails and `set -e` ends the
+    /// script early, and the job keeps the command's exit code. A value that names an existing
+    /// file is replaced by its contents, and a relative path is relative to the directory the job
+    /// runs in.
+    #[arg(long, value_name = "commands|path", conflicts_with = "interactive")]
+    pub epilogue: Option<String>,
     /// The shell shebang for the submission script
     #[arg(short = 'S', long, default_value = "#!/usr/bin/env bash", env = SSUBMIT_SHEBANG)]
     pub shebang: String,
@@ -118,6 +371,293 @@ pub struct Cli {
     /// 'ALL' to export all variables, or specify specific variables like 'PATH,HOME'.
     #[arg(long, default_value = "ALL")]
     pub export: String,
+    /// Apply a named profile from the configuration files
+    ///
+    /// A profile is a bundle of settings, e.g. memory, time, partition and GRES, defined under
+    /// `[profiles.<name>]` in a config file. Options given on the command line override the
+    /// profile, which in turn overrides environment variables and config file defaults.
+    #[arg(short = 'P', long)]
+    pub profile: Option<String>,
+    /// Print the merged configuration, and where each value came from, then exit
+    ///
+    /// Defaults can be set in a user config file (~/.config/ssubmit/config.toml) and a project
+    /// config file (.ssubmit.toml in the working directory or any of its parents). Precedence is
+    /// command line > profile > environment variable > project config > user config > built-in
+    /// default.
+    #[arg(long)]
+    pub show_config: bool,
+    #[command(subcommand)]
+    pub action: Option<Action>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Action {
+    /// Submit a pipeline of jobs described in a TOML file, in dependency order
+    ///
+    /// Each job is submitted with an afterok dependency on the jobs it depends on, using the job
+    /// IDs Slurm returned for them.
+    Run(RunArgs),
+    /// Report the state, elapsed time, nodes, exit code and pending reason of jobs
+    ///
+    /// Queued and running jobs are looked up with squeue and finished jobs with sacct. A job name
+    /// matches the current user's jobs with that name from the last week.
+    Status(StatusArgs),
+    /// List the jobs ssubmit has submitted, or show the script of one of them
+    ///
+    /// Every successful submission is recorded in $XDG_STATE_HOME/ssubmit/history.jsonl
+    /// (~/.local/state/ssubmit/history.jsonl by default), with the time, working directory,
+    /// submission plan, job ID and cluster.
+    History(HistoryArgs),
+    /// Submit a job from the submission history again, optionally with different resources
+    ///
+    /// The job's script is rendered again with any options given here, and submitted with the
+    /// same sbatch options as the original, except for its dependencies. The new job records
+    /// the ID of the job it is a resubmission of.
+    Resubmit(ResubmitArgs),
+    /// Cancel queued and running jobs given by ID, name or name pattern
+    ///
+    /// The matching jobs are listed, and cancelled with scancel once confirmed.
+    Cancel(CancelArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct RunArgs {
+    /// TOML file describing the jobs, their options and what they depend on
+    pub pipeline: PathBuf,
+    /// Print the sbatch command and submission script for every job, in submission order, but
+    /// do not submit them
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+    /// Create the directories of the jobs' output and error files if they do not exist
+    #[arg(long)]
+    pub mkdir: bool,
+    /// Do not check the jobs against the limits of their partitions before submitting
+    #[arg(long)]
+    pub no_check_limits: bool,
+    /// Print one versioned machine-readable response listing every job
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct StatusArgs {
+    /// Job IDs, or names given to ssubmit
+    #[arg(required = true, value_name = "JOB")]
+    pub jobs: Vec<String>,
+    /// Print one versioned machine-readable response listing every job
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct HistoryArgs {
+    /// Print the exact script submitted for a job, given by job ID or name (the latest job
+    /// with that name)
+    #[arg(long, value_name = "JOB")]
+    pub show: Option<String>,
+    /// Only list jobs with this name
+    #[arg(long)]
+    pub name: Option<String>,
+    /// Only list jobs submitted on or after this local date or time, e.g. 2024-05-01 or
+    /// "2024-05-01 13:30"
+    #[arg(long, value_name = "DATE")]
+    pub since: Option<String>,
+    /// Only list jobs submitted before the end of this local date, or before this time
+    #[arg(long, value_name = "DATE")]
+    pub until: Option<String>,
+    /// Only list jobs currently in one of these Slurm states, e.g. RUNNING or FAILED, as
+    /// reported by squeue and sacct
+    #[arg(long, value_delimiter = ',')]
+    pub state: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct ResubmitArgs {
+    /// Job ID, or name of the latest job with that name, in the submission history
+    pub job: String,
+    /// Memory for the new job, in the same format as for submission
+    #[arg(short, long = "mem", value_name = "size[unit]", value_parser = parse_memory)]
+    pub memory: Option<String>,
+    /// Time limit for the new job, in the same format as for submission
+    #[arg(short, long, value_parser = parse_time)]
+    pub time: Option<String>,
+    /// Partition for the new job; '' uses the cluster's default partition
+    #[arg(short, long)]
+    pub partition: Option<String>,
+    /// Account for the new job
+    #[arg(short = 'A', long)]
+    pub account: Option<String>,
+    /// Quality of service for the new job
+    #[arg(short, long)]
+    pub qos: Option<String>,
+    /// Number of CPUs per task for the new job
+    #[arg(short, long, value_name = "ncpus", value_parser = clap::value_parser!(u32).range(1..))]
+    pub cpus_per_task: Option<u32>,
+    /// GPUs for the new job, e.g. 2 or a100:2
+    #[arg(short = 'G', long, value_name = "[type:]number", value_parser = parse_gpus)]
+    pub gpus: Option<String>,
+    /// Print the sbatch command and submission script for the new job, but do not submit it
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+    /// Do not check the new job against the limits of its partition before submitting
+    #[arg(long)]
+    pub no_check_limits: bool,
+    /// Print one versioned machine-readable response, as for a submission
+    #[arg(long)]
+    pub json: bool,
+}
+
+impl ResubmitArgs {
+    /// Apply the options given to the job specification of the original job
+    pub fn apply(&self, job: &mut JobSpec) {
+        if let Some(memory) = &self.memory {
+            job.memory = memory.clone();
+        }
+        if let Some(time) = &self.time {
+            job.time = time.clone();
+        }
+        if self.partition.is_some() {
+            job.resources.partition = non_empty(&self.partition);
+        }
+        if self.account.is_some() {
+            job.resources.account = non_empty(&self.account);
+        }
+        if self.qos.is_some() {
+            job.resources.qos = non_empty(&self.qos);
+        }
+        if self.cpus_per_task.is_some() {
+            job.resources.cpus_per_task = self.cpus_per_task;
+        }
+        if self.gpus.is_some() {
+            job.resources.gpus = self.gpus.clone();
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct CancelArgs {
+    /// Job IDs or names of your queued and running jobs
+    #[arg(required = true, value_name = "JOB")]
+    pub jobs: Vec<String>,
+    /// Treat names as regular expressions that must match the whole job name, e.g. 'align_.*'
+    #[arg(short = 'E', long)]
+    pub regex: bool,
+    /// Cancel the jobs without asking for confirmation
+    #[arg(short, long)]
+    pub yes: bool,
+    /// Print one versioned machine-readable response listing the cancelled and failed job IDs.
+    /// Requires --yes
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// Settings that can be shown by --show-config
+trait SettingValue {
+    fn to_toml(&self) -> Option<toml::Value>;
+}
+
+impl SettingValue for String {
+    fn to_toml(&self) -> Option<toml::Value> {
+        Some(toml::Value::String(self.clone()))
+    }
+}
+
+impl SettingValue for Option<String> {
+    fn to_toml(&self) -> Option<toml::Value> {
+        non_empty(self).map(toml::Value::String)
+    }
+}
+
+impl SettingValue for Vec<String> {
+    fn to_toml(&self) -> Option<toml::Value> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(toml::Value::Array(
+                self.iter().cloned().map(toml::Value::String).collect(),
+            ))
+        }
+    }
+}
+
+impl SettingValue for bool {
+    fn to_toml(&self) -> Option<toml::Value> {
+        Some(toml::Value::Boolean(*self))
+    }
+}
+
+impl SettingValue for Option<u32> {
+    fn to_toml(&self) -> Option<toml::Value> {
+        self.map(|value| toml::Value::Integer(value.into()))
+    }
+}
+
+/// Fills in options that were not given on the command line from the selected profile or, if
+/// they were not given through an environment variable either, from the first configuration file
+/// that sets them
+struct Resolver<'a> {
+    command: clap::Command,
+    matches: &'a ArgMatches,
+    profile: Option<&'a Layer>,
+    layers: &'a [Layer],
+    settings: Vec<Setting>,
+}
+
+impl Resolver<'_> {
+    fn resolve<T: SettingValue>(
+        &mut self,
+        key: &'static str,
+        id: &str,
+        value: &mut T,
+        from_config: impl Fn(&Config) -> Option<Result<T, String>>,
+    ) -> Result<(), String> {
+        let from_layer =
+            |layer: &Layer| from_config(&layer.config).map(|parsed| (parsed, layer.source.clone()));
+        let value_source = self.matches.value_source(id);
+
+        let configured = if value_source == Some(ValueSource::CommandLine) {
+            None
+        } else if let Some(configured) = self.profile.and_then(from_layer) {
+            Some(configured)
+        } else if value_source == Some(ValueSource::EnvVariable) {
+            None
+        } else {
+            self.layers.iter().find_map(from_layer)
+        };
+
+        let source = match (configured, value_source) {
+            (Some((parsed, source)), _) => {
+                *value = parsed.map_err(|e| format!("Invalid {key} in {source}: {e}"))?;
+                source
+            }
+            (None, Some(ValueSource::CommandLine)) => Source::CommandLine,
+            (None, Some(ValueSource::EnvVariable)) => {
+                let variable = self
+                    .command
+                    .get_arguments()
+                    .find(|arg| arg.get_id() == id)
+                    .and_then(|arg| arg.get_env())
+                    .map(|variable| variable.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                Source::Environment(variable)
+            }
+            (None, _) => Source::Default,
+        };
+        self.settings.push(Setting {
+            key,
+            value: value.to_toml(),
+            source,
+        });
+        Ok(())
+    }
+}
+
+fn positive(value: u32) -> Result<Option<u32>, String> {
+    if value == 0 {
+        Err("value must be a positive number".to_string())
+    } else {
+        Ok(Some(value))
+    }
 }
 
 /// Try to get shell path using 'which' command
@@ -203,7 +743,200 @@ fn get_user_shell() -> String {
     get_shell_path_via_which("bash").unwrap_or_else(|| "bash".to_string())
 }
 
+/// The subcommands, each renamed with a leading NUL, for command lines that start with `--`
+const HIDDEN_SUBCOMMANDS: [&str; 5] = ["\0run", "\0status", "\0history", "\0resubmit", "\0cancel"];
+
 impl Cli {
+    /// Parse the command line, exiting on error, and keep the matches so the source of each
+    /// value can be checked when applying configuration files. A leading `--` submits a job
+    /// whose name is also a subcommand, e.g. `ssubmit -- run "make"`.
+    pub fn parse_with_matches() -> (Self, ArgMatches) {
+        let mut arguments: Vec<std::ffi::OsString> = std::env::args_os().collect();
+        let mut command = Self::command();
+        if arguments.get(1).map_or(false, |argument| argument == "--") {
+            arguments.remove(1);
+            // no argument can contain a NUL, so the renamed subcommands are never matched
+            for hidden in HIDDEN_SUBCOMMANDS {
+                command = command.mut_subcommand(&hidden[1..], |subcommand| {
+                    subcommand.name(hidden).hide(true)
+                });
+            }
+        }
+        let matches = command.get_matches_from(arguments);
+        let cli = <Self as clap::FromArgMatches>::from_arg_matches(&matches)
+            .unwrap_or_else(|error| error.exit());
+        (cli, matches)
+    }
+
+    /// Parse the given arguments, keeping the matches as for [`Cli::parse_with_matches`]
+    pub fn try_parse_with_matches<I, T>(arguments: I) -> Result<(Self, ArgMatches), clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString> + Clone,
+    {
+        let matches = Self::command().try_get_matches_from(arguments)?;
+        let cli = <Self as clap::FromArgMatches>::from_arg_matches(&matches)?;
+        Ok((cli, matches))
+    }
+
+    /// Fill in any options not set on the command line from the selected profile, then from the
+    /// environment and configuration files, returning every setting and where its value came from
+    pub fn apply_config(
+        &mut self,
+        matches: &ArgMatches,
+        layers: &[Layer],
+    ) -> Result<Vec<Setting>, String> {
+        let profile = match &self.profile {
+            Some(name) => Some(find_profile(layers, name)?),
+            None => None,
+        };
+        let mut resolver = Resolver {
+            command: Self::command(),
+            matches,
+            profile: profile.as_ref(),
+            layers,
+            settings: Vec::new(),
+        };
+
+        resolver.resolve("output", "output", &mut self.output, |c| {
+            c.output.clone().map(Ok)
+        })?;
+        resolver.resolve("error", "error", &mut self.error, |c| {
+            c.error.clone().map(Ok)
+        })?;
+        resolver.resolve("mem", "memory", &mut self.memory, |c| {
+            c.mem.as_deref().map(parse_memory)
+        })?;
+        resolver.resolve("time", "time", &mut self.time, |c| {
+            c.time.as_deref().map(parse_time)
+        })?;
+        resolver.resolve("partition", "partition", &mut self.partition, |c| {
+            c.partition.clone().map(|value| Ok(Some(value)))
+        })?;
+        resolver.resolve("account", "account", &mut self.account, |c| {
+            c.account.clone().map(|value| Ok(Some(value)))
+        })?;
+        resolver.resolve("qos", "qos", &mut self.qos, |c| {
+            c.qos.clone().map(|value| Ok(Some(value)))
+        })?;
+        resolver.resolve(
+            "cpus-per-task",
+            "cpus_per_task",
+            &mut self.cpus_per_task,
+            |c| c.cpus_per_task.map(positive),
+        )?;
+        resolver.resolve("ntasks", "ntasks", &mut self.ntasks, |c| {
+            c.ntasks.map(positive)
+        })?;
+        resolver.resolve("nodes", "nodes", &mut self.nodes, |c| c.nodes.map(positive))?;
+        resolver.resolve(
+            "ntasks-per-node",
+            "ntasks_per_node",
+            &mut self.ntasks_per_node,
+            |c| c.ntasks_per_node.map(positive),
+        )?;
+        resolver.resolve("gpus", "gpus", &mut self.gpus, |c| {
+            c.gpus.as_deref().map(|gpus| parse_gpus(gpus).map(Some))
+        })?;
+        resolver.resolve("gres", "gres", &mut self.gres, |c| {
+            c.gres.as_deref().map(|gres| parse_gres(gres).map(Some))
+        })?;
+        resolver.resolve("container", "container", &mut self.container, |c| {
+            c.container.clone().map(|value| Ok(Some(value)))
+        })?;
+        resolver.resolve("bind", "bind", &mut self.bind, |c| c.bind.clone().map(Ok))?;
+        resolver.resolve("nv", "nv", &mut self.nv, |c| c.nv.map(Ok))?;
+        resolver.resolve("module", "module", &mut self.module, |c| {
+            c.module.clone().map(Ok)
+        })?;
+        resolver.resolve("conda-env", "conda_env", &mut self.conda_env, |c| {
+            c.conda_env.clone().map(|value| Ok(Some(value)))
+        })?;
+        resolver.resolve("venv", "venv", &mut self.venv, |c| {
+            c.venv.clone().map(|value| Ok(Some(value)))
+        })?;
+        resolver.resolve("pixi", "pixi", &mut self.pixi, |c| {
+            c.pixi.clone().map(|value| Ok(Some(value)))
+        })?;
+        resolver.resolve("prologue", "prologue", &mut self.prologue, |c| {
+            c.prologue.clone().map(|value| Ok(Some(value)))
+        })?;
+        resolver.resolve("epilogue", "epilogue", &mut self.epilogue, |c| {
+            c.epilogue.clone().map(|value| Ok(Some(value)))
+        })?;
+        resolver.resolve("shebang", "shebang", &mut self.shebang, |c| {
+            c.shebang.clone().map(Ok)
+        })?;
+        resolver.resolve("set", "set", &mut self.set, |c| c.set.clone().map(Ok))?;
+        resolver.resolve("shell", "shell", &mut self.shell, |c| {
+            c.shell.clone().map(Ok)
+        })?;
+        resolver.resolve("export", "export", &mut self.export, |c| {
+            c.export.clone().map(Ok)
+        })?;
+
+        let mut settings = resolver.settings;
+        self.configured = settings
+            .iter()
+            .filter(|setting| !matches!(setting.source, Source::CommandLine | Source::Default))
+            .map(|setting| setting.key)
+            .collect();
+        if self.is_array() {
+            for setting in settings.iter_mut().filter(|s| s.source == Source::Default) {
+                let (value, pattern) = match setting.key {
+                    "output" => (&mut self.output, ARRAY_OUTPUT),
+                    "error" => (&mut self.error, ARRAY_ERROR),
+                    _ => continue,
+                };
+                *value = pattern.to_string();
+                setting.value = value.to_toml();
+            }
+        }
+
+        let sbatch_args = profile.iter().chain(layers).find_map(|layer| {
+            layer
+                .config
+                .sbatch_args
+                .as_ref()
+                .map(|args| (args, &layer.source))
+        });
+        match sbatch_args {
+            Some((args, source)) => {
+                self.configured_sbatch_args = Some((args.clone(), source.clone()));
+                settings.push(Setting {
+                    key: "sbatch-args",
+                    value: Some(toml::Value::Array(
+                        args.iter().cloned().map(toml::Value::String).collect(),
+                    )),
+                    source: source.clone(),
+                });
+            }
+            None => settings.push(Setting {
+                key: "sbatch-args",
+                value: None,
+                source: Source::Default,
+            }),
+        }
+
+        if let Some(profile) = &profile {
+            settings.insert(
+                0,
+                Setting {
+                    key: "profile",
+                    value: self.profile.to_toml(),
+                    source: profile.source.clone(),
+                },
+            );
+        }
+
+        Ok(settings)
+    }
+
+    /// The job name, which is required for everything except --show-config
+    pub fn job_name(&self) -> &str {
+        self.name.as_deref().unwrap_or_default()
+    }
+
     /// Validate the arguments and return the command to execute
     pub fn validate_and_get_command(&self) -> Result<String, String> {
         if self.interactive {
@@ -225,6 +958,207 @@ impl Cli {
             })
         }
     }
+
+    /// Whether a job array was requested with --array-file or --sample-sheet
+    fn is_array(&self) -> bool {
+        self.array_file.is_some() || self.sample_sheet.is_some()
+    }
+
+    /// The memory and time of each retry, escalated from the job's own
+    pub fn retry_resources(&self, job: &JobSpec) -> Result<Vec<(String, String)>, String> {
+        let retries = match self.retry {
+            Some(retries) => retries,
+            None if self.escalate_mem.is_some() || self.escalate_time.is_some() => {
+                return Err(
+                    "--escalate-mem and --escalate-time can only be used with --retry".to_string(),
+                )
+            }
+            None => return Ok(vec![]),
+        };
+
+        let mut memory = job.memory.clone();
+        let mut time = job.time.clone();
+        let mut resources = Vec::with_capacity(retries as usize);
+        for _ in 0..retries {
+            if let Some(factor) = self.escalate_mem {
+                memory = scale_memory(&memory, factor)?;
+            }
+            if let Some(factor) = self.escalate_time {
+                time = scale_time(&time, factor)?;
+            }
+            resources.push((memory.clone(), time.clone()));
+        }
+        Ok(resources)
+    }
+
+    /// The tasks of the job array, if --array-file or --sample-sheet was given
+    pub fn array_tasks(&self) -> Result<Option<Vec<ArrayTask>>, String> {
+        if let Some(path) = &self.sample_sheet {
+            let contents = std::fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read sample sheet {}: {e}", path.display()))?;
+            let delimiter = match path.extension() {
+                Some(extension) if extension.eq_ignore_ascii_case("csv") => b',',
+                _ => b'\t',
+            };
+            let sheet = SampleSheet::parse(&contents, delimiter)
+                .map_err(|e| format!("Invalid sample sheet {}: {e}", path.display()))?;
+            return sheet
+                .expand(self.command.as_deref().unwrap_or_default())
+                .map(Some);
+        }
+
+        let Some(path) = &self.array_file else {
+            return match self.array_throttle {
+                Some(_) => Err(
+                    "--array-throttle can only be used with --array-file or --sample-sheet"
+                        .to_string(),
+                ),
+                None => Ok(None),
+            };
+        };
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read array file {}: {e}", path.display()))?;
+        let commands = parse_array_commands(&contents);
+        if commands.is_empty() {
+            return Err(format!(
+                "Array file {} contains no commands",
+                path.display()
+            ));
+        }
+        let tasks = commands
+            .into_iter()
+            .map(|command| ArrayTask {
+                command,
+                variables: Vec::new(),
+            })
+            .collect();
+        Ok(Some(tasks))
+    }
+
+    /// The jobs this job depends on, resolving job names to IDs
+    pub fn dependencies(&self) -> Result<Vec<Dependency>, SubmissionError> {
+        [
+            (DependencyKind::After, &self.after),
+            (DependencyKind::AfterOk, &self.after_ok),
+            (DependencyKind::AfterAny, &self.after_any),
+            (DependencyKind::AfterNotOk, &self.after_notok),
+        ]
+        .into_iter()
+        .flat_map(|(kind, jobs)| jobs.iter().map(move |job| resolve_dependency(kind, job)))
+        .collect()
+    }
+
+    /// The first-class resource requests, without those from the environment or a configuration
+    /// file that the passthrough arguments override
+    pub fn resources(&self) -> Resources {
+        let mut resources = Resources {
+            partition: non_empty(&self.partition),
+            account: non_empty(&self.account),
+            qos: non_empty(&self.qos),
+            cpus_per_task: self.cpus_per_task,
+            ntasks: self.ntasks,
+            nodes: self.nodes,
+            ntasks_per_node: self.ntasks_per_node,
+            gpus: self.gpus.clone(),
+            gres: self.gres.clone(),
+            array: None,
+            dependency: None,
+        };
+        resources.defer_to_passthrough(&self.configured, &self.sbatch_arguments());
+        resources
+    }
+
+    /// The options passed on to sbatch or salloc: the sbatch-args of the configuration, then
+    /// the remainder
+    pub fn sbatch_arguments(&self) -> Vec<String> {
+        let mut arguments = match &self.configured_sbatch_args {
+            Some((arguments, _)) => arguments.clone(),
+            None => Vec::new(),
+        };
+        arguments.extend(self.remainder.iter().cloned());
+        arguments
+    }
+
+    /// Reject resources that are requested both first-class and in the remainder, or in the
+    /// sbatch-args of the configuration
+    pub fn check_conflicts(&self, resources: &Resources) -> Result<(), SubmissionError> {
+        resources.check_conflicts(&self.remainder)?;
+        if let Some((arguments, source)) = &self.configured_sbatch_args {
+            if let Some(option) = resources.conflict_in(arguments) {
+                return Err(SubmissionError::validation(format!(
+                    "{option} was given both as an ssubmit option and in the sbatch-args of {source}; remove one of them"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// The software environment the command runs in
+    pub fn environment(&self) -> Result<Environment, String> {
+        let container = match non_empty(&self.container) {
+            Some(image) => Some(Container {
+                image,
+                binds: self.bind.clone(),
+                nv: self.nv,
+            }),
+            None if !self.bind.is_empty() || self.nv => {
+                return Err("--bind and --nv can only be used with --container".to_string())
+            }
+            None => None,
+        };
+        let environment = Environment {
+            container,
+            conda_env: non_empty(&self.conda_env),
+            modules: self.module.clone(),
+            venv: non_empty(&self.venv),
+            pixi: non_empty(&self.pixi),
+            prologue: script_hook(&self.prologue, "prologue", &self.sbatch_arguments())?,
+            epilogue: script_hook(&self.epilogue, "epilogue", &self.sbatch_arguments())?,
+        };
+
+        // options set in configuration files are not checked for conflicts by clap
+        let exclusive: Vec<&str> = [
+            ("--venv", environment.venv.is_some()),
+            ("--pixi", environment.pixi.is_some()),
+            ("--conda-env", environment.conda_env.is_some()),
+            ("--container", environment.container.is_some()),
+        ]
+        .iter()
+        .filter(|(_, given)| *given)
+        .map(|(option, _)| *option)
+        .collect();
+        if let [first, second, ..] = exclusive.as_slice() {
+            return Err(format!("{first} cannot be used with {second}"));
+        }
+
+        environment.check_paths(&self.sbatch_arguments())?;
+        Ok(environment)
+    }
+}
+
+/// The commands of a prologue or epilogue, read from the file `value` names if there is one,
+/// otherwise `value` itself, or none if they are blank. A relative path is relative to the
+/// `--chdir` in `arguments`, like the other paths of a job's environment.
+fn script_hook(
+    value: &Option<String>,
+    kind: &str,
+    arguments: &[String],
+) -> Result<Option<String>, String> {
+    let Some(value) = value.clone().filter(|value| !value.trim().is_empty()) else {
+        return Ok(None);
+    };
+    let path = job_path(&value, arguments);
+    if !path.is_file() {
+        return Ok(Some(value));
+    }
+    let commands = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read {kind} file {}: {e}", path.display()))?;
+    Ok(Some(commands).filter(|commands| !commands.trim().is_empty()))
+}
+
+/// Treat an empty value (e.g. from an environment variable set to '') as unset
+fn non_empty(value: &Option<String>) -> Option<String> {
+    value.clone().filter(|value| !value.is_empty())
 }
 
 /// Parse a time string into a slurm time format
@@ -293,6 +1227,134 @@ fn parse_memory(s: &str) -> Result<String, String> {
     Ok(format!("{value}{unit}"))
 }
 
+/// Parse a scaling factor of at least 1, e.g. 2x or 1.5
+fn parse_factor(s: &str) -> Result<f64, String> {
+    let value = s.trim().trim_end_matches(['x', 'X']);
+    match value.parse::<f64>() {
+        Ok(factor) if factor.is_finite() && factor >= 1.0 => Ok(factor),
+        _ => Err(format!(
+            "{s} is not a valid factor; expected a number of at least 1, e.g. 2x or 1.5x"
+        )),
+    }
+}
+
+/// Scale a memory size, as normalised by `parse_memory`, by a factor
+fn scale_memory(memory: &str, factor: f64) -> Result<String, String> {
+    if memory == "0" {
+        return Err(
+            "--escalate-mem cannot scale --mem 0, which uses the cluster default memory"
+                .to_string(),
+        );
+    }
+    let bytes = Byte::parse_str(memory, true).map_err(|e| e.to_string())?;
+    let scaled = (bytes.as_u64() as f64 * factor).ceil() as u64;
+    parse_memory(&format!("{scaled}B"))
+}
+
+/// Scale a time limit, as normalised by `parse_time`, by a factor
+fn scale_time(time: &str, factor: f64) -> Result<String, String> {
+    let seconds = parse_time(time)
+        .ok()
+        .and_then(|time| slurm_time_seconds(&time))
+        .ok_or_else(|| format!("--escalate-time cannot scale the time limit {time}"))?;
+    if seconds == 0 {
+        return Err("--escalate-time cannot scale --time 0, which means no time limit".to_string());
+    }
+    let scaled = (seconds as f64 * factor).ceil() as u64;
+    Ok(std::time::Duration::from_secs(scaled).to_slurm_time())
+}
+
+/// Generic resource names that Slurm ships plugins for. Anything else in the first position of a
+/// GRES request, e.g. `type` or `type:number`, is taken to be a GPU type.
+const GRES_NAMES: [&str; 4] = ["gpu", "mps", "shard", "nic"];
+
+fn is_gres_word(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+fn parse_gres_count(s: &str, spec: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(format!(
+            "{spec} has an invalid count '{s}'; expected a positive number"
+        )),
+    }
+}
+
+/// Parse a GPU request into the sbatch `--gpus` format
+///
+/// # Examples
+///
+/// ```
+/// use ssubmit::parse_gpus;
+///
+/// let s = "A100 : 2";
+/// let actual = parse_gpus(s).unwrap();
+/// let expected = "A100:2";
+/// assert_eq!(actual, expected)
+/// ```
+fn parse_gpus(s: &str) -> Result<String, String> {
+    let fields: Vec<&str> = s.split(':').map(str::trim).collect();
+    match fields.as_slice() {
+        [count] => Ok(parse_gres_count(count, s)?.to_string()),
+        [kind, count] if is_gres_word(kind) => {
+            Ok(format!("{kind}:{}", parse_gres_count(count, s)?))
+        }
+        _ => Err(format!(
+            "{s} is not a valid GPU request; expected [type:]number"
+        )),
+    }
+}
+
+/// Parse a generic resource list into the sbatch `--gres` format
+///
+/// # Examples
+///
+/// ```
+/// use ssubmit::parse_gres;
+///
+/// let s = "a100:2, shard:4";
+/// let actual = parse_gres(s).unwrap();
+/// let expected = "gpu:a100:2,shard:4";
+/// assert_eq!(actual, expected)
+/// ```
+fn parse_gres(s: &str) -> Result<String, String> {
+    let mut normalised = Vec::new();
+    for spec in s.split(',').map(str::trim) {
+        let fields: Vec<&str> = spec.split(':').map(str::trim).collect();
+        let gres = match fields.as_slice() {
+            [count] if !count.is_empty() && count.chars().all(|c| c.is_ascii_digit()) => {
+                format!("gpu:{}", parse_gres_count(count, spec)?)
+            }
+            [name] if GRES_NAMES.contains(name) => name.to_string(),
+            [kind] if is_gres_word(kind) => format!("gpu:{kind}"),
+            [name, count]
+                if GRES_NAMES.contains(name) && count.chars().all(|c| c.is_ascii_digit()) =>
+            {
+                format!("{name}:{}", parse_gres_count(count, spec)?)
+            }
+            [name, kind] if GRES_NAMES.contains(name) && is_gres_word(kind) => {
+                format!("{name}:{kind}")
+            }
+            [kind, count] if is_gres_word(kind) => {
+                format!("gpu:{kind}:{}", parse_gres_count(count, spec)?)
+            }
+            [name, kind, count] if is_gres_word(name) && is_gres_word(kind) => {
+                format!("{name}:{kind}:{}", parse_gres_count(count, spec)?)
+            }
+            _ => {
+                return Err(format!(
+                    "{spec} is not a valid generic resource; expected name[:type][:number]"
+                ))
+            }
+        };
+        normalised.push(gres);
+    }
+    Ok(normalised.join(","))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -698,13 +1760,48 @@ mod tests {
     #[test]
     fn test_validate_and_get_command_interactive_no_command() {
         let cli = Cli {
-            name: "test".to_string(),
+            name: Some("test".to_string()),
             command: None,
             remainder: vec![],
+            configured: vec![],
+            configured_sbatch_args: None,
             output: "%x.out".to_string(),
             error: "%x.err".to_string(),
             memory: "1G".to_string(),
             time: "1d".to_string(),
+            partition: None,
+            account: None,
+            qos: None,
+            cpus_per_task: None,
+            ntasks: None,
+            nodes: None,
+            ntasks_per_node: None,
+            gpus: None,
+            gres: None,
+            array_file: None,
+            sample_sheet: None,
+            array_throttle: None,
+            after: vec![],
+            after_ok: vec![],
+            after_any: vec![],
+            after_notok: vec![],
+            retry: None,
+            escalate_mem: None,
+            escalate_time: None,
+            wait: false,
+            follow: false,
+            container: None,
+            bind: vec![],
+            nv: false,
+            conda_env: None,
+            module: vec![],
+            venv: None,
+            pixi: None,
+            prologue: None,
+            epilogue: None,
+            mkdir: false,
+            no_check_limits: false,
+            auto_partition: false,
             shebang: "#!/usr/bin/env bash".to_string(),
             set: "euxo pipefail".to_string(),
             dry_run: false,
@@ -713,6 +1810,9 @@ mod tests {
             interactive: true,
             shell: "zsh".to_string(),
             export: "ALL".to_string(),
+            profile: None,
+            show_config: false,
+            action: None,
         };
 
         let result = cli.validate_and_get_command().unwrap();
@@ -722,13 +1822,48 @@ mod tests {
     #[test]
     fn test_validate_and_get_command_interactive_with_command() {
         let cli = Cli {
-            name: "test".to_string(),
+            name: Some("test".to_string()),
             command: Some("custom command".to_string()),
             remainder: vec![],
+            configured: vec![],
+            configured_sbatch_args: None,
             output: "%x.out".to_string(),
             error: "%x.err".to_string(),
             memory: "1G".to_string(),
             time: "1d".to_string(),
+            partition: None,
+            account: None,
+            qos: None,
+            cpus_per_task: None,
+            ntasks: None,
+            nodes: None,
+            ntasks_per_node: None,
+            gpus: None,
+            gres: None,
+            array_file: None,
+            sample_sheet: None,
+            array_throttle: None,
+            after: vec![],
+            after_ok: vec![],
+            after_any: vec![],
+            after_notok: vec![],
+            retry: None,
+            escalate_mem: None,
+            escalate_time: None,
+            wait: false,
+            follow: false,
+            container: None,
+            bind: vec![],
+            nv: false,
+            conda_env: None,
+            module: vec![],
+            venv: None,
+            pixi: None,
+            prologue: None,
+            epilogue: None,
+            mkdir: false,
+            no_check_limits: false,
+            auto_partition: false,
             shebang: "#!/usr/bin/env bash".to_string(),
             set: "euxo pipefail".to_string(),
             dry_run: false,
@@ -737,6 +1872,9 @@ mod tests {
             interactive: true,
             shell: "bash".to_string(),
             export: "ALL".to_string(),
+            profile: None,
+            show_config: false,
+            action: None,
         };
 
         let result = cli.validate_and_get_command().unwrap();
@@ -746,13 +1884,48 @@ mod tests {
     #[test]
     fn test_validate_and_get_command_batch_no_command() {
         let cli = Cli {
-            name: "test".to_string(),
+            name: Some("test".to_string()),
             command: None,
             remainder: vec![],
+            configured: vec![],
+            configured_sbatch_args: None,
             output: "%x.out".to_string(),
             error: "%x.err".to_string(),
             memory: "1G".to_string(),
             time: "1d".to_string(),
+            partition: None,
+            account: None,
+            qos: None,
+            cpus_per_task: None,
+            ntasks: None,
+            nodes: None,
+            ntasks_per_node: None,
+            gpus: None,
+            gres: None,
+            array_file: None,
+            sample_sheet: None,
+            array_throttle: None,
+            after: vec![],
+            after_ok: vec![],
+            after_any: vec![],
+            after_notok: vec![],
+            retry: None,
+            escalate_mem: None,
+            escalate_time: None,
+            wait: false,
+            follow: false,
+            container: None,
+            bind: vec![],
+            nv: false,
+            conda_env: None,
+            module: vec![],
+            venv: None,
+            pixi: None,
+            prologue: None,
+            epilogue: None,
+            mkdir: false,
+            no_check_limits: false,
+            auto_partition: false,
             shebang: "#!/usr/bin/env bash".to_string(),
             set: "euxo pipefail".to_string(),
             dry_run: false,
@@ -761,6 +1934,9 @@ mod tests {
             interactive: false,
             shell: "bash".to_string(),
             export: "ALL".to_string(),
+            profile: None,
+            show_config: false,
+            action: None,
         };
 
         let result = cli.validate_and_get_command();
@@ -774,13 +1950,48 @@ mod tests {
     #[test]
     fn test_validate_and_get_command_batch_with_command() {
         let cli = Cli {
-            name: "test".to_string(),
+            name: Some("test".to_string()),
             command: Some("batch command".to_string()),
             remainder: vec![],
+            configured: vec![],
+            configured_sbatch_args: None,
             output: "%x.out".to_string(),
             error: "%x.err".to_string(),
             memory: "1G".to_string(),
             time: "1d".to_string(),
+            partition: None,
+            account: None,
+            qos: None,
+            cpus_per_task: None,
+            ntasks: None,
+            nodes: None,
+            ntasks_per_node: None,
+            gpus: None,
+            gres: None,
+            array_file: None,
+            sample_sheet: None,
+            array_throttle: None,
+            after: vec![],
+            after_ok: vec![],
+            after_any: vec![],
+            after_notok: vec![],
+            retry: None,
+            escalate_mem: None,
+            escalate_time: None,
+            wait: false,
+            follow: false,
+            container: None,
+            bind: vec![],
+            nv: false,
+            conda_env: None,
+            module: vec![],
+            venv: None,
+            pixi: None,
+            prologue: None,
+            epilogue: None,
+            mkdir: false,
+            no_check_limits: false,
+            auto_partition: false,
             shebang: "#!/usr/bin/env bash".to_string(),
             set: "euxo pipefail".to_string(),
             dry_run: false,
@@ -789,6 +2000,9 @@ mod tests {
             interactive: false,
             shell: "bash".to_string(),
             export: "ALL".to_string(),
+            profile: None,
+            show_config: false,
+            action: None,
         };
 
         let result = cli.validate_and_get_command().unwrap();
@@ -818,4 +2032,261 @@ mod tests {
         ]);
         assert_eq!(args.export, "PATH,HOME,USER");
     }
+
+    #[test]
+    fn test_hidden_subcommands_are_the_subcommands() {
+        let actual: Vec<String> = Cli::command()
+            .get_subcommands()
+            .map(|subcommand| format!("\0{}", subcommand.get_name()))
+            .collect();
+        assert_eq!(actual, HIDDEN_SUBCOMMANDS);
+    }
+
+    #[test]
+    fn test_cli_parse_resources() {
+        let args = Cli::parse_from([
+            "ssubmit",
+            "-c",
+            "8",
+            "--ntasks",
+            "2",
+            "-N",
+            "1",
+            "--ntasks-per-node",
+            "2",
+            "name",
+            "command",
+        ]);
+
+        let actual = args.resources();
+        let expected = Resources {
+            partition: None,
+            account: None,
+            qos: None,
+            cpus_per_task: Some(8),
+            ntasks: Some(2),
+            nodes: Some(1),
+            ntasks_per_node: Some(2),
+            gpus: None,
+            gres: None,
+            array: None,
+            dependency: None,
+        };
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_cli_parse_resources_rejects_zero() {
+        let result = Cli::try_parse_from(["ssubmit", "--cpus-per-task", "0", "name", "command"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_cli_parse_array_file() {
+        let args = Cli::parse_from([
+            "ssubmit",
+            "--array-file",
+            "cmds.txt",
+            "--array-throttle",
+            "5",
+            "name",
+        ]);
+
+        assert_eq!(args.array_file, Some(PathBuf::from("cmds.txt")));
+        assert_eq!(args.array_throttle, Some(5));
+        assert_eq!(args.command, None);
+    }
+
+    #[test]
+    fn test_cli_array_file_conflicts_with_command() {
+        let result =
+            Cli::try_parse_from(["ssubmit", "--array-file", "cmds.txt", "name", "command"]);
+        assert!(result.is_err());
+
+        let result = Cli::try_parse_from(["ssubmit", "--array-file", "cmds.txt", "-i", "name"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_cli_sample_sheet_requires_a_command_template() {
+        let result = Cli::try_parse_from(["ssubmit", "--sample-sheet", "samples.tsv", "name"]);
+        assert!(result.is_err());
+
+        let result = Cli::try_parse_from([
+            "ssubmit",
+            "--sample-sheet",
+            "samples.tsv",
+            "--array-file",
+            "cmds.txt",
+            "name",
+            "echo {sample}",
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_cli_array_throttle_requires_array_file() {
+        let args = Cli::parse_from(["ssubmit", "--array-throttle", "5", "name", "command"]);
+        assert!(args.array_tasks().is_err());
+    }
+
+    #[test]
+    fn test_parse_factor() {
+        assert_eq!(parse_factor("2x"), Ok(2.0));
+        assert_eq!(parse_factor("1.5"), Ok(1.5));
+        assert!(parse_factor("0.5x").is_err());
+        assert!(parse_factor("double").is_err());
+    }
+
+    #[test]
+    fn test_scale_memory() {
+        assert_eq!(scale_memory("1000M", 2.0), Ok("2000M".to_string()));
+        assert_eq!(scale_memory("500K", 1.5), Ok("750K".to_string()));
+        assert_eq!(scale_memory("800K", 2.0), Ok("2M".to_string()));
+        assert!(scale_memory("0", 2.0).is_err());
+    }
+
+    #[test]
+    fn test_scale_time() {
+        assert_eq!(scale_time("1:0:0", 1.5), Ok("1:30:0".to_string()));
+        assert_eq!(scale_time("30", 2.0), Ok("1:0:0".to_string()));
+        assert_eq!(scale_time("1-0", 2.0), Ok("48:0:0".to_string()));
+        assert!(scale_time("0", 2.0).is_err());
+    }
+
+    #[test]
+    fn test_retry_resources_compound() {
+        let args = Cli::parse_from([
+            "ssubmit",
+            "--mem",
+            "1G",
+            "--time",
+            "1h",
+            "--retry",
+            "2",
+            "--escalate-mem",
+            "2x",
+            "name",
+            "command",
+        ]);
+        let job = ssubmit::make_submission_plan(
+            &args.shebang,
+            &args.set,
+            args.job_name(),
+            &args.memory,
+            &args.time,
+            &args.error,
+            &args.output,
+            &args.resources(),
+            &Environment::default(),
+            "command",
+            &args.remainder,
+            &args.export,
+            false,
+        )
+        .job;
+
+        let actual = args.retry_resources(&job).unwrap();
+        let expected = vec![
+            ("2000M".to_string(), "1:0:0".to_string()),
+            ("4000M".to_string(), "1:0:0".to_string()),
+        ];
+        assert_eq!(actual, expected);
+
+        let args = Cli::parse_from(["ssubmit", "--escalate-time", "2x", "name", "command"]);
+        assert!(args.retry_resources(&job).is_err());
+    }
+
+    #[test]
+    fn test_parse_gpus_count() {
+        let actual = parse_gpus("2").unwrap();
+        let expected = "2";
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_parse_gpus_type_and_count() {
+        let actual = parse_gpus("a100:2").unwrap();
+        let expected = "a100:2";
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_parse_gpus_zero() {
+        let actual = parse_gpus("0").unwrap_err();
+        assert!(actual.contains("invalid count '0'"));
+    }
+
+    #[test]
+    fn test_parse_gpus_too_many_fields() {
+        let actual = parse_gpus("gpu:a100:2").unwrap_err();
+        assert!(actual.starts_with("gpu:a100:2 is not a valid GPU request"));
+    }
+
+    #[test]
+    fn test_parse_gres_count_is_gpu() {
+        let actual = parse_gres("2").unwrap();
+        let expected = "gpu:2";
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_parse_gres_type_and_count_is_gpu() {
+        let actual = parse_gres("a100:2").unwrap();
+        let expected = "gpu:a100:2";
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_parse_gres_named_with_count() {
+        let actual = parse_gres("shard:4").unwrap();
+        let expected = "shard:4";
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_parse_gres_name_only() {
+        let actual = parse_gres("gpu").unwrap();
+        let expected = "gpu";
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_parse_gres_name_and_type_without_count() {
+        let actual = parse_gres("gpu:a100").unwrap();
+        let expected = "gpu:a100";
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_parse_gres_type_only_is_gpu() {
+        let actual = parse_gres("a100").unwrap();
+        let expected = "gpu:a100";
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_parse_gres_zero_count() {
+        let actual = parse_gres("shard:0").unwrap_err();
+        assert!(actual.contains("invalid count '0'"));
+    }
+
+    #[test]
+    fn test_parse_gres_list_with_spaces() {
+        let actual = parse_gres("gpu:a100:2, shard:4").unwrap();
+        let expected = "gpu:a100:2,shard:4";
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_parse_gres_invalid_count() {
+        let actual = parse_gres("gpu:a100:two").unwrap_err();
+        assert!(actual.contains("invalid count 'two'"));
+    }
+
+    #[test]
+    fn test_parse_gres_empty_item() {
+        let actual = parse_gres("gpu:1,").unwrap_err();
+        assert!(actual.contains("is not a valid generic resource"));
+    }
 }
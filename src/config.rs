@@ -0,0 +1,319 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+/// Name of the project-local configuration file, discovered by walking up from the working
+/// directory
+pub const PROJECT_CONFIG_FILE: &str = ".ssubmit.toml";
+
+/// Defaults that can be set in a configuration file. Keys are spelled like the command line
+/// options they provide a default for.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub output: Option<String>,
+    pub error: Option<String>,
+    pub mem: Option<String>,
+    pub time: Option<String>,
+    pub partition: Option<String>,
+    pub account: Option<String>,
+    pub qos: Option<String>,
+    pub cpus_per_task: Option<u32>,
+    pub ntasks: Option<u32>,
+    pub nodes: Option<u32>,
+    pub ntasks_per_node: Option<u32>,
+    pub gpus: Option<String>,
+    pub gres: Option<String>,
+    pub container: Option<String>,
+    pub bind: Option<Vec<String>>,
+    pub nv: Option<bool>,
+    pub module: Option<Vec<String>>,
+    pub conda_env: Option<String>,
+    pub venv: Option<String>,
+    pub pixi: Option<String>,
+    /// Commands run before the command, inline or as the path of a file containing them
+    pub prologue: Option<String>,
+    /// Commands run when the script exits, inline or as the path of a file containing them
+    pub epilogue: Option<String>,
+    pub shebang: Option<String>,
+    pub set: Option<String>,
+    pub shell: Option<String>,
+    pub export: Option<String>,
+    /// Extra options passed to sbatch (or salloc) before any given after `--`
+    pub sbatch_args: Option<Vec<String>>,
+    /// Named bundles of settings, selected with --profile
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Config>,
+}
+
+/// Where a setting's value came from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    CommandLine,
+    Environment(String),
+    Pipeline(PathBuf),
+    ProjectConfig(PathBuf),
+    UserConfig(PathBuf),
+    Profile(String, Box<Source>),
+    Default,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::CommandLine => write!(f, "command line"),
+            Source::Environment(variable) => write!(f, "environment ({variable})"),
+            Source::Pipeline(path) => write!(f, "pipeline defaults ({})", path.display()),
+            Source::ProjectConfig(path) => write!(f, "project config ({})", path.display()),
+            Source::UserConfig(path) => write!(f, "user config ({})", path.display()),
+            Source::Profile(name, source) => write!(f, "profile {name} in {source}"),
+            Source::Default => write!(f, "built-in default"),
+        }
+    }
+}
+
+/// A resolved setting, as shown by --show-config
+#[derive(Debug, Clone, PartialEq)]
+pub struct Setting {
+    pub key: &'static str,
+    pub value: Option<toml::Value>,
+    pub source: Source,
+}
+
+/// Render the settings as TOML, annotated with where each value came from
+pub fn render(settings: &[Setting]) -> String {
+    let mut output = String::from(
+        "# Precedence: command line > profile > environment > pipeline defaults > project config > user config > built-in default\n",
+    );
+    for setting in settings {
+        match &setting.value {
+            Some(value) => {
+                let _ = writeln!(output, "{} = {value} # {}", setting.key, setting.source);
+            }
+            None => {
+                let _ = writeln!(output, "# {} is not set", setting.key);
+            }
+        }
+    }
+    output
+}
+
+/// A configuration file that was found and parsed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layer {
+    pub source: Source,
+    pub config: Config,
+}
+
+impl Config {
+    fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        let config: Config = toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))?;
+        if let Some((name, _)) = config
+            .profiles
+            .iter()
+            .find(|(_, profile)| !profile.profiles.is_empty())
+        {
+            bail!(
+                "Profile {name} in config file {} cannot define its own profiles",
+                path.display()
+            );
+        }
+        Ok(config)
+    }
+}
+
+/// Find a named profile, taking it from the highest precedence configuration file that defines it
+pub fn find_profile(layers: &[Layer], name: &str) -> Result<Layer, String> {
+    layers
+        .iter()
+        .find_map(|layer| {
+            layer.config.profiles.get(name).map(|profile| Layer {
+                source: Source::Profile(name.to_string(), Box::new(layer.source.clone())),
+                config: profile.clone(),
+            })
+        })
+        .ok_or_else(|| {
+            let mut known: Vec<&str> = layers
+                .iter()
+                .flat_map(|layer| layer.config.profiles.keys().map(String::as_str))
+                .collect();
+            known.sort_unstable();
+            known.dedup();
+            if known.is_empty() {
+                format!("Unknown profile {name}; no profiles are defined in the config files")
+            } else {
+                format!(
+                    "Unknown profile {name}; defined profiles are {}",
+                    known.join(", ")
+                )
+            }
+        })
+}
+
+/// The user configuration file: `$XDG_CONFIG_HOME/ssubmit/config.toml`, falling back to
+/// `~/.config/ssubmit/config.toml`
+pub fn user_config_path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(|home| PathBuf::from(home).join(".config"))
+        })?;
+    Some(config_home.join("ssubmit").join("config.toml"))
+}
+
+/// The closest project configuration file in `start` or one of its parents
+pub fn find_project_config(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_FILE))
+        .find(|path| path.is_file())
+}
+
+/// Load the configuration files that exist, highest precedence (project) first
+pub fn load_layers() -> Result<Vec<Layer>> {
+    let mut layers = Vec::new();
+
+    let project = std::env::current_dir()
+        .ok()
+        .and_then(|dir| find_project_config(&dir));
+    if let Some(path) = project {
+        layers.push(Layer {
+            config: Config::load(&path)?,
+            source: Source::ProjectConfig(path),
+        });
+    }
+
+    if let Some(path) = user_config_path().filter(|path| path.is_file()) {
+        layers.push(Layer {
+            config: Config::load(&path)?,
+            source: Source::UserConfig(path),
+        });
+    }
+
+    Ok(layers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_parses_kebab_case_keys() {
+        let config: Config = toml::from_str(
+            r#"
+mem = "4G"
+cpus-per-task = 4
+sbatch-args = ["--mail-type=END"]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.mem.as_deref(), Some("4G"));
+        assert_eq!(config.cpus_per_task, Some(4));
+        assert_eq!(
+            config.sbatch_args,
+            Some(vec!["--mail-type=END".to_string()])
+        );
+        assert_eq!(config.time, None);
+    }
+
+    #[test]
+    fn test_config_rejects_unknown_keys() {
+        let result: Result<Config, _> = toml::from_str("memory = \"4G\"");
+        assert!(result.unwrap_err().to_string().contains("unknown field"));
+    }
+
+    #[test]
+    fn test_find_profile_prefers_highest_precedence_layer() {
+        let parse = |contents: &str| -> Config { toml::from_str(contents).unwrap() };
+        let layers = vec![
+            Layer {
+                source: Source::ProjectConfig(PathBuf::from(".ssubmit.toml")),
+                config: parse("[profiles.gpu]\ngres = \"a100:1\"\n"),
+            },
+            Layer {
+                source: Source::UserConfig(PathBuf::from("config.toml")),
+                config: parse("[profiles.gpu]\ngres = \"2\"\n[profiles.short]\ntime = \"1h\"\n"),
+            },
+        ];
+
+        let gpu = find_profile(&layers, "gpu").unwrap();
+        assert_eq!(gpu.config.gres.as_deref(), Some("a100:1"));
+        assert_eq!(
+            gpu.source.to_string(),
+            "profile gpu in project config (.ssubmit.toml)"
+        );
+
+        let short = find_profile(&layers, "short").unwrap();
+        assert_eq!(short.config.time.as_deref(), Some("1h"));
+
+        let error = find_profile(&layers, "long").unwrap_err();
+        assert_eq!(
+            error,
+            "Unknown profile long; defined profiles are gpu, short"
+        );
+    }
+
+    #[test]
+    fn test_render_settings() {
+        let settings = vec![
+            Setting {
+                key: "mem",
+                value: Some(toml::Value::String("4000M".to_string())),
+                source: Source::UserConfig(PathBuf::from("/home/me/config.toml")),
+            },
+            Setting {
+                key: "cpus-per-task",
+                value: Some(toml::Value::Integer(8)),
+                source: Source::CommandLine,
+            },
+            Setting {
+                key: "partition",
+                value: Some(toml::Value::String("gpu".to_string())),
+                source: Source::Profile(
+                    "gpu-large".to_string(),
+                    Box::new(Source::ProjectConfig(PathBuf::from(".ssubmit.toml"))),
+                ),
+            },
+            Setting {
+                key: "qos",
+                value: None,
+                source: Source::Default,
+            },
+        ];
+
+        let actual = render(&settings);
+        let expected = r#"# Precedence: command line > profile > environment > pipeline defaults > project config > user config > built-in default
+mem = "4000M" # user config (/home/me/config.toml)
+cpus-per-task = 8 # command line
+partition = "gpu" # profile gpu-large in project config (.ssubmit.toml)
+# qos is not set
+"#;
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_find_project_config_walks_up() {
+        let root = std::env::temp_dir().join(format!("ssubmit-config-{}", std::process::id()));
+        let nested = root.join("a").join("b");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(root.join(PROJECT_CONFIG_FILE), "").unwrap();
+
+        let actual = find_project_config(&nested);
+        let expected = Some(root.join(PROJECT_CONFIG_FILE));
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(actual, expected);
+    }
+}
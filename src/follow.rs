@@ -0,0 +1,221 @@
+use std::fs::File;
+use std::io::{Read as _, Seek as _, SeekFrom, Write as _};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::warn;
+use ssubmit::{current_user, job_path, log_paths, JobMetadata, JobSpec, SubmissionPlan};
+
+/// How often the log files are checked for new output
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Streams the output and error files of a job to stdout, each line prefixed with the file it
+/// came from, until it is finished
+pub struct Follower {
+    sender: Sender<String>,
+    handle: JoinHandle<()>,
+}
+
+impl Follower {
+    /// Start following the log files of the job planned in `plan`, submitted as `job_id`
+    pub fn start(plan: &SubmissionPlan, job_id: &str) -> Self {
+        let job = plan.job.clone();
+        let arguments = plan.slurm.arguments.clone();
+        let user = current_user();
+        let mut logs = log_files(&job, &arguments, job_id, user.as_deref());
+        let (sender, receiver) = mpsc::channel::<String>();
+        let handle = thread::spawn(move || loop {
+            for log in &mut logs {
+                log.print_new(false);
+            }
+            match receiver.recv_timeout(POLL_INTERVAL) {
+                Ok(job_id) => {
+                    for log in &mut logs {
+                        log.print_new(true);
+                    }
+                    logs = log_files(&job, &arguments, &job_id, user.as_deref());
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    for log in &mut logs {
+                        log.print_new(true);
+                    }
+                    return;
+                }
+            }
+        });
+        Self { sender, handle }
+    }
+
+    /// Follow the log files of another submission of the job, e.g. a retry, instead
+    pub fn switch(&self, job_id: &str) {
+        let _ = self.sender.send(job_id.to_string());
+    }
+
+    /// Print what is left in the log files and stop following them
+    pub fn finish(self) {
+        drop(self.sender);
+        let _ = self.handle.join();
+    }
+}
+
+/// A log file being followed and how much of it has been printed
+struct LogFile {
+    path: PathBuf,
+    label: String,
+    offset: u64,
+    partial: String,
+}
+
+impl LogFile {
+    /// Print the complete lines written since the last call, and with `flush`, an incomplete last
+    /// line too. A file that does not exist yet has nothing to print.
+    fn print_new(&mut self, flush: bool) {
+        if let Ok(mut file) = File::open(&self.path) {
+            let length = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+            if length < self.offset {
+                // the file was truncated, e.g. by a retry writing to the same file
+                self.offset = 0;
+                self.partial.clear();
+            }
+            let mut bytes = Vec::new();
+            if file.seek(SeekFrom::Start(self.offset)).is_ok() {
+                if let Ok(read) = file.read_to_end(&mut bytes) {
+                    self.offset += read as u64;
+                }
+            }
+            self.partial.push_str(&String::from_utf8_lossy(&bytes));
+        }
+
+        let mut stdout = std::io::stdout().lock();
+        while let Some(end) = self.partial.find('\n') {
+            let line: String = self.partial.drain(..=end).collect();
+            let _ = write!(stdout, "[{}] {line}", self.label);
+        }
+        if flush && !self.partial.is_empty() {
+            let _ = writeln!(stdout, "[{}] {}", self.label, self.partial);
+            self.partial.clear();
+        }
+        let _ = stdout.flush();
+    }
+}
+
+/// The output and error files of a job submitted with `arguments`, one of each per array task.
+/// Relative paths are from the job's `--chdir`, if any. A file that is also the output file is
+/// only followed once.
+fn log_files(
+    job: &JobSpec,
+    arguments: &[String],
+    job_id: &str,
+    user: Option<&str>,
+) -> Vec<LogFile> {
+    let tasks: Vec<Option<usize>> = if job.tasks.is_empty() {
+        vec![None]
+    } else {
+        (1..=job.tasks.len()).map(Some).collect()
+    };
+
+    let mut logs: Vec<LogFile> = Vec::new();
+    for task in tasks {
+        let metadata = JobMetadata {
+            job_id: Some(job_id.to_string()),
+            array_task_id: task,
+            user: user.map(str::to_string),
+            ..JobMetadata::default()
+        };
+        let paths = log_paths(job, &metadata);
+        for (kind, path) in [("out", paths.output), ("err", paths.error)] {
+            if !path.unresolved.is_empty() {
+                warn!(
+                    "Cannot follow {}, as {} is only known once the job runs",
+                    path.path,
+                    path.unresolved.join(" and ")
+                );
+                continue;
+            }
+            let path = job_path(&path.path, arguments);
+            if logs.iter().any(|log| log.path == path) {
+                continue;
+            }
+            let label = match task {
+                Some(task) => format!("{kind} {task}"),
+                None => kind.to_string(),
+            };
+            logs.push(LogFile {
+                path,
+                label,
+                offset: 0,
+                partial: String::new(),
+            });
+        }
+    }
+    logs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ssubmit::{Environment, Resources};
+
+    #[test]
+    fn test_log_files_of_array_tasks() {
+        let job = JobSpec {
+            name: "align".to_string(),
+            command: "bwa mem".to_string(),
+            memory: "1G".to_string(),
+            time: "1:0:0".to_string(),
+            output: "%x_%A_%a.log".to_string(),
+            error: "%x_%A_%a.log".to_string(),
+            export: "ALL".to_string(),
+            shebang: "#!/usr/bin/env bash".to_string(),
+            set: "euxo pipefail".to_string(),
+            resources: Resources::default(),
+            environment: Environment::default(),
+            profile: None,
+            tasks: vec!["bwa mem a".to_string(), "bwa mem b".to_string()],
+            dependencies: vec![],
+            resubmit_of: None,
+            partition_reason: None,
+        };
+
+        let logs = log_files(&job, &[], "123", None);
+        let actual: Vec<(String, PathBuf)> =
+            logs.into_iter().map(|log| (log.label, log.path)).collect();
+
+        assert_eq!(
+            actual,
+            vec![
+                ("out 1".to_string(), PathBuf::from("align_123_1.log")),
+                ("out 2".to_string(), PathBuf::from("align_123_2.log")),
+            ]
+        );
+
+        let job = JobSpec {
+            output: "%x_%j.out".to_string(),
+            error: "%u.err".to_string(),
+            ..job
+        };
+        assert!(log_files(&job, &[], "123", None).is_empty());
+
+        let job = JobSpec {
+            output: "logs/%x_%j.out".to_string(),
+            error: "/tmp/%x_%j.err".to_string(),
+            tasks: vec![],
+            ..job
+        };
+        let arguments = vec!["--chdir=/scratch/run".to_string()];
+        let actual: Vec<PathBuf> = log_files(&job, &arguments, "123", None)
+            .into_iter()
+            .map(|log| log.path)
+            .collect();
+        assert_eq!(
+            actual,
+            vec![
+                PathBuf::from("/scratch/run/logs/align_123.out"),
+                PathBuf::from("/tmp/align_123.err"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,291 @@
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use ssubmit::{SubmissionPlan, SubmissionResult};
+
+/// Name of the history file in the ssubmit state directory
+pub const HISTORY_FILE: &str = "history.jsonl";
+
+/// A job submitted by ssubmit, as recorded in the history
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub submitted_at: DateTime<Utc>,
+    /// The working directory the job was submitted from
+    pub directory: PathBuf,
+    pub plan: SubmissionPlan,
+    pub submission: SubmissionResult,
+}
+
+/// Which records to list
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Filter {
+    pub name: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl Filter {
+    pub fn matches(&self, record: &Record) -> bool {
+        self.name
+            .as_ref()
+            .map_or(true, |name| &record.plan.job.name == name)
+            && self
+                .since
+                .map_or(true, |since| record.submitted_at >= since)
+            && self.until.map_or(true, |until| record.submitted_at < until)
+    }
+}
+
+/// The history file: `$XDG_STATE_HOME/ssubmit/history.jsonl`, falling back to
+/// `~/.local/state/ssubmit/history.jsonl`
+pub fn history_path() -> Option<PathBuf> {
+    let state_home = env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var_os("HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(|home| PathBuf::from(home).join(".local").join("state"))
+        })?;
+    Some(state_home.join("ssubmit").join(HISTORY_FILE))
+}
+
+/// Record a successful submission. The job has already been submitted, so a history that cannot
+/// be written is only a warning.
+pub fn record(plan: &SubmissionPlan, submission: &SubmissionResult) {
+    let path = match history_path() {
+        Some(path) => path,
+        None => return,
+    };
+    let record = Record {
+        submitted_at: Utc::now(),
+        directory: env::current_dir().unwrap_or_default(),
+        plan: plan.clone(),
+        submission: submission.clone(),
+    };
+    if let Err(error) = append(&path, &record) {
+        warn!(
+            "Failed to record job {} in the history: {error:#}",
+            submission.job_id
+        );
+    }
+}
+
+/// Append a record to the history file, creating the file and its directory if needed
+pub fn append(path: &Path, record: &Record) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+    }
+    let mut line = serde_json::to_string(record).context("Failed to render history record")?;
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .with_context(|| format!("Failed to write history file {}", path.display()))
+}
+
+/// Every record in the history file, oldest first. A missing file is an empty history, and lines
+/// that cannot be read, e.g. from an interrupted write, are skipped with a warning.
+pub fn load(path: &Path) -> Result<Vec<Record>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(error) => {
+            return Err(error)
+                .with_context(|| format!("Failed to read history file {}", path.display()))
+        }
+    };
+
+    let mut records = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(record) => records.push(record),
+            Err(error) => warn!(
+                "Skipping line {} of history file {}: {error}",
+                index + 1,
+                path.display()
+            ),
+        }
+    }
+    Ok(records)
+}
+
+/// The most recent record for a job given by ID or name
+pub fn find<'a>(records: &'a [Record], job: &str) -> Option<&'a Record> {
+    records
+        .iter()
+        .rev()
+        .find(|record| record.submission.job_id == job)
+        .or_else(|| {
+            records
+                .iter()
+                .rev()
+                .find(|record| record.plan.job.name == job)
+        })
+}
+
+/// Parse a local date (`2024-05-01`) or date and time (`2024-05-01 13:30`). A date is the start
+/// of that day or, if `end_of_day`, the start of the next.
+pub fn parse_date(value: &str, end_of_day: bool) -> Result<DateTime<Utc>, String> {
+    let value = value.trim();
+    let naive = if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        let start = date.and_hms_opt(0, 0, 0).expect("valid midnight");
+        if end_of_day {
+            start + Duration::days(1)
+        } else {
+            start
+        }
+    } else {
+        [
+            "%Y-%m-%d %H:%M",
+            "%Y-%m-%dT%H:%M",
+            "%Y-%m-%d %H:%M:%S",
+            "%Y-%m-%dT%H:%M:%S",
+        ]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .ok_or_else(|| format!("Invalid date {value}; expected YYYY-MM-DD or YYYY-MM-DD HH:MM"))?
+    };
+    local_to_utc(naive).ok_or_else(|| format!("{value} does not exist in the local time zone"))
+}
+
+fn local_to_utc(naive: NaiveDateTime) -> Option<DateTime<Utc>> {
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|local| local.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ssubmit::{Environment, JobSpec, Resources, SlurmPlan};
+
+    fn make_record(name: &str, job_id: &str, submitted_at: &str) -> Record {
+        Record {
+            submitted_at: DateTime::parse_from_rfc3339(submitted_at)
+                .unwrap()
+                .with_timezone(&Utc),
+            directory: PathBuf::from("/work"),
+            plan: SubmissionPlan {
+                job: JobSpec {
+                    name: name.to_string(),
+                    command: "echo hi".to_string(),
+                    memory: "1G".to_string(),
+                    time: "1:0:0".to_string(),
+                    output: "%x.out".to_string(),
+                    error: "%x.err".to_string(),
+                    export: "ALL".to_string(),
+                    shebang: "#!/usr/bin/env bash".to_string(),
+                    set: "euxo pipefail".to_string(),
+                    resources: Resources::default(),
+                    environment: Environment::default(),
+                    profile: None,
+                    tasks: vec![],
+                    dependencies: vec![],
+                    resubmit_of: None,
+                    partition_reason: None,
+                },
+                slurm: SlurmPlan {
+                    executable: "sbatch".to_string(),
+                    arguments: vec!["--export=ALL".to_string()],
+                    script: "#!/usr/bin/env bash\necho hi\n".to_string(),
+                },
+            },
+            submission: SubmissionResult {
+                job_id: job_id.to_string(),
+                cluster: None,
+            },
+        }
+    }
+
+    #[test]
+    fn test_append_and_load_round_trip() {
+        let dir = env::temp_dir().join(format!("ssubmit-history-{}", std::process::id()));
+        let path = dir.join("state").join(HISTORY_FILE);
+        let first = make_record("align", "100", "2024-05-01T10:00:00Z");
+        let second = make_record("merge", "101", "2024-05-02T10:00:00Z");
+
+        assert!(load(&path).unwrap().is_empty());
+        append(&path, &first).unwrap();
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"truncated\n")
+            .unwrap();
+        append(&path, &second).unwrap();
+
+        let actual = load(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(actual, vec![first, second]);
+    }
+
+    #[test]
+    fn test_filter_by_name_and_date() {
+        let record = make_record("align", "100", "2024-05-01T10:00:00Z");
+        let at = |value: &str| {
+            DateTime::parse_from_rfc3339(value)
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+
+        assert!(Filter::default().matches(&record));
+        assert!(!Filter {
+            name: Some("merge".to_string()),
+            ..Filter::default()
+        }
+        .matches(&record));
+        assert!(Filter {
+            since: Some(at("2024-05-01T10:00:00Z")),
+            until: Some(at("2024-05-01T10:00:01Z")),
+            ..Filter::default()
+        }
+        .matches(&record));
+        assert!(!Filter {
+            until: Some(at("2024-05-01T10:00:00Z")),
+            ..Filter::default()
+        }
+        .matches(&record));
+    }
+
+    #[test]
+    fn test_find_prefers_job_id_then_latest_name() {
+        let records = vec![
+            make_record("align", "100", "2024-05-01T10:00:00Z"),
+            make_record("align", "102", "2024-05-02T10:00:00Z"),
+            make_record("102", "103", "2024-05-03T10:00:00Z"),
+        ];
+
+        assert_eq!(find(&records, "100").unwrap().submission.job_id, "100");
+        assert_eq!(find(&records, "align").unwrap().submission.job_id, "102");
+        assert_eq!(find(&records, "102").unwrap().submission.job_id, "102");
+        assert!(find(&records, "merge").is_none());
+    }
+
+    #[test]
+    fn test_parse_date() {
+        let start = parse_date("2024-05-01", false).unwrap();
+        let end = parse_date("2024-05-01", true).unwrap();
+        assert_eq!(end - start, Duration::days(1));
+
+        let time = parse_date("2024-05-01 13:30", false).unwrap();
+        assert_eq!(time - start, Duration::minutes(13 * 60 + 30));
+        assert_eq!(parse_date("2024-05-01T13:30", true).unwrap(), time);
+
+        assert!(parse_date("yesterday", false).is_err());
+    }
+}
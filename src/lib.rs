@@ -1,15 +1,17 @@
 use std::cmp::max;
 use std::fmt::Write as _;
 use std::io::Write as _;
+use std::path::{Path, PathBuf};
 use std::process::{Command, ExitStatus, Stdio};
 use std::time::Duration; // import without risk of name clashing
 
 use log::warn;
-use serde::Serialize;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 
 pub const JSON_SCHEMA_VERSION: u8 = 1;
 
-#[derive(Debug, Clone, PartialEq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct JobSpec {
     pub name: String,
     pub command: String,
@@ -18,27 +20,597 @@ pub struct JobSpec {
     pub output: String,
     pub error: String,
     pub export: String,
+    /// The interpreter line of the submission script
+    pub shebang: String,
+    /// The shell options set at the top of the submission script, e.g. `euxo pipefail`
+    pub set: String,
+    #[serde(flatten)]
+    pub resources: Resources,
+    /// What the command runs in, e.g. a container
+    #[serde(flatten)]
+    pub environment: Environment,
+    /// The named configuration profile that was applied, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// The command run by each task of a job array, in task ID order starting at 1
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tasks: Vec<String>,
+    /// The jobs this job depends on, as given and as resolved to job IDs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<Dependency>,
+    /// The ID of the job this one is a resubmission of
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resubmit_of: Option<String>,
+    /// Why the partition was chosen, when it was chosen with --auto-partition
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partition_reason: Option<String>,
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize)]
+/// First-class Slurm resource requests and where they are scheduled and charged. Unset values are
+/// left to the cluster defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Resources {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partition: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qos: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpus_per_task: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ntasks: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nodes: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ntasks_per_node: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gpus: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gres: Option<String>,
+    /// Job array index specification, e.g. `1-10%2`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub array: Option<String>,
+    /// Job dependency specification, e.g. `afterok:123:124`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dependency: Option<String>,
+}
+
+/// A resource option that can be set either first-class or in the passthrough arguments
+struct ResourceOption {
+    long: &'static str,
+    short: Option<char>,
+}
+
+const PARTITION: ResourceOption = ResourceOption {
+    long: "--partition",
+    short: Some('p'),
+};
+const ACCOUNT: ResourceOption = ResourceOption {
+    long: "--account",
+    short: Some('A'),
+};
+const QOS: ResourceOption = ResourceOption {
+    long: "--qos",
+    short: Some('q'),
+};
+const CPUS_PER_TASK: ResourceOption = ResourceOption {
+    long: "--cpus-per-task",
+    short: Some('c'),
+};
+const NTASKS: ResourceOption = ResourceOption {
+    long: "--ntasks",
+    short: Some('n'),
+};
+const NODES: ResourceOption = ResourceOption {
+    long: "--nodes",
+    short: Some('N'),
+};
+const NTASKS_PER_NODE: ResourceOption = ResourceOption {
+    long: "--ntasks-per-node",
+    short: None,
+};
+const GPUS: ResourceOption = ResourceOption {
+    long: "--gpus",
+    short: Some('G'),
+};
+const GRES: ResourceOption = ResourceOption {
+    long: "--gres",
+    short: None,
+};
+const ARRAY: ResourceOption = ResourceOption {
+    long: "--array",
+    short: Some('a'),
+};
+const DEPENDENCY: ResourceOption = ResourceOption {
+    long: "--dependency",
+    short: Some('d'),
+};
+const CLUSTERS: ResourceOption = ResourceOption {
+    long: "--clusters",
+    short: Some('M'),
+};
+const CHDIR: ResourceOption = ResourceOption {
+    long: "--chdir",
+    short: Some('D'),
+};
+
+impl ResourceOption {
+    /// The value given to the option in the passthrough arguments, if any
+    fn value_in(&self, arguments: &[String]) -> Option<String> {
+        let mut arguments = arguments.iter();
+        while let Some(argument) = arguments.next() {
+            if let Some(rest) = argument.strip_prefix(self.long) {
+                if rest.is_empty() {
+                    return arguments.next().cloned();
+                }
+                if let Some(value) = rest.strip_prefix('=') {
+                    return Some(value.to_string());
+                }
+                continue;
+            }
+            let short_value = match (self.short, argument.strip_prefix('-')) {
+                (Some(short), Some(rest)) => rest.strip_prefix(short),
+                _ => None,
+            };
+            match short_value {
+                Some("") => return arguments.next().cloned(),
+                Some(value) => return Some(value.trim_start_matches('=').to_string()),
+                None => {}
+            }
+        }
+        None
+    }
+
+    fn is_set_in(&self, arguments: &[String]) -> bool {
+        arguments.iter().any(|argument| {
+            if let Some(rest) = argument.strip_prefix(self.long) {
+                return rest.is_empty() || rest.starts_with('=');
+            }
+            match (self.short, argument.strip_prefix('-')) {
+                (Some(short), Some(rest)) => rest.starts_with(short),
+                _ => false,
+            }
+        })
+    }
+}
+
+impl Resources {
+    fn requested(&self) -> Vec<(ResourceOption, String)> {
+        let count = |value: Option<u32>| value.map(|value| value.to_string());
+        [
+            (PARTITION, self.partition.clone()),
+            (ACCOUNT, self.account.clone()),
+            (QOS, self.qos.clone()),
+            (CPUS_PER_TASK, count(self.cpus_per_task)),
+            (NTASKS, count(self.ntasks)),
+            (NODES, count(self.nodes)),
+            (NTASKS_PER_NODE, count(self.ntasks_per_node)),
+            (GPUS, self.gpus.clone()),
+            (GRES, self.gres.clone()),
+            (ARRAY, self.array.clone()),
+            (DEPENDENCY, self.dependency.clone()),
+        ]
+        .into_iter()
+        .filter_map(|(option, value)| value.map(|value| (option, value)))
+        .collect()
+    }
+
+    /// The requested resources as `--option=value` arguments for sbatch or salloc
+    pub fn slurm_arguments(&self) -> Vec<String> {
+        self.requested()
+            .into_iter()
+            .map(|(option, value)| format!("{}={value}", option.long))
+            .collect()
+    }
+
+    /// Leave out the resources in `keys`, named by their option without the dashes, e.g.
+    /// `partition`, that are also set in the passthrough `arguments`, as those take precedence
+    pub fn defer_to_passthrough(&mut self, keys: &[&str], arguments: &[String]) {
+        let overridden = |option: &ResourceOption| {
+            keys.contains(&option.long.trim_start_matches('-')) && option.is_set_in(arguments)
+        };
+        if overridden(&PARTITION) {
+            self.partition = None;
+        }
+        if overridden(&ACCOUNT) {
+            self.account = None;
+        }
+        if overridden(&QOS) {
+            self.qos = None;
+        }
+        if overridden(&CPUS_PER_TASK) {
+            self.cpus_per_task = None;
+        }
+        if overridden(&NTASKS) {
+            self.ntasks = None;
+        }
+        if overridden(&NODES) {
+            self.nodes = None;
+        }
+        if overridden(&NTASKS_PER_NODE) {
+            self.ntasks_per_node = None;
+        }
+        if overridden(&GPUS) {
+            self.gpus = None;
+        }
+        if overridden(&GRES) {
+            self.gres = None;
+        }
+    }
+
+    /// The first requested resource that the passthrough `arguments` set too, as its long option
+    pub fn conflict_in(&self, arguments: &[String]) -> Option<&'static str> {
+        self.requested()
+            .into_iter()
+            .map(|(option, _)| option)
+            .find(|option| option.is_set_in(arguments))
+            .map(|option| option.long)
+    }
+
+    /// Reject resources that are requested both first-class and in the passthrough arguments
+    pub fn check_conflicts(&self, remainder: &[String]) -> Result<(), SubmissionError> {
+        match self.conflict_in(remainder) {
+            Some(option) => Err(SubmissionError::validation(format!(
+                "{option} was given both as an ssubmit option and after `--`; remove one of them"
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The software environment a job's command runs in, and the commands run before and after it.
+/// By default, the command runs in the environment the job was submitted from.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Environment {
+    /// The container image the command runs in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container: Option<Container>,
+    /// The name or path of the conda environment activated before the command is run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conda_env: Option<String>,
+    /// The environment modules loaded before the command is run, e.g. `samtools/1.17`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub modules: Vec<String>,
+    /// The Python virtual environment activated before the command is run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub venv: Option<String>,
+    /// The manifest of the pixi project whose environment is activated before the command is run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pixi: Option<String>,
+    /// Commands run just before the command, once the environment is set up
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prologue: Option<String>,
+    /// Commands run when the script exits, however it exits
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub epilogue: Option<String>,
+}
+
+/// A container image that a command is run in with Apptainer, or Singularity if Apptainer is
+/// not installed on the node
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Container {
+    /// An image file, or a URI such as `docker://ubuntu:24.04`
+    pub image: String,
+    /// Paths bound into the container as well as the working directory and log directories,
+    /// as `src[:dest[:opts]]`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub binds: Vec<String>,
+    /// Whether NVIDIA GPUs are made available in the container
+    #[serde(default)]
+    pub nv: bool,
+}
+
+impl Environment {
+    /// The script body that runs `command` in this environment, for a script with the given
+    /// `shebang` and `set` options, writing its output and error to `output` and `error`
+    pub fn render(
+        &self,
+        shebang: &str,
+        set: &str,
+        command: &str,
+        output: &str,
+        error: &str,
+    ) -> String {
+        let mut setup = String::new();
+        if !self.modules.is_empty() {
+            setup.push_str(&module_loads(&self.modules));
+        }
+        if let Some(conda_env) = &self.conda_env {
+            setup.push_str(&conda_activation(conda_env, shebang));
+        }
+        if let Some(venv) = &self.venv {
+            let activate = format!("{}/bin/activate", venv.trim_end_matches('/'));
+            let _ = writeln!(setup, "source {}", shell_quote(&activate));
+        }
+        if let Some(manifest) = &self.pixi {
+            let shell = match script_shell(shebang) {
+                "zsh" => "zsh",
+                _ => "bash",
+            };
+            let _ = writeln!(
+                setup,
+                "eval \"$(pixi shell-hook --shell {shell} --manifest-path {})\"",
+                shell_quote(manifest)
+            );
+        }
+        let mut body = String::new();
+        if let Some(epilogue) = &self.epilogue {
+            let _ = writeln!(body, "{}", epilogue_trap(epilogue));
+        }
+        if !setup.is_empty() {
+            let _ = writeln!(body, "{}", without_strict_options(&setup, set));
+        }
+        if let Some(prologue) = &self.prologue {
+            let _ = writeln!(body, "{}\n", prologue.trim_end());
+        }
+        match &self.container {
+            Some(container) => {
+                body.push_str(&container.wrap(command, shebang, set, &[output, error]));
+            }
+            None => body.push_str(command),
+        }
+        body
+    }
+
+    /// Check that the container image, virtual environment and pixi manifest exist, for a job
+    /// submitted with `arguments`
+    pub fn check_paths(&self, arguments: &[String]) -> Result<(), String> {
+        if let Some(container) = &self.container {
+            container.check_image(arguments)?;
+        }
+        if let Some(venv) = &self.venv {
+            let activate = job_path(venv, arguments).join("bin").join("activate");
+            if !activate.is_file() {
+                return Err(format!(
+                    "{venv} is not a virtual environment, as {} does not exist",
+                    activate.display()
+                ));
+            }
+        }
+        if let Some(manifest) = &self.pixi {
+            let path = job_path(manifest, arguments);
+            if !path.exists() {
+                return Err(format!("Pixi manifest {} does not exist", path.display()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Shell commands that run `epilogue` when the script exits, even when `set -e` ends it early.
+/// The epilogue runs to the end whatever its commands exit with, and the script exits with the
+/// exit code it would have without it.
+fn epilogue_trap(epilogue: &str) -> String {
+    format!(
+        "ssubmit_epilogue() {{\n{}\n}}\ntrap 'exit_code=$?; ssubmit_epilogue || true; exit \"$exit_code\"' EXIT\n",
+        epilogue.trim_end()
+    )
+}
+
+/// Shell commands that activate a conda environment with conda, or micromamba if conda is not
+/// installed on the node
+fn conda_activation(conda_env: &str, shebang: &str) -> String {
+    let shell = script_shell(shebang);
+    let environment = shell_quote(conda_env);
+    format!(
+        r#"if command -v conda >/dev/null 2>&1; then
+    eval "$(conda shell.{shell} hook)"
+    conda activate {environment}
+else
+    eval "$(micromamba shell hook --shell {shell})"
+    micromamba activate {environment}
+fi
+"#
+    )
+}
+
+/// Shell commands that load environment modules, after unloading any that are loaded
+fn module_loads(modules: &[String]) -> String {
+    let modules: Vec<String> = modules.iter().map(|module| shell_quote(module)).collect();
+    format!("module purge\nmodule load {}\n", modules.join(" "))
+}
+
+/// Wrap `commands` so they run with the `u` and `x` shell options in `set` turned off, as
+/// activation scripts, e.g. conda's and Lmod's, use unset variables and are very long
+fn without_strict_options(commands: &str, set: &str) -> String {
+    let options: String = set
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|option| matches!(option, 'u' | 'x'))
+        .collect();
+    if options.is_empty() {
+        commands.to_string()
+    } else {
+        format!("set +{options}\n{commands}set -{options}\n")
+    }
+}
+
+/// The shell that runs a script with `shebang`, as named by conda: bash, zsh or posix for any
+/// other shell
+fn script_shell(shebang: &str) -> &'static str {
+    let mut words = shebang.trim_start_matches("#!").split_whitespace();
+    let mut interpreter = words.next().unwrap_or_default();
+    if interpreter.ends_with("/env") {
+        interpreter = words
+            .find(|word| !word.starts_with('-'))
+            .unwrap_or_default();
+    }
+    match interpreter.rsplit('/').next() {
+        Some("bash") => "bash",
+        Some("zsh") => "zsh",
+        _ => "posix",
+    }
+}
+
+impl Container {
+    /// Shell commands that run `command` in the container, binding the working directory, the
+    /// directories of `logs` and any extra paths. The command is run by the shell of the script's
+    /// `shebang`, with its `set` options, as it would be outside of the container.
+    fn wrap(&self, command: &str, shebang: &str, set: &str, logs: &[&str]) -> String {
+        let mut binds = vec!["\"$PWD\"".to_string()];
+        let directories = logs.iter().filter_map(|log| bind_directory(log));
+        for bind in directories.chain(self.binds.iter().cloned()) {
+            let bind = shell_quote(&bind);
+            if !binds.contains(&bind) {
+                binds.push(bind);
+            }
+        }
+
+        let mut exec = String::from("\"$container_runtime\" exec");
+        if self.nv {
+            exec.push_str(" --nv");
+        }
+        for bind in binds {
+            let _ = write!(exec, " --bind {bind}");
+        }
+        let shell = match script_shell(shebang) {
+            "posix" => "sh",
+            shell => shell,
+        };
+        let command = if set.is_empty() {
+            command.to_string()
+        } else {
+            format!("set -{set}; {command}")
+        };
+        format!(
+            "container_runtime=$(command -v apptainer || command -v singularity || echo apptainer)\n{exec} {} {shell} -c {}",
+            shell_quote(&self.image),
+            shell_quote(&command)
+        )
+    }
+
+    /// Check that the image exists, if it is a file. A relative path is taken to be relative to
+    /// the `--chdir` in `arguments`, as that is where the job runs.
+    pub fn check_image(&self, arguments: &[String]) -> Result<(), String> {
+        if self.image.contains("://") {
+            return Ok(());
+        }
+        let image = job_path(&self.image, arguments);
+        if image.is_file() {
+            Ok(())
+        } else {
+            Err(format!(
+                "Container image {} does not exist",
+                image.display()
+            ))
+        }
+    }
+}
+
+/// Where `path` is for a job submitted with `arguments`: relative to the `--chdir` in them, if
+/// any, as that is where the job runs
+pub fn job_path(path: &str, arguments: &[String]) -> PathBuf {
+    match CHDIR.value_in(arguments) {
+        Some(directory) => Path::new(&directory).join(path),
+        None => PathBuf::from(path),
+    }
+}
+
+/// The directory of a log file that needs binding into a container: one that is absolute, so not
+/// under the working directory, and that has no filename patterns
+fn bind_directory(log: &str) -> Option<String> {
+    let directory = Path::new(log).parent()?;
+    if !directory.is_absolute()
+        || directory == Path::new("/dev")
+        || directory.to_string_lossy().contains('%')
+    {
+        return None;
+    }
+    Some(directory.to_string_lossy().into_owned())
+}
+
+/// When a dependent job may start, relative to the jobs it depends on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DependencyKind {
+    /// Once the jobs have started
+    After,
+    /// Once the jobs have completed successfully
+    AfterOk,
+    /// Once the jobs have finished, in any state
+    AfterAny,
+    /// Once the jobs have failed
+    AfterNotOk,
+}
+
+impl DependencyKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            DependencyKind::After => "after",
+            DependencyKind::AfterOk => "afterok",
+            DependencyKind::AfterAny => "afterany",
+            DependencyKind::AfterNotOk => "afternotok",
+        }
+    }
+}
+
+/// A dependency on a job given by ID or name
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Dependency {
+    #[serde(rename = "type")]
+    pub kind: DependencyKind,
+    /// The job ID or name as given
+    pub job: String,
+    /// The IDs of the jobs it refers to
+    pub job_ids: Vec<String>,
+}
+
+/// The `--dependency` specification requiring all of the dependencies, e.g.
+/// `afterok:123:124,afterany:125`
+pub fn dependency_specification(dependencies: &[Dependency]) -> Option<String> {
+    let mut groups: Vec<(DependencyKind, Vec<&str>)> = Vec::new();
+    for dependency in dependencies {
+        let index = match groups.iter().position(|(kind, _)| *kind == dependency.kind) {
+            Some(index) => index,
+            None => {
+                groups.push((dependency.kind, Vec::new()));
+                groups.len() - 1
+            }
+        };
+        let group = &mut groups[index].1;
+        for job_id in &dependency.job_ids {
+            if !group.contains(&job_id.as_str()) {
+                group.push(job_id);
+            }
+        }
+    }
+    let specification: Vec<String> = groups
+        .into_iter()
+        .map(|(kind, ids)| format!("{}:{}", kind.as_str(), ids.join(":")))
+        .collect();
+    (!specification.is_empty()).then(|| specification.join(","))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SlurmPlan {
     pub executable: String,
     pub arguments: Vec<String>,
     pub script: String,
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SubmissionPlan {
     pub job: JobSpec,
     pub slurm: SlurmPlan,
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SubmissionResult {
     pub job_id: String,
     pub cluster: Option<String>,
 }
 
+/// A job of a pipeline, with its submission result unless it was a dry run
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PipelineJobResult {
+    pub plan: SubmissionPlan,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub submission: Option<SubmissionResult>,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct SchedulerTestResult {
     pub stdout: String,
@@ -120,6 +692,18 @@ pub struct JsonResponse {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub test: Option<SchedulerTestResult>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub jobs: Option<Vec<PipelineJobResult>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub statuses: Option<Vec<JobStatus>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cancellation: Option<CancelResult>,
+    /// The status of a submitted job once it finished, when waiting for it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<JobStatus>,
+    /// The output and error files of the job, as far as they are known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logs: Option<LogPaths>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<JsonError>,
 }
 
@@ -132,6 +716,11 @@ impl JsonResponse {
             plan: Some(plan),
             submission: None,
             test: None,
+            jobs: None,
+            statuses: None,
+            cancellation: None,
+            outcome: None,
+            logs: None,
             error: None,
         }
     }
@@ -144,6 +733,11 @@ impl JsonResponse {
             plan: Some(plan),
             submission: Some(submission),
             test: None,
+            jobs: None,
+            statuses: None,
+            cancellation: None,
+            outcome: None,
+            logs: None,
             error: None,
         }
     }
@@ -156,10 +750,145 @@ impl JsonResponse {
             plan: Some(plan),
             submission: None,
             test: Some(test),
+            jobs: None,
+            statuses: None,
+            cancellation: None,
+            outcome: None,
+            logs: None,
             error: None,
         }
     }
 
+    pub fn pipeline(jobs: Vec<PipelineJobResult>) -> Self {
+        Self {
+            schema_version: JSON_SCHEMA_VERSION,
+            operation: "run".to_string(),
+            ok: true,
+            plan: None,
+            submission: None,
+            test: None,
+            jobs: Some(jobs),
+            statuses: None,
+            cancellation: None,
+            outcome: None,
+            logs: None,
+            error: None,
+        }
+    }
+
+    /// A pipeline that failed, listing the jobs that were submitted before the failure
+    pub fn pipeline_error(jobs: Vec<PipelineJobResult>, error: SubmissionError) -> Self {
+        Self {
+            schema_version: JSON_SCHEMA_VERSION,
+            operation: "run".to_string(),
+            ok: false,
+            plan: None,
+            submission: None,
+            test: None,
+            jobs: Some(jobs),
+            statuses: None,
+            cancellation: None,
+            outcome: None,
+            logs: None,
+            error: Some(JsonError {
+                kind: error.kind,
+                message: error.message,
+                exit_code: error.exit_code,
+                stderr: error.stderr,
+            }),
+        }
+    }
+
+    pub fn status(statuses: Vec<JobStatus>) -> Self {
+        Self {
+            schema_version: JSON_SCHEMA_VERSION,
+            operation: "status".to_string(),
+            ok: true,
+            plan: None,
+            submission: None,
+            test: None,
+            jobs: None,
+            statuses: Some(statuses),
+            cancellation: None,
+            outcome: None,
+            logs: None,
+            error: None,
+        }
+    }
+
+    pub fn status_error(error: SubmissionError) -> Self {
+        Self {
+            schema_version: JSON_SCHEMA_VERSION,
+            operation: "status".to_string(),
+            ok: false,
+            plan: None,
+            submission: None,
+            test: None,
+            jobs: None,
+            statuses: None,
+            cancellation: None,
+            outcome: None,
+            logs: None,
+            error: Some(JsonError {
+                kind: error.kind,
+                message: error.message,
+                exit_code: error.exit_code,
+                stderr: error.stderr,
+            }),
+        }
+    }
+
+    /// The jobs that matched and the outcome of cancelling them, which fails if any of them
+    /// could not be cancelled
+    pub fn cancellation(statuses: Vec<JobStatus>, cancellation: CancelResult) -> Self {
+        let error = (!cancellation.failed.is_empty()).then(|| JsonError {
+            kind: "slurm".to_string(),
+            message: format!(
+                "Failed to cancel {} of {} jobs",
+                cancellation.failed.len(),
+                cancellation.failed.len() + cancellation.cancelled.len()
+            ),
+            exit_code: None,
+            stderr: None,
+        });
+        Self {
+            schema_version: JSON_SCHEMA_VERSION,
+            operation: "cancel".to_string(),
+            ok: error.is_none(),
+            plan: None,
+            submission: None,
+            test: None,
+            jobs: None,
+            statuses: Some(statuses),
+            cancellation: Some(cancellation),
+            outcome: None,
+            logs: None,
+            error,
+        }
+    }
+
+    pub fn cancellation_error(error: SubmissionError) -> Self {
+        Self {
+            schema_version: JSON_SCHEMA_VERSION,
+            operation: "cancel".to_string(),
+            ok: false,
+            plan: None,
+            submission: None,
+            test: None,
+            jobs: None,
+            statuses: None,
+            cancellation: None,
+            outcome: None,
+            logs: None,
+            error: Some(JsonError {
+                kind: error.kind,
+                message: error.message,
+                exit_code: error.exit_code,
+                stderr: error.stderr,
+            }),
+        }
+    }
+
     pub fn error(kind: impl Into<String>, message: impl Into<String>) -> Self {
         Self {
             schema_version: JSON_SCHEMA_VERSION,
@@ -168,6 +897,11 @@ impl JsonResponse {
             plan: None,
             submission: None,
             test: None,
+            jobs: None,
+            statuses: None,
+            cancellation: None,
+            outcome: None,
+            logs: None,
             error: Some(JsonError {
                 kind: kind.into(),
                 message: message.into(),
@@ -185,6 +919,11 @@ impl JsonResponse {
             plan: Some(plan),
             submission: None,
             test: None,
+            jobs: None,
+            statuses: None,
+            cancellation: None,
+            outcome: None,
+            logs: None,
             error: Some(JsonError {
                 kind: error.kind,
                 message: error.message,
@@ -202,6 +941,11 @@ impl JsonResponse {
             plan: Some(plan),
             submission: None,
             test: None,
+            jobs: None,
+            statuses: None,
+            cancellation: None,
+            outcome: None,
+            logs: None,
             error: Some(JsonError {
                 kind: error.kind,
                 message: error.message,
@@ -218,7 +962,7 @@ static SCRIPT_TEMPLATE: &str = r#"$shebang$
 #SBATCH --time=$time$
 #SBATCH --error=$error$
 #SBATCH --output=$output$
-$set$
+$directives$$set$
 
 $cmd$
 "#;
@@ -232,12 +976,17 @@ pub fn make_submission_script(
     time: &str,
     error: &str,
     output: &str,
+    resources: &Resources,
     cmd: &str,
 ) -> String {
     let mut set_line = String::new();
     if !set.is_empty() {
         let _ = write!(set_line, "set -{set}");
     }
+    let mut directives = String::new();
+    for argument in resources.slurm_arguments() {
+        let _ = writeln!(directives, "#SBATCH {argument}");
+    }
     let script = SCRIPT_TEMPLATE
         .replace("$shebang$", shebang)
         .replace("$name$", name)
@@ -245,6 +994,7 @@ pub fn make_submission_script(
         .replace("$time$", time)
         .replace("$error$", error)
         .replace("$output$", output)
+        .replace("$directives$", &directives)
         .replace("$cmd$", cmd)
         .replace("$set$", &set_line);
 
@@ -260,6 +1010,156 @@ pub fn make_submission_script(
     }
 }
 
+/// One task of a job array
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArrayTask {
+    pub command: String,
+    /// Variables exported before the command is run
+    pub variables: Vec<(String, String)>,
+}
+
+/// The commands in a job array file: one per line, ignoring blank lines and `#` comments
+pub fn parse_array_commands(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// A table with a header row and one sample per row. Each sample becomes an array task whose
+/// command is a template with `{column}` placeholders filled in from the sample.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleSheet {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl SampleSheet {
+    /// Parse a sample sheet whose fields are separated by `delimiter`. Lines starting with `#`
+    /// are ignored and column names must be valid shell variable names.
+    pub fn parse(contents: &str, delimiter: u8) -> Result<Self, String> {
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .comment(Some(b'#'))
+            .trim(csv::Trim::All)
+            .from_reader(contents.as_bytes());
+
+        let identifier = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").expect("valid regex");
+        let mut columns: Vec<String> = Vec::new();
+        for column in reader.headers().map_err(|e| e.to_string())? {
+            if !identifier.is_match(column) {
+                return Err(format!(
+                    "column name '{column}' is not a valid shell variable name"
+                ));
+            }
+            if columns.iter().any(|seen| seen == column) {
+                return Err(format!("column {column} is given more than once"));
+            }
+            columns.push(column.to_string());
+        }
+
+        let mut rows = Vec::new();
+        for record in reader.records() {
+            let record = record.map_err(|e| e.to_string())?;
+            rows.push(record.iter().map(str::to_string).collect());
+        }
+        if rows.is_empty() {
+            return Err("it contains no samples".to_string());
+        }
+
+        Ok(Self { columns, rows })
+    }
+
+    /// One task per sample, running `template` with each `{column}` replaced by the sample's
+    /// value and every column exported as a variable. `${...}` is left for the shell. Values the
+    /// shell would split or interpret, e.g. `b 2.fq` or `a;b`, are quoted.
+    pub fn expand(&self, template: &str) -> Result<Vec<ArrayTask>, String> {
+        let placeholder = Regex::new(r"(\$?)\{([A-Za-z_][A-Za-z0-9_]*)\}").expect("valid regex");
+        for captures in placeholder.captures_iter(template) {
+            let name = &captures[2];
+            if captures[1].is_empty() && !self.columns.iter().any(|column| column == name) {
+                return Err(format!(
+                    "Unknown placeholder {{{name}}} in the command; the sample sheet columns are {}",
+                    self.columns.join(", ")
+                ));
+            }
+        }
+
+        let tasks = self
+            .rows
+            .iter()
+            .map(|row| {
+                let value = |name: &str| {
+                    let index = self.columns.iter().position(|column| column == name);
+                    index.map(|index| row[index].as_str()).unwrap_or_default()
+                };
+                let command = placeholder.replace_all(template, |captures: &regex::Captures| {
+                    if captures[1].is_empty() {
+                        shell_word(value(&captures[2]))
+                    } else {
+                        captures[0].to_string()
+                    }
+                });
+                ArrayTask {
+                    command: command.into_owned(),
+                    variables: self.columns.iter().cloned().zip(row.clone()).collect(),
+                }
+            })
+            .collect();
+        Ok(tasks)
+    }
+}
+
+/// Quote a value so the shell treats it literally
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// A value as a single shell word, quoted only if the shell would otherwise split or interpret it
+fn shell_word(value: &str) -> String {
+    let plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./:@%+=,".contains(c));
+    if plain {
+        value.to_string()
+    } else {
+        shell_quote(value)
+    }
+}
+
+/// The `--array` index specification for `tasks` tasks, running at most `throttle` at once
+pub fn array_indices(tasks: usize, throttle: Option<u32>) -> String {
+    match throttle {
+        Some(throttle) => format!("1-{tasks}%{throttle}"),
+        None => format!("1-{tasks}"),
+    }
+}
+
+/// A script body that runs the task for the current `$SLURM_ARRAY_TASK_ID`, where task 1 is the
+/// first task
+pub fn make_array_command(tasks: &[ArrayTask]) -> String {
+    let mut cmd = String::from("case \"${SLURM_ARRAY_TASK_ID:-}\" in\n");
+    for (index, task) in tasks.iter().enumerate() {
+        let _ = writeln!(cmd, "{})", index + 1);
+        if !task.variables.is_empty() {
+            let exports: Vec<String> = task
+                .variables
+                .iter()
+                .map(|(name, value)| format!("{name}={}", shell_quote(value)))
+                .collect();
+            let _ = writeln!(cmd, "    export {}", exports.join(" "));
+        }
+        let _ = write!(cmd, "    {}\n    ;;\n", task.command);
+    }
+    cmd.push_str(
+        "*)\n    echo \"No command for array task ${SLURM_ARRAY_TASK_ID:-}\" >&2\n    exit 1\n    ;;\nesac",
+    );
+    cmd
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn make_submission_plan(
     shebang: &str,
@@ -269,12 +1169,24 @@ pub fn make_submission_plan(
     time: &str,
     error: &str,
     output: &str,
+    resources: &Resources,
+    environment: &Environment,
     command: &str,
     remainder: &[String],
     export: &str,
     test_only: bool,
 ) -> SubmissionPlan {
-    let script = make_submission_script(shebang, set, name, memory, time, error, output, command);
+    let script = make_submission_script(
+        shebang,
+        set,
+        name,
+        memory,
+        time,
+        error,
+        output,
+        resources,
+        &environment.render(shebang, set, command, output, error),
+    );
     let effective_export = effective_export(remainder, export);
 
     let mut arguments = Vec::with_capacity(remainder.len() + usize::from(test_only));
@@ -306,6 +1218,15 @@ pub fn make_submission_plan(
             output: output.to_string(),
             error: error.to_string(),
             export: effective_export,
+            shebang: shebang.to_string(),
+            set: set.to_string(),
+            resources: resources.clone(),
+            environment: environment.clone(),
+            profile: None,
+            tasks: Vec::new(),
+            dependencies: Vec::new(),
+            resubmit_of: None,
+            partition_reason: None,
         },
         slurm: SlurmPlan {
             executable: "sbatch".to_string(),
@@ -315,6 +1236,812 @@ pub fn make_submission_plan(
     }
 }
 
+/// Plan a job again from its job specification, e.g. with different resources, passing the
+/// given sbatch arguments. The script is rendered anew, and everything in the specification
+/// that is not part of the script, such as the profile, is kept.
+pub fn replan_submission(job: &JobSpec, arguments: &[String]) -> SubmissionPlan {
+    plan_job(job, arguments, None)
+}
+
+/// Plan a job from its job specification, running `guard` before the job's environment is set
+/// up and its command is run
+fn plan_job(job: &JobSpec, arguments: &[String], guard: Option<&str>) -> SubmissionPlan {
+    let mut body = job.environment.render(
+        &job.shebang,
+        &job.set,
+        &job.command,
+        &job.output,
+        &job.error,
+    );
+    if let Some(guard) = guard {
+        body = format!("{guard}\n{body}");
+    }
+    let mut plan = make_submission_plan(
+        &job.shebang,
+        &job.set,
+        &job.name,
+        &job.memory,
+        &job.time,
+        &job.error,
+        &job.output,
+        &job.resources,
+        &Environment::default(),
+        &body,
+        arguments,
+        &job.export,
+        false,
+    );
+    plan.job.command = job.command.clone();
+    plan.job.environment = job.environment.clone();
+    plan.job.profile = job.profile.clone();
+    plan.job.tasks = job.tasks.clone();
+    plan.job.dependencies = job.dependencies.clone();
+    plan.job.resubmit_of = job.resubmit_of.clone();
+    plan.job.partition_reason = job.partition_reason.clone();
+    plan
+}
+
+/// Plan a retry of a job with the given memory and time, after the earlier `attempts` of it, in
+/// order. The retry depends on every earlier attempt failing, so Slurm removes it as soon as one
+/// of them succeeds, and it only runs the command if the previous attempt ran out of memory or
+/// time. Depending on the previous attempt alone is not enough: when a retry is removed, it
+/// counts as failed, and the retry after it would run.
+pub fn make_retry_plan(
+    plan: &SubmissionPlan,
+    attempts: &[String],
+    memory: &str,
+    time: &str,
+) -> SubmissionPlan {
+    let previous_job_id = attempts.last().map(String::as_str).unwrap_or_default();
+    let mut job = plan.job.clone();
+    job.memory = memory.to_string();
+    job.time = time.to_string();
+    job.dependencies = attempts
+        .iter()
+        .map(|attempt| Dependency {
+            kind: DependencyKind::AfterNotOk,
+            job: attempt.clone(),
+            job_ids: vec![attempt.clone()],
+        })
+        .collect();
+    job.resources.dependency = dependency_specification(&job.dependencies);
+    job.resubmit_of = Some(previous_job_id.to_string());
+
+    let mut arguments = plan.slurm.arguments.clone();
+    if !arguments
+        .iter()
+        .any(|argument| argument.starts_with("--kill-on-invalid-dep"))
+    {
+        arguments.push("--kill-on-invalid-dep=yes".to_string());
+    }
+    // the guard runs outside of any container, where sacct is available
+    plan_job(&job, &arguments, Some(&make_retry_guard(previous_job_id)))
+}
+
+/// Shell commands that end a retry successfully, without running the job's command, unless the
+/// previous attempt ran out of memory or time
+fn make_retry_guard(previous_job_id: &str) -> String {
+    format!(
+        r#"previous_state=$(sacct --jobs={previous_job_id} --allocations --noheader --parsable2 --format=State | head -n 1)
+case "$previous_state" in
+OUT_OF_MEMORY* | TIMEOUT*) ;;
+*)
+    echo "Not retrying: job {previous_job_id} ended as ${{previous_state:-UNKNOWN}}" >&2
+    exit 0
+    ;;
+esac
+"#
+    )
+}
+
+/// The number of seconds in a Slurm time limit, e.g. `90`, `1:30:00` or `2-12`
+pub fn slurm_time_seconds(time: &str) -> Option<u64> {
+    let (days, rest) = match time.split_once('-') {
+        Some((days, rest)) => (Some(days.parse::<u64>().ok()?), rest),
+        None => (None, time),
+    };
+    let fields = rest
+        .split(':')
+        .map(|field| field.parse::<u64>().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    let (hours, minutes, seconds) = match (days.is_some(), fields.as_slice()) {
+        (false, [minutes]) => (0, *minutes, 0),
+        (false, [minutes, seconds]) => (0, *minutes, *seconds),
+        (true, [hours]) => (*hours, 0, 0),
+        (true, [hours, minutes]) => (*hours, *minutes, 0),
+        (_, [hours, minutes, seconds]) => (*hours, *minutes, *seconds),
+        _ => return None,
+    };
+    Some(days.unwrap_or(0) * 86400 + hours * 3600 + minutes * 60 + seconds)
+}
+
+/// What is known about a job when expanding its filename patterns
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JobMetadata {
+    /// The job ID given by sbatch, which for a job array is the ID of the array as a whole
+    pub job_id: Option<String>,
+    /// The ID of an array task, starting at 1
+    pub array_task_id: Option<usize>,
+    /// The job ID of an array task, which is only known once the task is scheduled
+    pub array_task_job_id: Option<String>,
+    /// The name of the first node the job runs on
+    pub node: Option<String>,
+    /// The user submitting the job
+    pub user: Option<String>,
+}
+
+/// A file name with its sbatch patterns expanded as far as they are known
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExpandedFilename {
+    pub path: String,
+    /// The patterns left in `path` because their values are not known yet, e.g. `%N` before the
+    /// job runs
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unresolved: Vec<String>,
+}
+
+/// The output and error files of a job
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LogPaths {
+    pub output: ExpandedFilename,
+    pub error: ExpandedFilename,
+}
+
+/// The task ID Slurm gives a job that is not part of an array, its `NO_VAL`
+const NO_ARRAY_TASK: &str = "4294967294";
+
+/// Expand an sbatch `--output`/`--error` filename pattern for the batch script of `job`:
+///
+/// | pattern | value |
+/// |---|---|
+/// | `%%` | `%` |
+/// | `%A` | the job ID, or for an array task, the ID of the array |
+/// | `%a` | the array task ID, or 4294967294 if the job is not an array |
+/// | `%J` | `<%j>.batch` |
+/// | `%j` | the job ID, or for an array task, the task's own job ID |
+/// | `%N` | the first node of the job |
+/// | `%n`, `%t` | the node and task of the batch script, always 0 |
+/// | `%s` | the step of the batch script, `batch` |
+/// | `%u` | the user name |
+/// | `%x` | the job name |
+///
+/// A number between `%` and a numeric pattern zero-pads it to that width, e.g. `%3a` is `007`
+/// for task 7. As with sbatch, a pattern containing a backslash is not expanded, and only has its
+/// backslashes removed. Patterns that depend on something not in `metadata` are left as they are
+/// and listed in `unresolved`.
+pub fn expand_filename_pattern(
+    pattern: &str,
+    job: &JobSpec,
+    metadata: &JobMetadata,
+) -> ExpandedFilename {
+    if pattern.contains('\\') {
+        return ExpandedFilename {
+            path: pattern.replace('\\', ""),
+            unresolved: vec![],
+        };
+    }
+
+    let is_array = !job.tasks.is_empty();
+    let job_id = if is_array {
+        metadata.array_task_job_id.as_deref()
+    } else {
+        metadata.job_id.as_deref()
+    };
+    let array_task_id = if is_array {
+        metadata.array_task_id.map(|id| id.to_string())
+    } else {
+        Some(NO_ARRAY_TASK.to_string())
+    };
+
+    let mut path = String::new();
+    let mut unresolved = Vec::new();
+    let mut chars = pattern.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if c != '%' {
+            path.push(c);
+            continue;
+        }
+        let mut width = String::new();
+        while let Some(&(_, digit)) = chars.peek() {
+            if !digit.is_ascii_digit() {
+                break;
+            }
+            width.push(digit);
+            chars.next();
+        }
+        let specifier = chars.next().map(|(_, specifier)| specifier);
+        let end = chars.peek().map_or(pattern.len(), |&(index, _)| index);
+        let original = &pattern[start..end];
+        let width: usize = width.parse().unwrap_or(0);
+        let pad = |value: &str| format!("{value:0>width$}");
+
+        let value = match specifier {
+            Some('%') => Some("%".to_string()),
+            Some('A') => metadata.job_id.as_deref().map(pad),
+            Some('a') => array_task_id.as_deref().map(pad),
+            Some('J') => job_id.map(|id| format!("{}.batch", pad(id))),
+            Some('j') => job_id.map(pad),
+            Some('N') => metadata.node.clone(),
+            Some('n' | 't') => Some(pad("0")),
+            Some('s') => Some("batch".to_string()),
+            Some('u') => metadata.user.clone(),
+            Some('x') => Some(job.name.clone()),
+            // not a pattern, so taken literally
+            _ => {
+                path.push_str(original);
+                continue;
+            }
+        };
+        match value {
+            Some(value) => path.push_str(&value),
+            None => {
+                path.push_str(original);
+                unresolved.push(original.to_string());
+            }
+        }
+    }
+    ExpandedFilename { path, unresolved }
+}
+
+/// The output and error files of `job`, as far as they are known
+pub fn log_paths(job: &JobSpec, metadata: &JobMetadata) -> LogPaths {
+    LogPaths {
+        output: expand_filename_pattern(&job.output, job, metadata),
+        error: expand_filename_pattern(&job.error, job, metadata),
+    }
+}
+
+/// The name of the user running ssubmit
+pub fn current_user() -> Option<String> {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("LOGNAME"))
+        .ok()
+        .filter(|user| !user.is_empty())
+        .or_else(|| {
+            let output = Command::new("id").arg("-un").output().ok()?;
+            let user = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            if output.status.success() && !user.is_empty() {
+                Some(user)
+            } else {
+                None
+            }
+        })
+}
+
+/// Check that the directories of a job's output and error files exist and are writable, as
+/// Slurm fails a job without writing anything otherwise. With `create`, missing directories are
+/// created, and returned. Relative paths are from the job's `--chdir`, if any, or the current
+/// directory, and directories named with a pattern only known once the job runs are not checked.
+pub fn check_log_directories(
+    plan: &SubmissionPlan,
+    create: bool,
+) -> Result<Vec<PathBuf>, SubmissionError> {
+    let metadata = JobMetadata {
+        user: current_user(),
+        ..JobMetadata::default()
+    };
+    let logs = log_paths(&plan.job, &metadata);
+    let working_directory = CHDIR.value_in(&plan.slurm.arguments).map(PathBuf::from);
+
+    let mut created = Vec::new();
+    for (kind, file) in [("output", logs.output), ("error", logs.error)] {
+        let directory = match Path::new(&file.path).parent() {
+            Some(directory) if !directory.as_os_str().is_empty() => directory,
+            _ => continue,
+        };
+        let directory_name = directory.to_string_lossy();
+        if file
+            .unresolved
+            .iter()
+            .any(|pattern| directory_name.contains(pattern.as_str()))
+        {
+            continue;
+        }
+        let directory = match &working_directory {
+            Some(working_directory) => working_directory.join(directory),
+            None => directory.to_path_buf(),
+        };
+
+        if !directory.exists() {
+            if !create {
+                return Err(SubmissionError::validation(format!(
+                    "Directory {} for {kind} file {} does not exist. Create it or use --mkdir",
+                    directory.display(),
+                    file.path
+                )));
+            }
+            std::fs::create_dir_all(&directory).map_err(|error| {
+                SubmissionError::validation(format!(
+                    "Failed to create directory {} for {kind} file {}: {error}",
+                    directory.display(),
+                    file.path
+                ))
+            })?;
+            created.push(directory);
+        } else if !directory.is_dir() {
+            return Err(SubmissionError::validation(format!(
+                "{} for {kind} file {} is not a directory",
+                directory.display(),
+                file.path
+            )));
+        } else if !is_writable(&directory) {
+            return Err(SubmissionError::validation(format!(
+                "Directory {} for {kind} file {} is not writable",
+                directory.display(),
+                file.path
+            )));
+        }
+    }
+    Ok(created)
+}
+
+/// Whether the current user can write to `path`. If that cannot be checked, it is assumed they
+/// can, and Slurm has the last word.
+fn is_writable(path: &Path) -> bool {
+    Command::new("test")
+        .arg("-w")
+        .arg(path)
+        .status()
+        .map_or(true, |status| status.success())
+}
+
+/// Check that every one of `modules` is available from `module avail`, as a name or as a
+/// name/version. If the module command is not available, the modules are not checked.
+pub fn check_modules(modules: &[String]) -> Result<(), SubmissionError> {
+    if modules.is_empty() {
+        return Ok(());
+    }
+    let output = match Command::new("bash")
+        .args(["-c", "module -t avail 2>&1"])
+        .output()
+    {
+        Ok(output) if output.status.success() => output,
+        _ => {
+            warn!("Not checking the modules, as the module command is not available");
+            return Ok(());
+        }
+    };
+    let available = parse_module_avail(&String::from_utf8_lossy(&output.stdout));
+    let unknown: Vec<&str> = modules
+        .iter()
+        .filter(|module| !is_module_available(module, &available))
+        .map(String::as_str)
+        .collect();
+    if unknown.is_empty() {
+        Ok(())
+    } else {
+        Err(SubmissionError::validation(format!(
+            "Unknown module{}: {}. Run `module avail` to see the available modules",
+            if unknown.len() == 1 { "" } else { "s" },
+            unknown.join(", ")
+        )))
+    }
+}
+
+/// Parse `module -t avail` output into the available modules, without markers such as
+/// `(default)`
+pub fn parse_module_avail(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.ends_with(':'))
+        .map(|line| match line.find('(') {
+            Some(start) => line[..start].to_string(),
+            None => line.to_string(),
+        })
+        .collect()
+}
+
+/// Whether `module` is one of `available`, or is the name of one of them without its version
+fn is_module_available(module: &str, available: &[String]) -> bool {
+    let module = module.trim_end_matches('/');
+    available.iter().any(|candidate| {
+        let candidate = candidate.trim_end_matches('/');
+        candidate == module
+            || candidate
+                .strip_prefix(module)
+                .map_or(false, |version| version.starts_with('/'))
+    })
+}
+
+/// The limits of a Slurm partition that jobs are checked against before they are submitted.
+/// Memory is in megabytes, and `None` is unlimited or unknown.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PartitionLimits {
+    pub name: String,
+    /// Whether jobs that do not name a partition run in this one
+    pub default: bool,
+    /// Whether the partition is down, drained or inactive, so takes no jobs
+    #[serde(default)]
+    pub down: bool,
+    /// `MaxTime`, as Slurm gives it
+    pub max_time: Option<String>,
+    pub max_memory_per_node: Option<u64>,
+    pub max_nodes: Option<u32>,
+    pub max_cpus_per_node: Option<u32>,
+    /// The CPUs of the largest node
+    pub node_cpus: Option<u32>,
+    /// The memory of the largest node
+    pub node_memory: Option<u64>,
+    /// The GPUs of the node with the most
+    pub node_gpus: u32,
+    /// The types of the GPUs in the partition, e.g. `a100`
+    pub gpu_types: Vec<String>,
+}
+
+/// Query the limits of every partition with `scontrol show partition` and the configuration of
+/// their nodes with `sinfo`
+pub fn query_partition_limits() -> Result<Vec<PartitionLimits>, SubmissionError> {
+    let partitions = run_query("scontrol", &["show", "partition", "--all", "--oneliner"])?;
+    let nodes = run_query("sinfo", &["--noheader", "--format=%P|%c|%m|%G"])?;
+    let mut limits = parse_scontrol_partitions(&partitions);
+    add_sinfo_nodes(&mut limits, &nodes);
+    Ok(limits)
+}
+
+/// Parse `scontrol show partition --oneliner` output
+pub fn parse_scontrol_partitions(output: &str) -> Vec<PartitionLimits> {
+    fn limited(value: &str) -> Option<&str> {
+        Some(value).filter(|value| *value != "UNLIMITED")
+    }
+    output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<(&str, &str)> = line
+                .split_whitespace()
+                .filter_map(|field| field.split_once('='))
+                .collect();
+            let field = |key: &str| {
+                fields
+                    .iter()
+                    .find(|(name, _)| *name == key)
+                    .map(|(_, value)| *value)
+            };
+            Some(PartitionLimits {
+                name: field("PartitionName")?.to_string(),
+                default: field("Default") == Some("YES"),
+                down: field("State").map_or(false, |state| state != "UP"),
+                max_time: field("MaxTime").and_then(limited).map(str::to_string),
+                max_memory_per_node: field("MaxMemPerNode")
+                    .and_then(limited)
+                    .and_then(|value| value.parse().ok()),
+                max_nodes: field("MaxNodes")
+                    .and_then(limited)
+                    .and_then(|value| value.parse().ok()),
+                max_cpus_per_node: field("MaxCPUsPerNode")
+                    .and_then(limited)
+                    .and_then(|value| value.parse().ok()),
+                ..PartitionLimits::default()
+            })
+        })
+        .collect()
+}
+
+/// Add the largest node configuration of each partition from `sinfo --format=%P|%c|%m|%G`
+/// output, which has a line for each group of nodes with the same configuration
+pub fn add_sinfo_nodes(partitions: &mut [PartitionLimits], output: &str) {
+    for fields in split_status_lines(output, 4) {
+        // the default partition is marked with *
+        let name = fields[0].trim_end_matches('*');
+        let partition = match partitions
+            .iter_mut()
+            .find(|partition| partition.name == name)
+        {
+            Some(partition) => partition,
+            None => continue,
+        };
+        // sinfo appends + when the nodes of a group differ
+        let number = |value: &str| value.trim().trim_end_matches('+').parse::<u64>().ok();
+        if let Some(cpus) = number(&fields[1]) {
+            partition.node_cpus = partition.node_cpus.max(Some(cpus as u32));
+        }
+        if let Some(memory) = number(&fields[2]) {
+            partition.node_memory = partition.node_memory.max(Some(memory));
+        }
+        let mut gpus = 0;
+        for (kind, count) in parse_gpu_gres(&fields[3]) {
+            gpus += count;
+            if let Some(kind) = kind {
+                if !partition.gpu_types.contains(&kind) {
+                    partition.gpu_types.push(kind);
+                }
+            }
+        }
+        partition.node_gpus = partition.node_gpus.max(gpus);
+    }
+}
+
+/// The GPUs in a node's generic resources, e.g. `gpu:a100:4(S:0-1),gpu:v100:2`, by type
+fn parse_gpu_gres(gres: &str) -> Vec<(Option<String>, u32)> {
+    // drop the socket bindings, which can contain commas
+    let mut resources = String::new();
+    let mut depth = 0;
+    for c in gres.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ if depth == 0 => resources.push(c),
+            _ => {}
+        }
+    }
+    resources
+        .split(',')
+        .filter_map(|resource| {
+            let fields: Vec<&str> = resource.trim().split(':').collect();
+            match fields.as_slice() {
+                ["gpu"] => Some((None, 1)),
+                ["gpu", count] => match count.parse() {
+                    Ok(count) => Some((None, count)),
+                    // a type without a count, which is one GPU
+                    Err(_) => Some((Some(count.to_string()), 1)),
+                },
+                ["gpu", kind, count] => Some((Some(kind.to_string()), count.parse().ok()?)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// A memory request such as `4000M` or `5K` in megabytes
+fn memory_megabytes(memory: &str) -> Option<u64> {
+    let memory = memory.trim().to_ascii_uppercase();
+    let memory = memory.trim_end_matches('B');
+    let (number, multiplier) = match memory.char_indices().last()? {
+        (index, 'K') => (&memory[..index], 0),
+        (index, 'M') => (&memory[..index], 1),
+        (index, 'G') => (&memory[..index], 1024),
+        (index, 'T') => (&memory[..index], 1024 * 1024),
+        _ => (memory, 1),
+    };
+    let number: u64 = number.parse().ok()?;
+    if multiplier == 0 {
+        Some((number + 1023) / 1024)
+    } else {
+        Some(number * multiplier)
+    }
+}
+
+/// Check a job against the limits of the partition it would run in, named with `--partition` or
+/// otherwise the default. For a list of partitions, the job has to fit in one of them. A job is
+/// not checked if no partitions are known, or if it is for another cluster.
+pub fn check_partition_limits(
+    plan: &SubmissionPlan,
+    partitions: &[PartitionLimits],
+) -> Result<(), SubmissionError> {
+    let arguments = &plan.slurm.arguments;
+    if partitions.is_empty() || CLUSTERS.is_set_in(arguments) {
+        return Ok(());
+    }
+    let requested = plan
+        .job
+        .resources
+        .partition
+        .clone()
+        .or_else(|| PARTITION.value_in(arguments));
+    let names: Vec<String> = match requested {
+        Some(names) => names.split(',').map(str::to_string).collect(),
+        None => match partitions.iter().find(|partition| partition.default) {
+            Some(partition) => vec![partition.name.clone()],
+            None => return Ok(()),
+        },
+    };
+
+    let mut first_error = None;
+    for name in &names {
+        let partition = match partitions.iter().find(|partition| &partition.name == name) {
+            Some(partition) => partition,
+            None => {
+                let known: Vec<&str> = partitions
+                    .iter()
+                    .map(|partition| partition.name.as_str())
+                    .collect();
+                return Err(SubmissionError::validation(format!(
+                    "Partition {name} does not exist; the partitions are {}",
+                    known.join(", ")
+                )));
+            }
+        };
+        match check_job_fits(plan, partition) {
+            Ok(()) => return Ok(()),
+            Err(error) => {
+                first_error.get_or_insert(error);
+            }
+        }
+    }
+    first_error.map_or(Ok(()), |error| Err(SubmissionError::validation(error)))
+}
+
+/// Choose the partition for a job, and any retries of it, as the partition with the shortest
+/// time limit that they fit in, preferring the default partition among those with the same limit.
+/// Partitions that are down are skipped. Returns the partition and why it was chosen.
+pub fn choose_partition(
+    plans: &[SubmissionPlan],
+    partitions: &[PartitionLimits],
+) -> Result<(String, String), SubmissionError> {
+    if plans
+        .iter()
+        .any(|plan| PARTITION.is_set_in(&plan.slurm.arguments))
+    {
+        return Err(SubmissionError::validation(
+            "--auto-partition cannot be used with a --partition passed to sbatch",
+        ));
+    }
+    if partitions.is_empty() {
+        return Err(SubmissionError::validation(
+            "No partitions were found to choose from",
+        ));
+    }
+
+    let mut rejections = Vec::new();
+    let mut candidates = Vec::new();
+    for partition in partitions {
+        if partition.down {
+            rejections.push(format!("Partition {} is down", partition.name));
+            continue;
+        }
+        match plans
+            .iter()
+            .try_for_each(|plan| check_job_fits(plan, partition))
+        {
+            Ok(()) => candidates.push(partition),
+            Err(error) => rejections.push(error),
+        }
+    }
+
+    let time_limit = |partition: &PartitionLimits| {
+        partition
+            .max_time
+            .as_deref()
+            .and_then(slurm_time_seconds)
+            .unwrap_or(u64::MAX)
+    };
+    let chosen = candidates
+        .iter()
+        .min_by_key(|partition| (time_limit(partition), !partition.default))
+        .ok_or_else(|| {
+            SubmissionError::validation(format!(
+                "The job does not fit in any partition. {}",
+                rejections.join(". ")
+            ))
+        })?;
+    let limit = chosen.max_time.as_deref().unwrap_or("none");
+    let reason = if candidates.len() == 1 {
+        format!("it is the only partition the job fits in, with a time limit of {limit}")
+    } else {
+        format!(
+            "it has the shortest time limit, {limit}, of the {} partitions the job fits in",
+            candidates.len()
+        )
+    };
+    Ok((chosen.name.clone(), reason))
+}
+
+fn check_job_fits(plan: &SubmissionPlan, partition: &PartitionLimits) -> Result<(), String> {
+    let job = &plan.job;
+    let arguments = &plan.slurm.arguments;
+    let name = &partition.name;
+    let count = |value: Option<u32>, option: &ResourceOption| {
+        value.or_else(|| {
+            option
+                .value_in(arguments)
+                .and_then(|value| value.parse().ok())
+        })
+    };
+
+    if let (Some(max_time), Some(time)) = (&partition.max_time, slurm_time_seconds(&job.time)) {
+        if slurm_time_seconds(max_time).map_or(false, |max| time > max) {
+            return Err(format!(
+                "Partition {name} allows at most {max_time}, but a time limit of {} was requested",
+                job.time
+            ));
+        }
+    }
+
+    // 0 requests all the memory of a node
+    if let Some(memory) = memory_megabytes(&job.memory).filter(|memory| *memory > 0) {
+        if let Some(max) = partition.max_memory_per_node.filter(|max| memory > *max) {
+            return Err(format!(
+                "Partition {name} allows at most {max}M of memory per node, but {} was requested",
+                job.memory
+            ));
+        }
+        if let Some(max) = partition.node_memory.filter(|max| memory > *max) {
+            return Err(format!(
+                "The largest node in partition {name} has {max}M of memory, but {} was requested",
+                job.memory
+            ));
+        }
+    }
+
+    let nodes = count(job.resources.nodes, &NODES);
+    if let (Some(nodes), Some(max)) = (nodes, partition.max_nodes) {
+        if nodes > max {
+            return Err(format!(
+                "Partition {name} allows at most {max} nodes, but {nodes} were requested"
+            ));
+        }
+    }
+
+    let cpus = count(job.resources.cpus_per_task, &CPUS_PER_TASK).unwrap_or(1)
+        * count(job.resources.ntasks_per_node, &NTASKS_PER_NODE).unwrap_or(1);
+    if let Some(max) = partition.max_cpus_per_node.filter(|max| cpus > *max) {
+        return Err(format!(
+            "Partition {name} allows at most {max} CPUs per node, but {cpus} were requested"
+        ));
+    }
+    if let Some(max) = partition.node_cpus.filter(|max| cpus > *max) {
+        return Err(format!(
+            "The largest node in partition {name} has {max} CPUs, but {cpus} were requested per node"
+        ));
+    }
+
+    let gres = job
+        .resources
+        .gres
+        .clone()
+        .or_else(|| GRES.value_in(arguments));
+    for (kind, count) in parse_gpu_gres(gres.as_deref().unwrap_or_default()) {
+        if partition.node_gpus == 0 {
+            return Err(format!(
+                "Partition {name} has no GPUs, but --gres={} was requested",
+                gres.unwrap_or_default()
+            ));
+        }
+        if let Some(kind) = kind {
+            if !partition.gpu_types.is_empty() && !partition.gpu_types.contains(&kind) {
+                return Err(format!(
+                    "Partition {name} has no {kind} GPUs; its GPUs are {}",
+                    partition.gpu_types.join(", ")
+                ));
+            }
+        }
+        if count > partition.node_gpus {
+            return Err(format!(
+                "Partition {name} has at most {} GPUs per node, but {count} were requested per node",
+                partition.node_gpus
+            ));
+        }
+    }
+
+    let gpus = job
+        .resources
+        .gpus
+        .clone()
+        .or_else(|| GPUS.value_in(arguments));
+    if let Some(gpus) = gpus {
+        let (kind, number) = match gpus.rsplit_once(':') {
+            Some((kind, number)) => (Some(kind), number),
+            None => (None, gpus.as_str()),
+        };
+        let number: u32 = number.parse().unwrap_or(0);
+        if partition.node_gpus == 0 {
+            return Err(format!(
+                "Partition {name} has no GPUs, but --gpus={gpus} was requested"
+            ));
+        }
+        if let Some(kind) = kind {
+            if !partition.gpu_types.is_empty() && !partition.gpu_types.iter().any(|t| t == kind) {
+                return Err(format!(
+                    "Partition {name} has no {kind} GPUs; its GPUs are {}",
+                    partition.gpu_types.join(", ")
+                ));
+            }
+        }
+        // without --nodes, GPUs can be spread over as many nodes as Slurm needs
+        if let Some(nodes) = nodes {
+            let max = partition.node_gpus * nodes;
+            if number > max {
+                return Err(format!(
+                    "Partition {name} has at most {} GPUs per node, so {number} GPUs do not fit on {nodes} node{}",
+                    partition.node_gpus,
+                    if nodes == 1 { "" } else { "s" }
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
 pub fn prepare_machine_submission(
     plan: &SubmissionPlan,
 ) -> Result<SubmissionPlan, SubmissionError> {
@@ -399,15 +2126,480 @@ pub fn run_sbatch(plan: &SubmissionPlan) -> Result<SbatchOutput, SubmissionError
             })?;
     }
 
-    let output = child.wait_with_output().map_err(|error| {
-        SubmissionError::process(format!("Failed to execute sbatch process: {error}"), None)
-    })?;
+    let output = child.wait_with_output().map_err(|error| {
+        SubmissionError::process(format!("Failed to execute sbatch process: {error}"), None)
+    })?;
+
+    Ok(SbatchOutput {
+        status: output.status,
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    })
+}
+
+/// Resolve a job given as an ID or name. IDs, including array tasks such as `123_4`, are used
+/// as given. A name refers to the current user's queued and running jobs with that name or, if
+/// there are none, to the most recent such job in the accounting records of the last week.
+pub fn resolve_dependency(kind: DependencyKind, job: &str) -> Result<Dependency, SubmissionError> {
+    let job = job.trim();
+    if job.is_empty() {
+        return Err(SubmissionError::validation(
+            "Job dependencies must be job IDs or names, not empty",
+        ));
+    }
+
+    let job_ids = if is_job_id(job) {
+        vec![job.to_string()]
+    } else {
+        find_jobs_by_name(job)?
+    };
+    Ok(Dependency {
+        kind,
+        job: job.to_string(),
+        job_ids,
+    })
+}
+
+fn is_job_id(job: &str) -> bool {
+    let job_id = Regex::new(r"^\d+(_\d+)?$").expect("valid regex");
+    job_id.is_match(job)
+}
+
+/// How far back the accounting records are searched for jobs given by name
+static ACCOUNTING_WINDOW: &str = "--starttime=now-7days";
+
+fn find_jobs_by_name(name: &str) -> Result<Vec<String>, SubmissionError> {
+    let name_argument = format!("--name={name}");
+    let queued = run_query(
+        "squeue",
+        &["--me", "--noheader", "--format=%F", &name_argument],
+    )?;
+    let job_ids = parse_job_ids(&queued);
+    if !job_ids.is_empty() {
+        return Ok(job_ids);
+    }
+
+    let finished = run_query(
+        "sacct",
+        &[
+            "--noheader",
+            "--parsable2",
+            "--allocations",
+            ACCOUNTING_WINDOW,
+            "--format=JobID",
+            &name_argument,
+        ],
+    )?;
+    match parse_job_ids(&finished).pop() {
+        Some(job_id) => Ok(vec![job_id]),
+        None => Err(SubmissionError::validation(format!(
+            "No job named {name} was found for the current user"
+        ))),
+    }
+}
+
+/// The distinct job IDs, one per line, with any array task suffix removed
+fn parse_job_ids(output: &str) -> Vec<String> {
+    let mut job_ids: Vec<String> = Vec::new();
+    for line in output.lines() {
+        let job_id = line.trim().split('_').next().unwrap_or_default();
+        if !job_id.is_empty() && !job_ids.iter().any(|seen| seen == job_id) {
+            job_ids.push(job_id.to_string());
+        }
+    }
+    job_ids
+}
+
+/// The status of a job, as reported by squeue while it is queued or running and by sacct once
+/// it has finished
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JobStatus {
+    pub job_id: String,
+    pub name: String,
+    pub state: String,
+    pub elapsed: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nodes: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// The outcome of cancelling jobs
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct CancelResult {
+    pub cancelled: Vec<String>,
+    pub failed: Vec<CancelFailure>,
+}
+
+/// A job that could not be cancelled, and why
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CancelFailure {
+    pub job_id: String,
+    #[serde(flatten)]
+    pub error: JsonError,
+}
+
+static SQUEUE_STATUS_FORMAT: &str = "--format=%i|%j|%T|%M|%N|%r";
+static SACCT_STATUS_FORMAT: &str = "--format=JobID,JobName,State,Elapsed,NodeList,ExitCode";
+
+/// Look up the status of jobs given as IDs or names. An ID includes the tasks of an array job
+/// and a name matches the current user's jobs with that name from the last week.
+pub fn query_job_statuses(jobs: &[String]) -> Result<Vec<JobStatus>, SubmissionError> {
+    let queued = parse_squeue_statuses(&run_query(
+        "squeue",
+        &["--me", "--noheader", SQUEUE_STATUS_FORMAT],
+    )?);
+
+    let mut statuses: Vec<JobStatus> = Vec::new();
+    for job in jobs {
+        let job = job.trim();
+        if job.is_empty() {
+            return Err(SubmissionError::validation(
+                "Jobs must be given as job IDs or names, not empty",
+            ));
+        }
+
+        let by_id = is_job_id(job);
+        let task_prefix = format!("{job}_");
+        let mut found: Vec<JobStatus> = queued
+            .iter()
+            .filter(|status| {
+                if by_id {
+                    status.job_id == job || status.job_id.starts_with(&task_prefix)
+                } else {
+                    status.name == job
+                }
+            })
+            .cloned()
+            .collect();
+
+        let selector = if by_id {
+            format!("--jobs={job}")
+        } else {
+            format!("--name={job}")
+        };
+        let mut arguments = vec![
+            "--noheader",
+            "--parsable2",
+            "--allocations",
+            SACCT_STATUS_FORMAT,
+            &selector,
+        ];
+        if !by_id {
+            arguments.push(ACCOUNTING_WINDOW);
+        }
+        // squeue is enough for queued and running jobs on clusters without accounting
+        match run_query("sacct", &arguments) {
+            Ok(finished) => {
+                for status in parse_sacct_statuses(&finished) {
+                    if !found.iter().any(|seen| seen.job_id == status.job_id) {
+                        found.push(status);
+                    }
+                }
+            }
+            Err(error) if found.is_empty() => return Err(error),
+            Err(_) => {}
+        }
+
+        if found.is_empty() {
+            return Err(SubmissionError::validation(if by_id {
+                format!("No job with ID {job} was found")
+            } else {
+                format!("No job named {job} was found for the current user")
+            }));
+        }
+        for status in found {
+            if !statuses.iter().any(|seen| seen.job_id == status.job_id) {
+                statuses.push(status);
+            }
+        }
+    }
+    Ok(statuses)
+}
+
+/// The current user's queued and running jobs that match any of the given job IDs or names.
+/// With `regex`, names are regular expressions that must match the whole job name. Every job ID
+/// or name must match at least one job.
+pub fn find_queued_jobs(jobs: &[String], regex: bool) -> Result<Vec<JobStatus>, SubmissionError> {
+    let queued = parse_squeue_statuses(&run_query(
+        "squeue",
+        &["--me", "--noheader", SQUEUE_STATUS_FORMAT],
+    )?);
+
+    let mut matched: Vec<JobStatus> = Vec::new();
+    for job in jobs {
+        let job = job.trim();
+        if job.is_empty() {
+            return Err(SubmissionError::validation(
+                "Jobs must be given as job IDs or names, not empty",
+            ));
+        }
+        let pattern = if regex {
+            let pattern = Regex::new(&format!("^(?:{job})$")).map_err(|error| {
+                SubmissionError::validation(format!("Invalid job name pattern {job}: {error}"))
+            })?;
+            Some(pattern)
+        } else {
+            None
+        };
+
+        let task_prefix = format!("{job}_");
+        let mut found = false;
+        for status in &queued {
+            let is_match = match &pattern {
+                Some(pattern) => pattern.is_match(&status.name),
+                None if is_job_id(job) => {
+                    status.job_id == job || status.job_id.starts_with(&task_prefix)
+                }
+                None => status.name == job,
+            };
+            if is_match {
+                found = true;
+                if !matched.iter().any(|seen| seen.job_id == status.job_id) {
+                    matched.push(status.clone());
+                }
+            }
+        }
+        if !found {
+            return Err(SubmissionError::validation(format!(
+                "No queued or running job of the current user matches {job}"
+            )));
+        }
+    }
+    Ok(matched)
+}
+
+/// Cancel each job with scancel, carrying on past any that fail
+pub fn cancel_jobs(job_ids: &[String]) -> CancelResult {
+    let mut result = CancelResult::default();
+    for job_id in job_ids {
+        match run_query("scancel", &[job_id]) {
+            Ok(_) => result.cancelled.push(job_id.clone()),
+            Err(error) => result.failed.push(CancelFailure {
+                job_id: job_id.clone(),
+                error: JsonError {
+                    kind: error.kind,
+                    message: error.message,
+                    exit_code: error.exit_code,
+                    stderr: error.stderr,
+                },
+            }),
+        }
+    }
+    result
+}
+
+/// Slurm job states in which a job has finished
+const FINISHED_STATES: [&str; 10] = [
+    "BOOT_FAIL",
+    "CANCELLED",
+    "COMPLETED",
+    "DEADLINE",
+    "FAILED",
+    "NODE_FAIL",
+    "OUT_OF_MEMORY",
+    "PREEMPTED",
+    "REVOKED",
+    "TIMEOUT",
+];
+
+/// The longest time between checks on a job that is being waited for
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+pub fn is_finished_state(state: &str) -> bool {
+    FINISHED_STATES.contains(&state)
+}
+
+/// How many checks in a row a job can be missing from both sacct and squeue before waiting for
+/// it fails, as it does when accounting is disabled and the job has left the queue
+const MAX_MISSING_CHECKS: u32 = 3;
+
+/// Wait for a job to finish by polling sacct, starting every second and backing off to every
+/// minute. `on_state` is called with the job's state whenever it changes. While sacct has no
+/// record of the job, its state is taken from squeue.
+pub fn wait_for_job(
+    job_id: &str,
+    mut on_state: impl FnMut(&JobStatus),
+) -> Result<JobStatus, SubmissionError> {
+    let selector = format!("--jobs={job_id}");
+    let mut interval = Duration::from_secs(1);
+    let mut last_state = String::new();
+    let mut missing = 0;
+    loop {
+        let output = run_query(
+            "sacct",
+            &[
+                "--noheader",
+                "--parsable2",
+                "--allocations",
+                SACCT_STATUS_FORMAT,
+                &selector,
+            ],
+        )?;
+        // a job that was only just submitted may not be in the accounting records yet
+        let (status, recorded) = match summarise_statuses(job_id, parse_sacct_statuses(&output)) {
+            Some(status) => (Some(status), true),
+            None => (queued_status(job_id)?, false),
+        };
+        match status {
+            Some(status) => {
+                missing = 0;
+                if status.state != last_state {
+                    on_state(&status);
+                    last_state = status.state.clone();
+                }
+                // only the accounting records have the exit code of a finished job
+                if recorded && is_finished_state(&status.state) {
+                    return Ok(status);
+                }
+            }
+            None => {
+                missing += 1;
+                if missing >= MAX_MISSING_CHECKS {
+                    return Err(SubmissionError::output(
+                        format!(
+                            "Slurm accounting has no record of job {job_id} and it is not in the queue, so its outcome is unknown. Check that accounting (sacct) is enabled"
+                        ),
+                        None,
+                    ));
+                }
+            }
+        }
+        std::thread::sleep(interval);
+        interval = (interval * 2).min(MAX_POLL_INTERVAL);
+    }
+}
+
+/// The status of a job, or its array tasks, in the current user's queue, if it is there
+fn queued_status(job_id: &str) -> Result<Option<JobStatus>, SubmissionError> {
+    let queued = parse_squeue_statuses(&run_query(
+        "squeue",
+        &["--me", "--noheader", SQUEUE_STATUS_FORMAT],
+    )?);
+    let task_prefix = format!("{job_id}_");
+    let tasks = queued
+        .into_iter()
+        .filter(|status| status.job_id == job_id || status.job_id.starts_with(&task_prefix))
+        .collect();
+    Ok(summarise_statuses(job_id, tasks))
+}
+
+/// The status of a job as a whole. An array job is represented by an unfinished task while
+/// there is one, and otherwise by a task that did not complete, if any.
+fn summarise_statuses(job_id: &str, statuses: Vec<JobStatus>) -> Option<JobStatus> {
+    let representative = statuses
+        .iter()
+        .find(|status| !is_finished_state(&status.state))
+        .or_else(|| statuses.iter().find(|status| status.state != "COMPLETED"))
+        .or_else(|| statuses.first())?;
+    let mut status = representative.clone();
+    status.job_id = job_id.to_string();
+    Some(status)
+}
+
+/// Parse `squeue --format=%i|%j|%T|%M|%N|%r` output
+pub fn parse_squeue_statuses(output: &str) -> Vec<JobStatus> {
+    split_status_lines(output, 6)
+        .into_iter()
+        .map(|fields| JobStatus {
+            job_id: fields[0].clone(),
+            name: fields[1].clone(),
+            state: fields[2].clone(),
+            elapsed: fields[3].clone(),
+            nodes: non_empty_trimmed(&fields[4]),
+            exit_code: None,
+            signal: None,
+            reason: non_empty_trimmed(&fields[5]).filter(|reason| reason != "None"),
+        })
+        .collect()
+}
 
-    Ok(SbatchOutput {
+/// Parse `sacct --parsable2 --format=JobID,JobName,State,Elapsed,NodeList,ExitCode` output
+pub fn parse_sacct_statuses(output: &str) -> Vec<JobStatus> {
+    split_status_lines(output, 6)
+        .into_iter()
+        .map(|fields| {
+            let mut exit = fields[5].split(':').map(|value| value.parse::<i32>().ok());
+            JobStatus {
+                job_id: fields[0].clone(),
+                name: fields[1].clone(),
+                // e.g. CANCELLED by 1234
+                state: fields[2]
+                    .split_whitespace()
+                    .next()
+                    .unwrap_or_default()
+                    .to_string(),
+                elapsed: fields[3].clone(),
+                nodes: non_empty_trimmed(&fields[4]).filter(|nodes| nodes != "None assigned"),
+                exit_code: exit.next().flatten(),
+                signal: exit.next().flatten(),
+                reason: None,
+            }
+        })
+        .collect()
+}
+
+/// Split `|`-separated lines into `count` fields. The job name is the second field, and takes
+/// any extra separators, as it is the only field that can contain one.
+fn split_status_lines(output: &str, count: usize) -> Vec<Vec<String>> {
+    output
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split('|').collect();
+            if fields.len() < count {
+                return None;
+            }
+            let name_end = fields.len() - (count - 2);
+            let mut split = vec![fields[0].trim().to_string(), fields[1..name_end].join("|")];
+            split.extend(
+                fields[name_end..]
+                    .iter()
+                    .map(|field| field.trim().to_string()),
+            );
+            Some(split)
+        })
+        .collect()
+}
+
+/// Run a Slurm query command, returning its stdout
+fn run_query(program: &str, arguments: &[&str]) -> Result<String, SubmissionError> {
+    let output = Command::new(program)
+        .args(arguments)
+        .output()
+        .map_err(|error| {
+            SubmissionError::process(format!("Failed to execute {program}: {error}"), None)
+        })?;
+    let output = SbatchOutput {
         status: output.status,
         stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
         stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
-    })
+    };
+    match classify_query_failure(program, &output) {
+        Some(error) => Err(error),
+        None => Ok(output.stdout),
+    }
+}
+
+pub fn classify_query_failure(program: &str, output: &SbatchOutput) -> Option<SubmissionError> {
+    let stderr = non_empty_trimmed(&output.stderr);
+    match output.status.code() {
+        Some(0) => None,
+        Some(exit_code) => Some(SubmissionError {
+            kind: "slurm".to_string(),
+            message: format!("{program} failed with exit code {exit_code}"),
+            exit_code: Some(exit_code),
+            stderr,
+        }),
+        None => Some(SubmissionError::process(
+            format!("{program} process terminated by signal"),
+            stderr,
+        )),
+    }
 }
 
 pub fn submit_sbatch(plan: &SubmissionPlan) -> Result<SubmissionResult, SubmissionError> {
@@ -496,6 +2688,18 @@ pub fn parse_submission_output(
     })
 }
 
+/// The job ID and cluster from sbatch's default output, e.g.
+/// `Submitted batch job 123 on cluster alpha`
+pub fn parse_submitted_message(stdout: &str) -> Option<SubmissionResult> {
+    let message =
+        Regex::new(r"Submitted batch job (\d+)(?: on cluster (\S+))?").expect("valid regex");
+    let captures = message.captures(stdout)?;
+    Some(SubmissionResult {
+        job_id: captures[1].to_string(),
+        cluster: captures.get(2).map(|cluster| cluster.as_str().to_string()),
+    })
+}
+
 fn non_empty_trimmed(value: &str) -> Option<String> {
     let value = value.trim();
     (!value.is_empty()).then(|| value.to_string())
@@ -659,7 +2863,17 @@ mod tests {
         let output = "%x.out";
         let cmd = "python -c 'print(1+1)'";
 
-        let actual = make_submission_script(shebang, set, name, memory, time, error, output, cmd);
+        let actual = make_submission_script(
+            shebang,
+            set,
+            name,
+            memory,
+            time,
+            error,
+            output,
+            &Resources::default(),
+            cmd,
+        );
         let expected = format!(
             r#"{shebang}
 #SBATCH --job-name={name}
@@ -686,7 +2900,17 @@ set -{set}
         let output = "%x.out";
         let cmd = "python -c 'print(1+1)'";
 
-        let actual = make_submission_script(shebang, set, name, memory, time, error, output, cmd);
+        let actual = make_submission_script(
+            shebang,
+            set,
+            name,
+            memory,
+            time,
+            error,
+            output,
+            &Resources::default(),
+            cmd,
+        );
         let expected = format!(
             r#"{shebang}
 #SBATCH --job-name={name}
@@ -713,7 +2937,17 @@ set -{set}
         let output = "%x.out";
         let cmd = "python -c 'print(1+1)'";
 
-        let actual = make_submission_script(shebang, set, name, memory, time, error, output, cmd);
+        let actual = make_submission_script(
+            shebang,
+            set,
+            name,
+            memory,
+            time,
+            error,
+            output,
+            &Resources::default(),
+            cmd,
+        );
         let expected = format!(
             r#"{shebang}
 #SBATCH --job-name={name}
@@ -727,4 +2961,1121 @@ set -{set}
         );
         assert_eq!(actual, expected)
     }
+
+    #[test]
+    fn test_make_submission_script_with_resources() {
+        let resources = Resources {
+            partition: Some("gpu".to_string()),
+            account: None,
+            qos: Some("normal".to_string()),
+            cpus_per_task: Some(8),
+            ntasks: None,
+            nodes: Some(2),
+            ntasks_per_node: Some(4),
+            gpus: None,
+            gres: Some("gpu:a100:2".to_string()),
+            array: None,
+            dependency: None,
+        };
+
+        let actual = make_submission_script(
+            "#/bin/bash",
+            "eux",
+            "job",
+            "1M",
+            "5:56:00",
+            "%x.err",
+            "%x.out",
+            &resources,
+            "hostname",
+        );
+        let expected = r#"#/bin/bash
+#SBATCH --job-name=job
+#SBATCH --mem=1M
+#SBATCH --time=5:56:00
+#SBATCH --error=%x.err
+#SBATCH --output=%x.out
+#SBATCH --partition=gpu
+#SBATCH --qos=normal
+#SBATCH --cpus-per-task=8
+#SBATCH --nodes=2
+#SBATCH --ntasks-per-node=4
+#SBATCH --gres=gpu:a100:2
+set -eux
+
+hostname
+"#;
+        assert_eq!(actual, expected)
+    }
+
+    #[test]
+    fn test_resources_conflict_with_remainder() {
+        let resources = Resources {
+            cpus_per_task: Some(8),
+            ntasks: Some(2),
+            ..Resources::default()
+        };
+
+        for remainder in [
+            vec!["-c", "4"],
+            vec!["-c4"],
+            vec!["--cpus-per-task=4"],
+            vec!["--partition=short", "--cpus-per-task", "4"],
+            vec!["-n", "2"],
+            vec!["--ntasks=2"],
+        ] {
+            let remainder: Vec<String> = remainder.into_iter().map(String::from).collect();
+            let error = resources.check_conflicts(&remainder).unwrap_err();
+            assert_eq!(error.kind, "validation");
+        }
+    }
+
+    #[test]
+    fn test_gpu_resources_conflict_with_remainder() {
+        let resources = Resources {
+            gpus: Some("a100:2".to_string()),
+            gres: Some("shard:4".to_string()),
+            ..Resources::default()
+        };
+
+        for remainder in [vec!["-G", "2"], vec!["--gpus=2"], vec!["--gres", "gpu:1"]] {
+            let remainder: Vec<String> = remainder.into_iter().map(String::from).collect();
+            assert!(resources.check_conflicts(&remainder).is_err());
+        }
+
+        let remainder: Vec<String> = ["--gpus-per-task=1", "--gres-flags=enforce-binding"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert!(resources.check_conflicts(&remainder).is_ok());
+    }
+
+    #[test]
+    fn test_scheduling_options_conflict_with_remainder() {
+        let resources = Resources {
+            partition: Some("short".to_string()),
+            account: Some("lab".to_string()),
+            qos: Some("normal".to_string()),
+            ..Resources::default()
+        };
+
+        for remainder in [
+            vec!["-p", "long"],
+            vec!["--partition=long"],
+            vec!["-A", "other"],
+            vec!["--account", "other"],
+            vec!["-q", "high"],
+            vec!["--qos=high"],
+        ] {
+            let remainder: Vec<String> = remainder.into_iter().map(String::from).collect();
+            assert!(resources.check_conflicts(&remainder).is_err());
+        }
+    }
+
+    #[test]
+    fn test_resources_without_conflict() {
+        let resources = Resources {
+            ntasks: Some(2),
+            ..Resources::default()
+        };
+        let remainder: Vec<String> = ["--ntasks-per-node=2", "-N", "1", "--nodelist=a"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        assert!(resources.check_conflicts(&remainder).is_ok());
+        assert!(Resources::default().check_conflicts(&remainder).is_ok());
+    }
+
+    #[test]
+    fn test_parse_array_commands() {
+        let contents = "# samples\necho a\n\n  echo b  \r\n#echo c\necho 'd # e'\n";
+
+        let actual = parse_array_commands(contents);
+        let expected = vec!["echo a", "echo b", "echo 'd # e'"];
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_array_indices() {
+        assert_eq!(array_indices(3, None), "1-3");
+        assert_eq!(array_indices(100, Some(10)), "1-100%10");
+    }
+
+    #[test]
+    fn test_make_array_command() {
+        let tasks: Vec<ArrayTask> = ["echo a", "echo b | wc -c"]
+            .into_iter()
+            .map(|command| ArrayTask {
+                command: command.to_string(),
+                variables: Vec::new(),
+            })
+            .collect();
+
+        let actual = make_array_command(&tasks);
+        let expected = r#"case "${SLURM_ARRAY_TASK_ID:-}" in
+1)
+    echo a
+    ;;
+2)
+    echo b | wc -c
+    ;;
+*)
+    echo "No command for array task ${SLURM_ARRAY_TASK_ID:-}" >&2
+    exit 1
+    ;;
+esac"#;
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_array_conflicts_with_remainder() {
+        let resources = Resources {
+            array: Some("1-3".to_string()),
+            ..Resources::default()
+        };
+
+        for remainder in [vec!["-a", "1-5"], vec!["--array=1-5"]] {
+            let remainder: Vec<String> = remainder.into_iter().map(String::from).collect();
+            assert!(resources.check_conflicts(&remainder).is_err());
+        }
+        assert!(resources
+            .check_conflicts(&["--account=lab".to_string()])
+            .is_ok());
+    }
+
+    #[test]
+    fn test_defer_to_passthrough() {
+        let mut resources = Resources {
+            partition: Some("short".to_string()),
+            account: Some("lab".to_string()),
+            cpus_per_task: Some(4),
+            ..Resources::default()
+        };
+        let remainder = vec!["-p".to_string(), "long".to_string(), "-c8".to_string()];
+
+        resources.defer_to_passthrough(&["partition", "account", "mem"], &remainder);
+
+        assert_eq!(resources.partition, None);
+        assert_eq!(resources.account, Some("lab".to_string()));
+        assert_eq!(resources.cpus_per_task, Some(4));
+        assert!(resources.check_conflicts(&remainder).is_err());
+    }
+
+    #[test]
+    fn test_sample_sheet_expands_placeholders() {
+        let sheet =
+            SampleSheet::parse("sample\treads\nA1\ta1.fq\n# skipped\nB2\tb 2.fq\n", b'\t').unwrap();
+
+        let actual = sheet
+            .expand("minimap2 ref.fa {reads} > {sample}.paf # ${HOME} {sample}")
+            .unwrap();
+
+        assert_eq!(actual.len(), 2);
+        assert_eq!(
+            actual[0].command,
+            "minimap2 ref.fa a1.fq > A1.paf # ${HOME} A1"
+        );
+        assert_eq!(
+            actual[1].variables,
+            vec![
+                ("sample".to_string(), "B2".to_string()),
+                ("reads".to_string(), "b 2.fq".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sample_sheet_quotes_values_the_shell_would_interpret() {
+        let sheet = SampleSheet::parse("sample\nA 1\nB2;rm -rf x\nit's\n\"\"\n", b'\t').unwrap();
+
+        let actual: Vec<String> = sheet
+            .expand("echo {sample}")
+            .unwrap()
+            .into_iter()
+            .map(|task| task.command)
+            .collect();
+
+        assert_eq!(
+            actual,
+            vec![
+                "echo 'A 1'",
+                "echo 'B2;rm -rf x'",
+                r"echo 'it'\''s'",
+                "echo ''"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sample_sheet_parses_quoted_csv() {
+        let sheet = SampleSheet::parse("id,note\n1,\"a, b\"\n", b',').unwrap();
+        assert_eq!(sheet.rows, vec![vec!["1".to_string(), "a, b".to_string()]]);
+    }
+
+    #[test]
+    fn test_sample_sheet_rejects_unknown_placeholder() {
+        let sheet = SampleSheet::parse("sample\nA1\n", b'\t').unwrap();
+        let error = sheet.expand("echo {sampel}").unwrap_err();
+        assert_eq!(
+            error,
+            "Unknown placeholder {sampel} in the command; the sample sheet columns are sample"
+        );
+    }
+
+    #[test]
+    fn test_sample_sheet_rejects_invalid_tables() {
+        assert!(SampleSheet::parse("sample id\nA1\n", b'\t').is_err());
+        assert!(SampleSheet::parse("a\ta\n1\t2\n", b'\t').is_err());
+        assert!(SampleSheet::parse("a\tb\n1\n", b'\t').is_err());
+        assert_eq!(
+            SampleSheet::parse("a\tb\n", b'\t').unwrap_err(),
+            "it contains no samples"
+        );
+    }
+
+    #[test]
+    fn test_make_array_command_exports_variables() {
+        let tasks = vec![ArrayTask {
+            command: "echo A1".to_string(),
+            variables: vec![
+                ("sample".to_string(), "A1".to_string()),
+                ("note".to_string(), "it's".to_string()),
+            ],
+        }];
+
+        let actual = make_array_command(&tasks);
+
+        assert!(
+            actual.contains("1)\n    export sample='A1' note='it'\\''s'\n    echo A1\n    ;;\n")
+        );
+    }
+
+    #[test]
+    fn test_dependency_specification_groups_by_kind() {
+        let dependency = |kind, job: &str, job_ids: &[&str]| Dependency {
+            kind,
+            job: job.to_string(),
+            job_ids: job_ids.iter().map(|id| id.to_string()).collect(),
+        };
+        let dependencies = vec![
+            dependency(DependencyKind::AfterOk, "123", &["123"]),
+            dependency(DependencyKind::AfterAny, "index", &["130"]),
+            dependency(DependencyKind::AfterOk, "align", &["124", "125", "123"]),
+        ];
+
+        let actual = dependency_specification(&dependencies);
+        let expected = Some("afterok:123:124:125,afterany:130".to_string());
+
+        assert_eq!(actual, expected);
+        assert_eq!(dependency_specification(&[]), None);
+    }
+
+    #[test]
+    fn test_is_job_id() {
+        assert!(is_job_id("123"));
+        assert!(is_job_id("123_4"));
+        assert!(!is_job_id("align"));
+        assert!(!is_job_id("123abc"));
+        assert!(!is_job_id("123_"));
+    }
+
+    #[test]
+    fn test_parse_job_ids() {
+        let actual = parse_job_ids("123\n124_1\n124_[2-5]\n\n123\n");
+        let expected = vec!["123".to_string(), "124".to_string()];
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_dependency_conflicts_with_remainder() {
+        let resources = Resources {
+            dependency: Some("afterok:1".to_string()),
+            ..Resources::default()
+        };
+
+        for remainder in [vec!["-d", "afterany:2"], vec!["--dependency=afterany:2"]] {
+            let remainder: Vec<String> = remainder.into_iter().map(String::from).collect();
+            assert!(resources.check_conflicts(&remainder).is_err());
+        }
+    }
+
+    #[test]
+    fn test_parse_submitted_message() {
+        let actual = parse_submitted_message("Submitted batch job 123 on cluster alpha\n");
+        let expected = Some(SubmissionResult {
+            job_id: "123".to_string(),
+            cluster: Some("alpha".to_string()),
+        });
+        assert_eq!(actual, expected);
+
+        let actual = parse_submitted_message("Submitted batch job 124\n").unwrap();
+        assert_eq!(actual.job_id, "124");
+        assert_eq!(actual.cluster, None);
+
+        assert_eq!(
+            parse_submitted_message("sbatch: Job 125 to start at now"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_replan_submission_renders_changed_resources() {
+        let resources = Resources {
+            partition: Some("short".to_string()),
+            ..Resources::default()
+        };
+        let arguments = vec!["--constraint=avx2".to_string(), "--export=NONE".to_string()];
+        let mut original = make_submission_plan(
+            "#!/bin/sh",
+            "eu",
+            "align",
+            "1000M",
+            "1:0:0",
+            "%x.err",
+            "%x.out",
+            &resources,
+            &Environment::default(),
+            "echo align",
+            &arguments,
+            "ALL",
+            false,
+        );
+        original.job.profile = Some("small".to_string());
+
+        let mut job = original.job.clone();
+        job.memory = "2000M".to_string();
+        job.resources.partition = Some("long".to_string());
+        job.resubmit_of = Some("123".to_string());
+        let actual = replan_submission(&job, &original.slurm.arguments);
+
+        assert_eq!(actual.job, job);
+        assert_eq!(actual.slurm.arguments, original.slurm.arguments);
+        assert_eq!(
+            actual.slurm.script,
+            original
+                .slurm
+                .script
+                .replace("--mem=1000M", "--mem=2000M")
+                .replace("--partition=short", "--partition=long")
+        );
+    }
+
+    #[test]
+    fn test_make_retry_plan() {
+        let original = make_submission_plan(
+            "#!/usr/bin/env bash",
+            "euxo pipefail",
+            "align",
+            "1000M",
+            "1:0:0",
+            "%x.err",
+            "%x.out",
+            &Resources::default(),
+            &Environment::default(),
+            "minimap2 ref.fa reads.fq",
+            &["--constraint=avx2".to_string()],
+            "ALL",
+            false,
+        );
+
+        let actual = make_retry_plan(&original, &["123".to_string()], "2000M", "1:30:0");
+
+        assert_eq!(actual.job.memory, "2000M");
+        assert_eq!(actual.job.time, "1:30:0");
+        assert_eq!(actual.job.resubmit_of.as_deref(), Some("123"));
+        assert_eq!(
+            actual.job.resources.dependency.as_deref(),
+            Some("afternotok:123")
+        );
+        assert_eq!(
+            actual.slurm.arguments,
+            vec![
+                "--constraint=avx2",
+                "--export=ALL",
+                "--kill-on-invalid-dep=yes"
+            ]
+        );
+        let script = &actual.slurm.script;
+        assert!(script.contains("#SBATCH --mem=2000M\n"));
+        assert!(script.contains("#SBATCH --dependency=afternotok:123\n"));
+        assert!(script.contains("sacct --jobs=123 "));
+        assert!(script.ends_with("    ;;\nesac\n\nminimap2 ref.fa reads.fq\n"));
+    }
+
+    #[test]
+    fn test_environment_render_container() {
+        let environment = Environment {
+            container: Some(Container {
+                image: "tools.sif".to_string(),
+                binds: vec!["/data".to_string(), "/logs".to_string()],
+                nv: true,
+            }),
+            ..Environment::default()
+        };
+
+        let actual = environment.render(
+            "#!/usr/bin/env bash",
+            "euxo pipefail",
+            "samtools view in.bam | wc -l",
+            "/logs/%x.out",
+            "%x.err",
+        );
+        let expected = r#"container_runtime=$(command -v apptainer || command -v singularity || echo apptainer)
+"$container_runtime" exec --nv --bind "$PWD" --bind '/logs' --bind '/data' 'tools.sif' bash -c 'set -euxo pipefail; samtools view in.bam | wc -l'"#;
+        assert_eq!(actual, expected);
+
+        let environment = Environment {
+            container: Some(Container {
+                image: "tools.sif".to_string(),
+                binds: Vec::new(),
+                nv: false,
+            }),
+            ..Environment::default()
+        };
+        let actual = environment.render("#!/bin/sh", "e", "echo hi", "%x.out", "%x.err");
+        assert!(actual.ends_with("'tools.sif' sh -c 'set -e; echo hi'"));
+        let actual = environment.render("#!/bin/zsh", "", "echo hi", "%x.out", "%x.err");
+        assert!(actual.ends_with("'tools.sif' zsh -c 'echo hi'"));
+
+        let actual =
+            Environment::default().render("#!/bin/sh", "e", "echo 'hi'", "%x.out", "%x.err");
+        assert_eq!(actual, "echo 'hi'");
+    }
+
+    #[test]
+    fn test_environment_render_conda_env() {
+        let environment = Environment {
+            conda_env: Some("qc tools".to_string()),
+            ..Environment::default()
+        };
+
+        let actual = environment.render(
+            "#!/usr/bin/env bash",
+            "euxo pipefail",
+            "fastqc reads.fq",
+            "%x.out",
+            "%x.err",
+        );
+        let expected = r#"set +ux
+if command -v conda >/dev/null 2>&1; then
+    eval "$(conda shell.bash hook)"
+    conda activate 'qc tools'
+else
+    eval "$(micromamba shell hook --shell bash)"
+    micromamba activate 'qc tools'
+fi
+set -ux
+
+fastqc reads.fq"#;
+        assert_eq!(actual, expected);
+
+        let actual = environment.render("#!/bin/sh", "e", "fastqc reads.fq", "%x.out", "%x.err");
+        assert!(actual.starts_with("if command -v conda"));
+        assert!(actual.contains("conda shell.posix hook"));
+    }
+
+    #[test]
+    fn test_environment_render_modules() {
+        let environment = Environment {
+            modules: vec!["anaconda3/2024.02".to_string(), "cuda".to_string()],
+            conda_env: Some("torch".to_string()),
+            ..Environment::default()
+        };
+
+        let actual = environment.render("#!/bin/bash", "e", "python train.py", "%x.out", "%x.err");
+        assert!(actual.starts_with(
+            "module purge\nmodule load 'anaconda3/2024.02' 'cuda'\nif command -v conda"
+        ));
+        assert!(actual.ends_with("fi\n\npython train.py"));
+    }
+
+    #[test]
+    fn test_module_availability() {
+        let output = "/opt/modulefiles/Core:
+gcc/12.2.0
+gcc/13.1.0(default)
+samtools/1.17
+/opt/modulefiles/Compiler:
+StdEnv
+";
+        let available = parse_module_avail(output);
+        assert_eq!(
+            available,
+            vec!["gcc/12.2.0", "gcc/13.1.0", "samtools/1.17", "StdEnv"]
+        );
+
+        assert!(is_module_available("gcc", &available));
+        assert!(is_module_available("gcc/13.1.0", &available));
+        assert!(is_module_available("StdEnv", &available));
+        assert!(!is_module_available("gcc/14.1.0", &available));
+        assert!(!is_module_available("samtool", &available));
+        assert!(!is_module_available("samtools/1", &available));
+    }
+
+    #[test]
+    fn test_environment_render_venv_and_pixi() {
+        let venv = Environment {
+            venv: Some(".venv/".to_string()),
+            ..Environment::default()
+        };
+        let actual = venv.render("#!/bin/bash", "eu", "python run.py", "%x.out", "%x.err");
+        assert_eq!(
+            actual,
+            "set +u\nsource '.venv/bin/activate'\nset -u\n\npython run.py"
+        );
+
+        let pixi = Environment {
+            pixi: Some("project/pixi.toml".to_string()),
+            ..Environment::default()
+        };
+        let actual = pixi.render("#!/bin/sh", "e", "python run.py", "%x.out", "%x.err");
+        assert_eq!(
+            actual,
+            "eval \"$(pixi shell-hook --shell bash --manifest-path 'project/pixi.toml')\"\n\npython run.py"
+        );
+    }
+
+    #[test]
+    fn test_environment_check_paths() {
+        let dir = std::env::temp_dir().join(format!("ssubmit-venv-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("env/bin")).unwrap();
+        std::fs::write(dir.join("env/bin/activate"), "").unwrap();
+        std::fs::write(dir.join("pixi.toml"), "").unwrap();
+        let chdir = vec!["-D".to_string(), dir.display().to_string()];
+
+        let environment = Environment {
+            venv: Some("env".to_string()),
+            ..Environment::default()
+        };
+        let found = environment.check_paths(&chdir);
+        let missing = environment.check_paths(&[]);
+        let manifest = Environment {
+            pixi: Some("pixi.toml".to_string()),
+            ..Environment::default()
+        }
+        .check_paths(&chdir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(found.is_ok());
+        assert_eq!(
+            missing,
+            Err("env is not a virtual environment, as env/bin/activate does not exist".to_string())
+        );
+        assert!(manifest.is_ok());
+        assert_eq!(
+            Environment {
+                pixi: Some("pixi.toml".to_string()),
+                ..Environment::default()
+            }
+            .check_paths(&[]),
+            Err("Pixi manifest pixi.toml does not exist".to_string())
+        );
+    }
+
+    #[test]
+    fn test_environment_render_prologue_and_epilogue() {
+        let environment = Environment {
+            modules: vec!["cuda".to_string()],
+            prologue: Some("hostname\nnvidia-smi\n".to_string()),
+            epilogue: Some("date".to_string()),
+            ..Environment::default()
+        };
+
+        let actual = environment.render("#!/bin/bash", "e", "python train.py", "%x.out", "%x.err");
+        let expected = r#"ssubmit_epilogue() {
+date
+}
+trap 'exit_code=$?; ssubmit_epilogue || true; exit "$exit_code"' EXIT
+
+module purge
+module load 'cuda'
+
+hostname
+nvidia-smi
+
+python train.py"#;
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_script_shell() {
+        assert_eq!(script_shell("#!/usr/bin/env bash"), "bash");
+        assert_eq!(script_shell("#!/bin/bash -l"), "bash");
+        assert_eq!(script_shell("#!/usr/bin/env -S zsh -f"), "zsh");
+        assert_eq!(script_shell("#!/bin/sh"), "posix");
+        assert_eq!(script_shell("#!/bin/dash"), "posix");
+    }
+
+    #[test]
+    fn test_retry_guard_runs_outside_the_container() {
+        let environment = Environment {
+            container: Some(Container {
+                image: "docker://ubuntu:24.04".to_string(),
+                binds: vec![],
+                nv: false,
+            }),
+            ..Environment::default()
+        };
+        let original = make_submission_plan(
+            "#!/usr/bin/env bash",
+            "euxo pipefail",
+            "align",
+            "1000M",
+            "1:0:0",
+            "%x.err",
+            "%x.out",
+            &Resources::default(),
+            &environment,
+            "echo 'done'",
+            &[],
+            "ALL",
+            false,
+        );
+
+        let actual = make_retry_plan(&original, &["123".to_string()], "2000M", "1:30:0");
+
+        assert_eq!(actual.job.command, "echo 'done'");
+        assert_eq!(actual.job.environment, environment);
+        assert!(actual.slurm.script.ends_with(
+            "esac\n\ncontainer_runtime=$(command -v apptainer || command -v singularity || echo apptainer)\n\"$container_runtime\" exec --bind \"$PWD\" 'docker://ubuntu:24.04' bash -c 'set -euxo pipefail; echo '\\''done'\\'''\n"
+        ));
+    }
+
+    #[test]
+    fn test_container_check_image() {
+        let dir = std::env::temp_dir().join(format!("ssubmit-image-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("tools.sif"), "").unwrap();
+        let container = Container {
+            image: "tools.sif".to_string(),
+            binds: vec![],
+            nv: false,
+        };
+        let chdir = format!("--chdir={}", dir.display());
+
+        let found = container.check_image(&[chdir]);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(found.is_ok());
+        assert_eq!(
+            container.check_image(&[]),
+            Err("Container image tools.sif does not exist".to_string())
+        );
+        let uri = Container {
+            image: "docker://ubuntu:24.04".to_string(),
+            ..container
+        };
+        assert!(uri.check_image(&[]).is_ok());
+    }
+
+    #[test]
+    fn test_slurm_time_seconds() {
+        let cases = [
+            ("90", Some(5400)),
+            ("1:30", Some(90)),
+            ("1:30:0", Some(5400)),
+            ("2-12", Some(216000)),
+            ("2-12:30", Some(217800)),
+            ("1-0:0:1", Some(86401)),
+            ("0", Some(0)),
+            ("1:2:3:4", None),
+            ("1h", None),
+        ];
+        for (time, expected) in cases {
+            assert_eq!(slurm_time_seconds(time), expected, "{time}");
+        }
+    }
+
+    #[test]
+    fn test_summarise_array_statuses() {
+        let statuses = parse_sacct_statuses(
+            "130_1|a|COMPLETED|00:01:00|node1|0:0\n130_2|a|FAILED|00:00:10|node2|3:0\n130_[3-4]|a|PENDING|00:00:00|None assigned|0:0\n",
+        );
+
+        let running = summarise_statuses("130", statuses.clone()).unwrap();
+        assert_eq!(running.job_id, "130");
+        assert_eq!(running.state, "PENDING");
+
+        let finished = summarise_statuses("130", statuses[..2].to_vec()).unwrap();
+        assert_eq!(finished.state, "FAILED");
+        assert_eq!(finished.exit_code, Some(3));
+
+        assert!(summarise_statuses("130", vec![]).is_none());
+    }
+
+    #[test]
+    fn test_parse_squeue_statuses() {
+        let actual = parse_squeue_statuses(
+            "123|align|RUNNING|1:02|node1|None\n124_[1-3]|a|b|PENDING|0:00||Priority\n",
+        );
+        let expected = vec![
+            JobStatus {
+                job_id: "123".to_string(),
+                name: "align".to_string(),
+                state: "RUNNING".to_string(),
+                elapsed: "1:02".to_string(),
+                nodes: Some("node1".to_string()),
+                exit_code: None,
+                signal: None,
+                reason: None,
+            },
+            JobStatus {
+                job_id: "124_[1-3]".to_string(),
+                name: "a|b".to_string(),
+                state: "PENDING".to_string(),
+                elapsed: "0:00".to_string(),
+                nodes: None,
+                exit_code: None,
+                signal: None,
+                reason: Some("Priority".to_string()),
+            },
+        ];
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_parse_sacct_statuses() {
+        let actual = parse_sacct_statuses(
+            "120|index|COMPLETED|00:10:00|node[1-2]|0:0\n121|qc|CANCELLED by 1000|00:00:00|None assigned|0:15\nmalformed\n",
+        );
+
+        assert_eq!(actual.len(), 2);
+        assert_eq!(actual[0].state, "COMPLETED");
+        assert_eq!(actual[0].nodes.as_deref(), Some("node[1-2]"));
+        assert_eq!(actual[0].exit_code, Some(0));
+        assert_eq!(actual[1].state, "CANCELLED");
+        assert_eq!(actual[1].nodes, None);
+        assert_eq!(actual[1].signal, Some(15));
+    }
+
+    #[test]
+    fn test_expand_filename_pattern() {
+        let mut job = make_submission_plan(
+            "#!/usr/bin/env bash",
+            "euxo pipefail",
+            "align",
+            "1000M",
+            "1:0:0",
+            "%x.err",
+            "%x.out",
+            &Resources::default(),
+            &Environment::default(),
+            "echo align",
+            &[],
+            "ALL",
+            false,
+        )
+        .job;
+        let metadata = JobMetadata {
+            job_id: Some("123".to_string()),
+            user: Some("alice".to_string()),
+            ..JobMetadata::default()
+        };
+        let expand = |pattern: &str, job: &JobSpec, metadata: &JobMetadata| {
+            expand_filename_pattern(pattern, job, metadata)
+        };
+
+        let actual = expand("logs/%u/%x_%j.%%", &job, &metadata);
+        assert_eq!(actual.path, "logs/alice/align_123.%");
+        assert!(actual.unresolved.is_empty());
+        assert_eq!(
+            expand("%J_%s_%n_%t_%A_%a", &job, &metadata).path,
+            "123.batch_batch_0_0_123_4294967294"
+        );
+        assert_eq!(
+            expand("%8j_%3x_%q", &job, &metadata).path,
+            "00000123_align_%q"
+        );
+        assert_eq!(
+            expand("%N\\_%j", &job, &metadata).path,
+            "%N_%j",
+            "a backslash turns off expansion"
+        );
+
+        let actual = expand("%x_%j_%N.out", &job, &JobMetadata::default());
+        assert_eq!(actual.path, "align_%j_%N.out");
+        assert_eq!(actual.unresolved, vec!["%j", "%N"]);
+
+        job.tasks = vec!["echo a".to_string(), "echo b".to_string()];
+        let task = JobMetadata {
+            array_task_id: Some(2),
+            ..metadata.clone()
+        };
+        assert_eq!(
+            expand("%x_%A_%3a.out", &job, &task).path,
+            "align_123_002.out"
+        );
+        let actual = expand("%x_%j.out", &job, &task);
+        assert_eq!(actual.unresolved, vec!["%j"]);
+        assert_eq!(expand("%x_%a.out", &job, &metadata).unresolved, vec!["%a"]);
+    }
+
+    #[test]
+    fn test_check_log_directories_from_chdir() {
+        let dir = std::env::temp_dir().join(format!("ssubmit-log-dirs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let plan = make_submission_plan(
+            "#!/usr/bin/env bash",
+            "euxo pipefail",
+            "align",
+            "1000M",
+            "1:0:0",
+            "%x.err",
+            "logs/%x.out",
+            &Resources::default(),
+            &Environment::default(),
+            "echo align",
+            &["--chdir".to_string(), dir.display().to_string()],
+            "ALL",
+            false,
+        );
+
+        let missing = check_log_directories(&plan, false).unwrap_err();
+        let created = check_log_directories(&plan, true);
+        let existing = check_log_directories(&plan, false);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(missing.kind, "validation");
+        assert!(missing.message.contains("logs/align.out does not exist"));
+        assert_eq!(created.unwrap(), vec![dir.join("logs")]);
+        assert_eq!(existing.unwrap(), Vec::<PathBuf>::new());
+    }
+
+    const SCONTROL_PARTITIONS: &str = "PartitionName=short AllowGroups=ALL Default=YES MaxNodes=2 MaxTime=04:00:00 MinNodes=0 MaxCPUsPerNode=UNLIMITED MaxMemPerNode=64000 State=UP
+PartitionName=gpu AllowGroups=ALL Default=NO MaxNodes=UNLIMITED MaxTime=2-00:00:00 MaxCPUsPerNode=32 MaxMemPerNode=UNLIMITED State=UP
+";
+    const SINFO_NODES: &str = "short*|16|32000|(null)
+short*|32+|128000|(null)
+gpu|48|512000|gpu:a100:4(S:0,1),gpu:v100:2
+";
+
+    fn partitions() -> Vec<PartitionLimits> {
+        let mut partitions = parse_scontrol_partitions(SCONTROL_PARTITIONS);
+        add_sinfo_nodes(&mut partitions, SINFO_NODES);
+        partitions
+    }
+
+    #[test]
+    fn test_parse_partition_limits() {
+        let actual = partitions();
+
+        let expected = vec![
+            PartitionLimits {
+                name: "short".to_string(),
+                default: true,
+                down: false,
+                max_time: Some("04:00:00".to_string()),
+                max_memory_per_node: Some(64000),
+                max_nodes: Some(2),
+                max_cpus_per_node: None,
+                node_cpus: Some(32),
+                node_memory: Some(128000),
+                node_gpus: 0,
+                gpu_types: vec![],
+            },
+            PartitionLimits {
+                name: "gpu".to_string(),
+                default: false,
+                down: false,
+                max_time: Some("2-00:00:00".to_string()),
+                max_memory_per_node: None,
+                max_nodes: None,
+                max_cpus_per_node: Some(32),
+                node_cpus: Some(48),
+                node_memory: Some(512000),
+                node_gpus: 6,
+                gpu_types: vec!["a100".to_string(), "v100".to_string()],
+            },
+        ];
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_memory_megabytes() {
+        assert_eq!(memory_megabytes("4000M"), Some(4000));
+        assert_eq!(memory_megabytes("5K"), Some(1));
+        assert_eq!(memory_megabytes("2G"), Some(2048));
+        assert_eq!(memory_megabytes("1TB"), Some(1024 * 1024));
+        assert_eq!(memory_megabytes("300"), Some(300));
+        assert_eq!(memory_megabytes("lots"), None);
+    }
+
+    #[test]
+    fn test_check_partition_limits() {
+        let partitions = partitions();
+        let check = |memory: &str, time: &str, resources: Resources, arguments: &[&str]| {
+            let arguments: Vec<String> = arguments.iter().map(|arg| arg.to_string()).collect();
+            let plan = make_submission_plan(
+                "#!/usr/bin/env bash",
+                "euxo pipefail",
+                "align",
+                memory,
+                time,
+                "%x.err",
+                "%x.out",
+                &resources,
+                &Environment::default(),
+                "echo align",
+                &arguments,
+                "ALL",
+                false,
+            );
+            check_partition_limits(&plan, &partitions).map_err(|error| error.message)
+        };
+        let gpu = |gpus: &str, nodes: Option<u32>| Resources {
+            partition: Some("gpu".to_string()),
+            gpus: Some(gpus.to_string()),
+            nodes,
+            ..Resources::default()
+        };
+
+        assert_eq!(check("4000M", "4:0:0", Resources::default(), &[]), Ok(()));
+        assert_eq!(
+            check("4000M", "24:0:0", Resources::default(), &[]),
+            Err(
+                "Partition short allows at most 04:00:00, but a time limit of 24:0:0 was requested"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            check("100000M", "1:0:0", Resources::default(), &[]),
+            Err("Partition short allows at most 64000M of memory per node, but 100000M was requested"
+                .to_string())
+        );
+        assert_eq!(
+            check(
+                "100000M",
+                "24:0:0",
+                Resources::default(),
+                &["--partition=gpu"]
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            check("600000M", "1:0:0", Resources::default(), &["-p", "gpu"]),
+            Err("The largest node in partition gpu has 512000M of memory, but 600000M was requested"
+                .to_string())
+        );
+        assert_eq!(
+            check(
+                "1000M",
+                "1:0:0",
+                Resources::default(),
+                &["--partition=short,gpu", "-c", "40"]
+            ),
+            Err(
+                "The largest node in partition short has 32 CPUs, but 40 were requested per node"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            check(
+                "1000M",
+                "25:0:0",
+                Resources::default(),
+                &["--partition=short,gpu"]
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            check("1000M", "1:0:0", Resources::default(), &["--nodes=3"]),
+            Err("Partition short allows at most 2 nodes, but 3 were requested".to_string())
+        );
+        assert_eq!(
+            check(
+                "1000M",
+                "1:0:0",
+                Resources::default(),
+                &["--partition=long"]
+            ),
+            Err("Partition long does not exist; the partitions are short, gpu".to_string())
+        );
+        assert_eq!(
+            check("1000M", "1:0:0", Resources::default(), &["-G", "1"]),
+            Err("Partition short has no GPUs, but --gpus=1 was requested".to_string())
+        );
+        assert_eq!(
+            check("1000M", "1:0:0", gpu("h100:1", None), &[]),
+            Err("Partition gpu has no h100 GPUs; its GPUs are a100, v100".to_string())
+        );
+        assert_eq!(
+            check("1000M", "1:0:0", gpu("a100:8", Some(1)), &[]),
+            Err(
+                "Partition gpu has at most 6 GPUs per node, so 8 GPUs do not fit on 1 node"
+                    .to_string()
+            )
+        );
+        assert_eq!(check("1000M", "1:0:0", gpu("a100:8", None), &[]), Ok(()));
+        assert_eq!(
+            check(
+                "1000M",
+                "24:0:0",
+                Resources::default(),
+                &["--clusters=other"]
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_choose_partition() {
+        let mut partitions = partitions();
+        partitions.push(PartitionLimits {
+            name: "debug".to_string(),
+            down: true,
+            max_time: Some("30:00".to_string()),
+            ..PartitionLimits::default()
+        });
+        let plan = |memory: &str, time: &str, arguments: &[&str]| {
+            let arguments: Vec<String> = arguments.iter().map(|arg| arg.to_string()).collect();
+            make_submission_plan(
+                "#!/usr/bin/env bash",
+                "euxo pipefail",
+                "align",
+                memory,
+                time,
+                "%x.err",
+                "%x.out",
+                &Resources::default(),
+                &Environment::default(),
+                "echo align",
+                &arguments,
+                "ALL",
+                false,
+            )
+        };
+
+        let (partition, reason) =
+            choose_partition(&[plan("4000M", "10:0", &[])], &partitions).unwrap();
+        assert_eq!(partition, "short");
+        assert_eq!(
+            reason,
+            "it has the shortest time limit, 04:00:00, of the 2 partitions the job fits in"
+        );
+
+        let plans = [plan("4000M", "1:0:0", &[]), plan("100000M", "2:0:0", &[])];
+        let (partition, reason) = choose_partition(&plans, &partitions).unwrap();
+        assert_eq!(partition, "gpu");
+        assert_eq!(
+            reason,
+            "it is the only partition the job fits in, with a time limit of 2-00:00:00"
+        );
+
+        let error = choose_partition(&[plan("4000M", "3-0", &[])], &partitions).unwrap_err();
+        assert_eq!(
+            error.message,
+            "The job does not fit in any partition. \
+             Partition short allows at most 04:00:00, but a time limit of 3-0 was requested. \
+             Partition gpu allows at most 2-00:00:00, but a time limit of 3-0 was requested. \
+             Partition debug is down"
+        );
+
+        let error =
+            choose_partition(&[plan("4000M", "1:0:0", &["-p", "gpu"])], &partitions).unwrap_err();
+        assert_eq!(error.kind, "validation");
+    }
 }
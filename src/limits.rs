@@ -0,0 +1,139 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use ssubmit::{
+    check_partition_limits, query_partition_limits, PartitionLimits, SubmissionError,
+    SubmissionPlan,
+};
+
+/// Name of the partition limits cache in the ssubmit cache directory
+pub const CACHE_FILE: &str = "partitions.json";
+
+/// How long the cached partition limits are used before they are queried again
+fn cache_lifetime() -> Duration {
+    Duration::hours(24)
+}
+
+/// The partition limits of the cluster, as last queried
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Cache {
+    fetched_at: DateTime<Utc>,
+    partitions: Vec<PartitionLimits>,
+}
+
+/// The cache file: `$XDG_CACHE_HOME/ssubmit/partitions.json`, falling back to
+/// `~/.cache/ssubmit/partitions.json`
+pub fn cache_path() -> Option<PathBuf> {
+    let cache_home = env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var_os("HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(|home| PathBuf::from(home).join(".cache"))
+        })?;
+    Some(cache_home.join("ssubmit").join(CACHE_FILE))
+}
+
+/// Check a job against the limits of its partition before it is submitted. Cached limits are
+/// used while they are fresh, and queried again before a job is rejected, in case they changed.
+/// If the limits cannot be queried, the job is not checked and Slurm has the last word.
+pub fn check(plan: &SubmissionPlan) -> Result<(), SubmissionError> {
+    let path = cache_path();
+    if let Some(cache) = path.as_deref().and_then(load_fresh) {
+        if check_partition_limits(plan, &cache.partitions).is_ok() {
+            return Ok(());
+        }
+    }
+
+    match query_and_cache(path.as_deref()) {
+        Ok(partitions) => check_partition_limits(plan, &partitions),
+        Err(error) => {
+            warn!(
+                "Not checking the job against the partition limits: {}",
+                error.message
+            );
+            Ok(())
+        }
+    }
+}
+
+/// The limits of every partition, from the cache while it is fresh
+pub fn partitions() -> Result<Vec<PartitionLimits>, SubmissionError> {
+    let path = cache_path();
+    match path.as_deref().and_then(load_fresh) {
+        Some(cache) => Ok(cache.partitions),
+        None => query_and_cache(path.as_deref()),
+    }
+}
+
+/// Query the limits of every partition and cache them
+fn query_and_cache(path: Option<&Path>) -> Result<Vec<PartitionLimits>, SubmissionError> {
+    let cache = Cache {
+        fetched_at: Utc::now(),
+        partitions: query_partition_limits()?,
+    };
+    if let Some(path) = path {
+        if let Err(error) = save(path, &cache) {
+            warn!("Failed to cache the partition limits: {error:#}");
+        }
+    }
+    Ok(cache.partitions)
+}
+
+/// The cached limits, unless they are missing, unreadable or stale
+fn load_fresh(path: &Path) -> Option<Cache> {
+    let contents = fs::read_to_string(path).ok()?;
+    let cache: Cache = serde_json::from_str(&contents).ok()?;
+    let age = Utc::now() - cache.fetched_at;
+    if age < Duration::zero() || age > cache_lifetime() {
+        return None;
+    }
+    Some(cache)
+}
+
+fn save(path: &Path, cache: &Cache) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+    }
+    let contents = serde_json::to_string(cache).context("Failed to render partition limits")?;
+    fs::write(path, contents)
+        .with_context(|| format!("Failed to write cache file {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_fresh_caches_are_loaded() {
+        let dir = env::temp_dir().join(format!("ssubmit-limits-{}", std::process::id()));
+        let path = dir.join("cache").join(CACHE_FILE);
+        let mut cache = Cache {
+            fetched_at: Utc::now(),
+            partitions: vec![PartitionLimits {
+                name: "short".to_string(),
+                default: true,
+                max_time: Some("4:00:00".to_string()),
+                ..PartitionLimits::default()
+            }],
+        };
+
+        assert_eq!(load_fresh(&path), None);
+        save(&path, &cache).unwrap();
+        let fresh = load_fresh(&path);
+        cache.fetched_at = Utc::now() - cache_lifetime() - Duration::minutes(1);
+        save(&path, &cache).unwrap();
+        let stale = load_fresh(&path);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(fresh.unwrap().partitions, cache.partitions);
+        assert_eq!(stale, None);
+    }
+}
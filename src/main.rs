@@ -1,20 +1,35 @@
 use anyhow::{anyhow, Context, Result};
-use clap::Parser;
+use clap::parser::ValueSource;
 use env_logger::Builder;
 use log::{error, info, LevelFilter};
+use std::io::Write as _;
 use std::process::Command;
 
+use std::collections::HashMap;
+
 use ssubmit::{
-    classify_sbatch_failure, make_submission_plan, prepare_machine_submission,
-    prepare_machine_test, run_sbatch, submit_sbatch, test_sbatch, JsonResponse, SubmissionError,
+    array_indices, cancel_jobs, check_log_directories, check_modules, choose_partition,
+    classify_sbatch_failure, current_user, dependency_specification, find_queued_jobs, log_paths,
+    make_array_command, make_retry_plan, make_submission_plan, parse_submission_output,
+    parse_submitted_message, prepare_machine_submission, prepare_machine_test, query_job_statuses,
+    replan_submission, run_sbatch, submit_sbatch, test_sbatch, wait_for_job, ArrayTask, Dependency,
+    DependencyKind, JobMetadata, JobStatus, JsonError, JsonResponse, PipelineJobResult, Resources,
+    SubmissionError, SubmissionPlan,
 };
 
-use crate::cli::Cli;
+use crate::cli::{Action, CancelArgs, Cli, HistoryArgs, ResubmitArgs, RunArgs, StatusArgs};
+use crate::follow::Follower;
+use crate::pipeline::Pipeline;
 
 mod cli;
+mod config;
+mod follow;
+mod history;
+mod limits;
+mod pipeline;
 
 fn main() -> Result<()> {
-    let args = Cli::parse();
+    let (mut args, matches) = Cli::parse_with_matches();
 
     // setup logger
     let mut log_builder = Builder::new();
@@ -23,24 +38,752 @@ fn main() -> Result<()> {
         .format_module_path(false)
         .init();
 
-    if args.json && args.interactive {
-        return emit_json_error("JSON mode does not support interactive jobs");
+    match &args.action {
+        Some(Action::Run(run)) => return handle_pipeline(run),
+        Some(Action::Status(status)) => return handle_status(status),
+        Some(Action::History(history)) => return handle_history(history),
+        Some(Action::Resubmit(resubmit)) => return handle_resubmit(resubmit),
+        Some(Action::Cancel(cancel)) => return handle_cancel(cancel),
+        None => {}
     }
 
-    // Validate and get the command to execute
-    let command = match args.validate_and_get_command() {
-        Ok(command) => command,
+    let settings = match config::load_layers()
+        .map_err(|error| format!("{error:#}"))
+        .and_then(|layers| args.apply_config(&matches, &layers))
+    {
+        Ok(settings) => settings,
         Err(error) if args.json => return emit_json_error(error),
         Err(error) => return Err(anyhow!(error)),
     };
 
+    if args.show_config {
+        print!("{}", config::render(&settings));
+        return Ok(());
+    }
+
+    if args.auto_partition {
+        if matches.value_source("partition") == Some(ValueSource::CommandLine) {
+            let message = "--auto-partition cannot be used with --partition";
+            if args.json {
+                return emit_json_error(message);
+            }
+            return Err(anyhow!(message));
+        }
+        // the partition is chosen instead of taken from the environment or configuration
+        args.partition = None;
+    }
+
+    if args.json && args.interactive {
+        return emit_json_error("JSON mode does not support interactive jobs");
+    }
+
     if args.interactive {
-        handle_interactive_job(&args, &command)
+        let command = match args.validate_and_get_command() {
+            Ok(command) => command,
+            Err(error) => return Err(anyhow!(error)),
+        };
+        let resources = args
+            .dependencies()
+            .and_then(|dependencies| job_resources(&args, None, &dependencies))
+            .map_err(|error| anyhow!(human_submission_error(&error)))?;
+        return handle_interactive_job(&args, &command, &resources);
+    }
+
+    let planned = args
+        .dependencies()
+        .and_then(|dependencies| plan_batch_job(&args, dependencies))
+        .and_then(|plan| {
+            let retries = plan_retries(&args, &plan)?;
+            let plan = if args.auto_partition {
+                auto_partition(plan, &retries)?
+            } else {
+                plan
+            };
+            prepare_log_directories(&plan, args.mkdir, args.dry_run)?;
+            if !args.dry_run {
+                check_modules(&plan.job.environment.modules)?;
+                if !args.no_check_limits {
+                    check_limits(&plan, &retries)?;
+                }
+            }
+            Ok((plan, retries))
+        });
+    let (plan, retries) = match planned {
+        Ok(planned) => planned,
+        Err(error) if args.json => return emit_json_failure(error),
+        Err(error) => return Err(anyhow!(human_submission_error(&error))),
+    };
+    handle_batch_job(&args, plan, &retries)
+}
+
+/// Check the log directories of a job about to be submitted, creating any that are missing if
+/// `mkdir`. A dry run creates nothing, and only reports missing directories without `mkdir`.
+fn prepare_log_directories(
+    plan: &SubmissionPlan,
+    mkdir: bool,
+    dry_run: bool,
+) -> Result<(), SubmissionError> {
+    if dry_run {
+        if !mkdir {
+            check_log_directories(plan, false)?;
+        }
+        return Ok(());
+    }
+    for directory in check_log_directories(plan, mkdir)? {
+        info!("Created directory {}", directory.display());
+    }
+    Ok(())
+}
+
+/// Plan a job again in the partition chosen for it, and its retries, with --auto-partition
+fn auto_partition(
+    plan: SubmissionPlan,
+    retries: &[(String, String)],
+) -> Result<SubmissionPlan, SubmissionError> {
+    let mut plans = vec![plan.clone()];
+    plans.extend(dry_run_retries(&plan, retries));
+    let (partition, reason) = choose_partition(&plans, &limits::partitions()?)?;
+    info!("Chose partition {partition}, as {reason}");
+    let mut job = plan.job;
+    job.resources.partition = Some(partition);
+    job.partition_reason = Some(reason);
+    Ok(replan_submission(&job, &plan.slurm.arguments))
+}
+
+/// Check a job and its retries, with their escalated resources, against the limits of their
+/// partition
+fn check_limits(
+    plan: &SubmissionPlan,
+    retries: &[(String, String)],
+) -> Result<(), SubmissionError> {
+    limits::check(plan)?;
+    for (index, retry) in dry_run_retries(plan, retries).iter().enumerate() {
+        limits::check(retry).map_err(|mut error| {
+            error.message = format!("Retry {}: {}", index + 1, error.message);
+            error
+        })?;
+    }
+    Ok(())
+}
+
+/// The memory and time of each retry requested with --retry
+fn plan_retries(
+    args: &Cli,
+    plan: &SubmissionPlan,
+) -> Result<Vec<(String, String)>, SubmissionError> {
+    let retries = args
+        .retry_resources(&plan.job)
+        .map_err(SubmissionError::validation)?;
+    if !retries.is_empty() {
+        // every retry depends on the attempt before it
+        let resources = Resources {
+            dependency: Some("afternotok".to_string()),
+            ..Resources::default()
+        };
+        args.check_conflicts(&resources)?;
+    }
+    Ok(retries)
+}
+
+/// Submit the retries of a submitted job, each depending on the attempt before it. Returns the
+/// retries that were submitted, and the error that stopped the rest, if any.
+fn submit_retries(
+    plan: &SubmissionPlan,
+    job_id: &str,
+    retries: &[(String, String)],
+) -> (Vec<PipelineJobResult>, Option<SubmissionError>) {
+    let mut attempts = vec![job_id.to_string()];
+    let mut submitted = Vec::with_capacity(retries.len());
+    for (index, (memory, time)) in retries.iter().enumerate() {
+        let retry = make_retry_plan(plan, &attempts, memory, time);
+        let submission = prepare_machine_submission(&retry).and_then(|retry| {
+            let result = submit_sbatch(&retry)?;
+            Ok((retry, result))
+        });
+        match submission {
+            Ok((retry, result)) => {
+                history::record(&retry, &result);
+                attempts.push(result.job_id.clone());
+                submitted.push(PipelineJobResult {
+                    plan: retry,
+                    submission: Some(result),
+                });
+            }
+            Err(mut error) => {
+                error.message = format!("Retry {}: {}", index + 1, error.message);
+                return (submitted, Some(error));
+            }
+        }
+    }
+    (submitted, None)
+}
+
+/// The retry plans for a dry run, in which job IDs are not known
+fn dry_run_retries(plan: &SubmissionPlan, retries: &[(String, String)]) -> Vec<SubmissionPlan> {
+    // the original job is attempt 1
+    let attempts: Vec<String> = (1..=retries.len())
+        .map(|attempt| format!("<attempt {attempt}>"))
+        .collect();
+    retries
+        .iter()
+        .enumerate()
+        .map(|(index, (memory, time))| make_retry_plan(plan, &attempts[..=index], memory, time))
+        .collect()
+}
+
+/// Wait for a submitted job to finish and, if it ran out of memory or time, for the retry that
+/// runs after it, switching `follower` to each retry that is waited for
+fn wait_for_attempts(
+    job_id: &str,
+    retries: &[PipelineJobResult],
+    log: bool,
+    follower: Option<&Follower>,
+) -> Result<JobStatus, SubmissionError> {
+    let mut job_id = job_id.to_string();
+    let mut retries = retries.iter().filter_map(|retry| retry.submission.as_ref());
+    loop {
+        let status = wait_for_job(&job_id, |status| {
+            if log {
+                info!("Job {} is {}", job_id, status.state);
+            }
+        })?;
+        let retry = retries.next();
+        match (status.state.as_str(), retry) {
+            ("OUT_OF_MEMORY" | "TIMEOUT", Some(retry)) => {
+                if log {
+                    info!("Waiting for retry job {}", retry.job_id);
+                }
+                if let Some(follower) = follower {
+                    follower.switch(&retry.job_id);
+                }
+                job_id = retry.job_id.clone();
+            }
+            _ => return Ok(status),
+        }
+    }
+}
+
+/// The exit code for ssubmit to exit with after waiting for a job
+fn job_exit_code(status: &JobStatus) -> i32 {
+    match (status.exit_code, status.signal) {
+        (Some(code), _) if code != 0 => code,
+        (_, Some(signal)) if signal != 0 => 128 + signal,
+        _ if status.state == "COMPLETED" => 0,
+        _ => 1,
+    }
+}
+
+/// Log the submitted retries of a job
+fn log_retries(retries: &[PipelineJobResult]) {
+    for (index, retry) in retries.iter().enumerate() {
+        if let (Some(submission), Some(previous)) = (&retry.submission, &retry.plan.job.resubmit_of)
+        {
+            info!(
+                "Submitted retry {} as batch job {}, to run if job {previous} runs out of memory or time",
+                index + 1,
+                submission.job_id
+            );
+        }
+    }
+}
+
+/// The first-class Slurm options for a job, including its array and dependencies
+fn job_resources(
+    args: &Cli,
+    tasks: Option<&[ArrayTask]>,
+    dependencies: &[Dependency],
+) -> Result<Resources, SubmissionError> {
+    let mut resources = args.resources();
+    if let Some(tasks) = tasks {
+        resources.array = Some(array_indices(tasks.len(), args.array_throttle));
+    }
+    resources.dependency = dependency_specification(dependencies);
+    args.check_conflicts(&resources)?;
+    Ok(resources)
+}
+
+/// Validate the options for a batch job and plan its submission
+fn plan_batch_job(
+    args: &Cli,
+    dependencies: Vec<Dependency>,
+) -> Result<SubmissionPlan, SubmissionError> {
+    let tasks = args.array_tasks().map_err(SubmissionError::validation)?;
+    let command = match &tasks {
+        Some(tasks) => make_array_command(tasks),
+        None => args
+            .validate_and_get_command()
+            .map_err(SubmissionError::validation)?,
+    };
+    let resources = job_resources(args, tasks.as_deref(), &dependencies)?;
+    let environment = args.environment().map_err(SubmissionError::validation)?;
+
+    let mut plan = make_submission_plan(
+        &args.shebang,
+        &args.set,
+        args.job_name(),
+        &args.memory,
+        &args.time,
+        &args.error,
+        &args.output,
+        &resources,
+        &environment,
+        &command,
+        &args.sbatch_arguments(),
+        &args.export,
+        args.test_only,
+    );
+    plan.job.profile = args.profile.clone();
+    plan.job.tasks = tasks
+        .unwrap_or_default()
+        .into_iter()
+        .map(|task| task.command)
+        .collect();
+    plan.job.dependencies = dependencies;
+    Ok(plan)
+}
+
+/// Submit the jobs of a pipeline in dependency order, each after the jobs it depends on
+fn handle_pipeline(run: &RunArgs) -> Result<()> {
+    let fail = |error: SubmissionError, jobs: Vec<PipelineJobResult>| {
+        if run.json {
+            emit_json_response(JsonResponse::pipeline_error(jobs, error.clone()))?;
+        }
+        Err(anyhow!(human_submission_error(&error)))
+    };
+
+    let loaded = Pipeline::load(&run.pipeline).and_then(|pipeline| {
+        let mut layers = vec![pipeline.defaults_layer(&run.pipeline)];
+        layers.extend(config::load_layers()?);
+        Ok((pipeline, layers))
+    });
+    let (pipeline, layers) = match loaded {
+        Ok(loaded) => loaded,
+        Err(error) => {
+            let mut message = format!("{error:#}");
+            if !run.pipeline.exists() {
+                // before the subcommand, `ssubmit run "command"` submitted a job named run
+                message.push_str(". To submit a job named run, use `ssubmit -- run <command>`");
+            }
+            return fail(SubmissionError::validation(message), vec![]);
+        }
+    };
+    let ordered = match pipeline.ordered_jobs() {
+        Ok(ordered) => ordered,
+        Err(error) => return fail(SubmissionError::validation(error), vec![]),
+    };
+
+    let mut job_ids: HashMap<&str, String> = HashMap::new();
+    let mut results: Vec<PipelineJobResult> = Vec::with_capacity(ordered.len());
+    for job in ordered {
+        let args = job.arguments().and_then(|arguments| {
+            let (mut args, matches) = Cli::try_parse_with_matches(arguments).map_err(|error| {
+                let rendered = error.render().to_string();
+                let first_line = rendered.lines().next().unwrap_or_default();
+                first_line.trim_start_matches("error: ").to_string()
+            })?;
+            args.apply_config(&matches, &layers)?;
+            if args.retry.is_some() || args.wait || args.follow {
+                return Err(
+                    "--retry, --wait and --follow are not supported in pipelines".to_string(),
+                );
+            }
+            Ok(args)
+        });
+        let plan = args
+            .map_err(|error| {
+                SubmissionError::validation(format!(
+                    "Invalid options for job {}: {error}",
+                    job.name
+                ))
+            })
+            .and_then(|args| {
+                let mut dependencies = args.dependencies()?;
+                dependencies.extend(job.depends_on.iter().map(|name| Dependency {
+                    kind: DependencyKind::AfterOk,
+                    job: name.clone(),
+                    job_ids: vec![job_ids[name.as_str()].clone()],
+                }));
+                let plan = plan_batch_job(&args, dependencies)?;
+                prepare_log_directories(&plan, run.mkdir || args.mkdir, run.dry_run)?;
+                if !run.dry_run {
+                    check_modules(&plan.job.environment.modules)?;
+                    if !(run.no_check_limits || args.no_check_limits) {
+                        limits::check(&plan)?;
+                    }
+                }
+                Ok(plan)
+            });
+        let plan = match plan {
+            Ok(plan) => plan,
+            Err(error) => return fail(error, results),
+        };
+
+        if run.dry_run {
+            // the job ID is not known until the job is submitted
+            job_ids.insert(&job.name, format!("<{}>", job.name));
+            if !run.json {
+                println!("# {}", job.name);
+                print_dry_run(&plan);
+            }
+            results.push(PipelineJobResult {
+                plan,
+                submission: None,
+            });
+            continue;
+        }
+
+        let submission = prepare_machine_submission(&plan).and_then(|plan| {
+            let result = submit_sbatch(&plan)?;
+            Ok((plan, result))
+        });
+        match submission {
+            Ok((plan, result)) => {
+                history::record(&plan, &result);
+                if !run.json {
+                    info!("Submitted {} as batch job {}", job.name, result.job_id);
+                }
+                job_ids.insert(&job.name, result.job_id.clone());
+                results.push(PipelineJobResult {
+                    plan,
+                    submission: Some(result),
+                });
+            }
+            Err(mut error) => {
+                error.message = format!("Job {}: {}", job.name, error.message);
+                return fail(error, results);
+            }
+        }
+    }
+
+    if run.json {
+        emit_json_response(JsonResponse::pipeline(results))
     } else {
-        handle_batch_job(&args, &command)
+        if run.dry_run {
+            info!("Dry run requested. Nothing submitted");
+        }
+        Ok(())
     }
 }
 
+fn handle_status(status: &StatusArgs) -> Result<()> {
+    let statuses = match query_job_statuses(&status.jobs) {
+        Ok(statuses) => statuses,
+        Err(error) if status.json => {
+            let message = human_submission_error(&error);
+            emit_json_response(JsonResponse::status_error(error))?;
+            return Err(anyhow!(message));
+        }
+        Err(error) => return Err(anyhow!(human_submission_error(&error))),
+    };
+
+    if status.json {
+        emit_json_response(JsonResponse::status(statuses))
+    } else {
+        print_statuses(&statuses);
+        Ok(())
+    }
+}
+
+fn handle_cancel(args: &CancelArgs) -> Result<()> {
+    let fail = |error: SubmissionError| {
+        let message = human_submission_error(&error);
+        if args.json {
+            emit_json_response(JsonResponse::cancellation_error(error))?;
+        }
+        Err(anyhow!(message))
+    };
+    if args.json && !args.yes {
+        return fail(SubmissionError::validation(
+            "JSON mode cannot ask for confirmation; use --yes to cancel the jobs",
+        ));
+    }
+    let matched = match find_queued_jobs(&args.jobs, args.regex) {
+        Ok(matched) => matched,
+        Err(error) => return fail(error),
+    };
+
+    if !args.json {
+        print_statuses(&matched);
+        if !args.yes && !confirm(&format!("Cancel {} job(s)?", matched.len()))? {
+            info!("Nothing cancelled");
+            return Ok(());
+        }
+    }
+
+    let job_ids: Vec<String> = matched.iter().map(|status| status.job_id.clone()).collect();
+    let result = cancel_jobs(&job_ids);
+    if args.json {
+        let failed = result.failed.len();
+        emit_json_response(JsonResponse::cancellation(matched, result))?;
+        return if failed == 0 {
+            Ok(())
+        } else {
+            Err(anyhow!("Failed to cancel {failed} job(s)"))
+        };
+    }
+
+    for job_id in &result.cancelled {
+        info!("Cancelled job {job_id}");
+    }
+    for failure in &result.failed {
+        let message = match &failure.error.stderr {
+            Some(stderr) => format!("{}: {stderr}", failure.error.message),
+            None => failure.error.message.clone(),
+        };
+        error!("Failed to cancel job {}: {message}", failure.job_id);
+    }
+    if result.failed.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("Failed to cancel {} job(s)", result.failed.len()))
+    }
+}
+
+/// Ask a yes or no question on the terminal, defaulting to no
+fn confirm(question: &str) -> Result<bool> {
+    eprint!("{question} [y/N] ");
+    std::io::stderr().flush().ok();
+    let mut answer = String::new();
+    let read = std::io::stdin()
+        .read_line(&mut answer)
+        .context("Failed to read the answer")?;
+    if read == 0 {
+        return Err(anyhow!(
+            "No answer was given; use --yes to go ahead without confirmation"
+        ));
+    }
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Print job statuses as a table with one job per line
+fn print_statuses(statuses: &[JobStatus]) {
+    let rows: Vec<Vec<String>> = statuses
+        .iter()
+        .map(|status| {
+            let exit = match (status.exit_code, status.signal) {
+                (Some(code), Some(signal)) => format!("{code}:{signal}"),
+                (Some(code), None) => code.to_string(),
+                _ => "-".to_string(),
+            };
+            vec![
+                status.job_id.clone(),
+                status.name.clone(),
+                status.state.clone(),
+                status.elapsed.clone(),
+                status.nodes.clone().unwrap_or_else(|| "-".to_string()),
+                exit,
+                status.reason.clone().unwrap_or_else(|| "-".to_string()),
+            ]
+        })
+        .collect();
+    print_table(
+        &[
+            "JOBID", "NAME", "STATE", "ELAPSED", "NODES", "EXIT", "REASON",
+        ],
+        &rows,
+    );
+}
+
+/// Print rows under a header, with the columns aligned
+fn print_table(header: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = header.iter().map(|field| field.len()).collect();
+    for row in rows {
+        for (width, field) in widths.iter_mut().zip(row) {
+            *width = (*width).max(field.len());
+        }
+    }
+    let render = |fields: Vec<&str>| {
+        let padded: Vec<String> = fields
+            .iter()
+            .zip(&widths)
+            .map(|(field, width)| format!("{field:width$}"))
+            .collect();
+        println!("{}", padded.join("  ").trim_end());
+    };
+    render(header.to_vec());
+    for row in rows {
+        render(row.iter().map(String::as_str).collect());
+    }
+}
+
+fn handle_history(args: &HistoryArgs) -> Result<()> {
+    let path = history::history_path().ok_or_else(|| {
+        anyhow!("Cannot find the history: neither XDG_STATE_HOME nor HOME is set")
+    })?;
+    let records = history::load(&path)?;
+
+    if let Some(job) = &args.show {
+        let record = history::find(&records, job)
+            .ok_or_else(|| anyhow!("No job {job} was found in the history"))?;
+        print!("{}", record.plan.slurm.script);
+        return Ok(());
+    }
+
+    let filter = history::Filter {
+        name: args.name.clone(),
+        since: args
+            .since
+            .as_deref()
+            .map(|date| history::parse_date(date, false))
+            .transpose()
+            .map_err(|error| anyhow!(error))?,
+        until: args
+            .until
+            .as_deref()
+            .map(|date| history::parse_date(date, true))
+            .transpose()
+            .map_err(|error| anyhow!(error))?,
+    };
+
+    let mut header = vec!["SUBMITTED", "JOBID", "NAME", "DIRECTORY"];
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    for record in records.iter().filter(|record| filter.matches(record)) {
+        let mut row = vec![
+            record
+                .submitted_at
+                .with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M")
+                .to_string(),
+            record.submission.job_id.clone(),
+            record.plan.job.name.clone(),
+            record.directory.display().to_string(),
+        ];
+        if !args.state.is_empty() {
+            let states = job_states(&record.submission.job_id)
+                .map_err(|error| anyhow!(human_submission_error(&error)))?;
+            let wanted = states.iter().any(|state| {
+                args.state
+                    .iter()
+                    .any(|wanted| wanted.eq_ignore_ascii_case(state))
+            });
+            if !wanted {
+                continue;
+            }
+            row.push(states.join(","));
+        }
+        rows.push(row);
+    }
+    if !args.state.is_empty() {
+        header.push("STATE");
+    }
+    print_table(&header, &rows);
+    Ok(())
+}
+
+/// Submit a job from the history again, with any resources changed
+fn handle_resubmit(args: &ResubmitArgs) -> Result<()> {
+    let original = history::history_path()
+        .ok_or_else(|| {
+            "Cannot find the history: neither XDG_STATE_HOME nor HOME is set".to_string()
+        })
+        .and_then(|path| history::load(&path).map_err(|error| format!("{error:#}")))
+        .and_then(|records| {
+            history::find(&records, &args.job)
+                .cloned()
+                .ok_or_else(|| format!("No job {} was found in the history", args.job))
+        });
+    let original = match original {
+        Ok(original) => original,
+        Err(error) if args.json => return emit_json_error(error),
+        Err(error) => return Err(anyhow!(error)),
+    };
+
+    let mut job = original.plan.job.clone();
+    args.apply(&mut job);
+    // the jobs it depended on have already run
+    job.resources.dependency = None;
+    job.dependencies.clear();
+    job.resubmit_of = Some(original.submission.job_id.clone());
+    if let Err(error) = job
+        .resources
+        .check_conflicts(&original.plan.slurm.arguments)
+    {
+        return if args.json {
+            emit_json_failure(error)
+        } else {
+            Err(anyhow!(human_submission_error(&error)))
+        };
+    }
+    let plan = replan_submission(&job, &original.plan.slurm.arguments);
+
+    if args.dry_run {
+        if let Err(error) = prepare_log_directories(&plan, false, true) {
+            return if args.json {
+                emit_json_failure(error)
+            } else {
+                Err(anyhow!(human_submission_error(&error)))
+            };
+        }
+        if args.json {
+            let mut response = JsonResponse::plan(plan.clone());
+            response.logs = Some(log_paths(&plan.job, &job_metadata(None)));
+            return emit_json_response(response);
+        }
+        info!("Dry run requested. Nothing submitted");
+        let logs = log_paths(&plan.job, &job_metadata(None));
+        info!(
+            "Output would be written to {} and errors to {}",
+            logs.output.path, logs.error.path
+        );
+        print_dry_run(&plan);
+        return Ok(());
+    }
+
+    let submission = prepare_log_directories(&plan, false, false)
+        .and_then(|()| check_modules(&plan.job.environment.modules))
+        .and_then(|()| {
+            if args.no_check_limits {
+                Ok(())
+            } else {
+                limits::check(&plan)
+            }
+        })
+        .and_then(|()| prepare_machine_submission(&plan))
+        .map(|machine_plan| {
+            let result = submit_sbatch(&machine_plan);
+            (machine_plan, result)
+        });
+    match submission {
+        Ok((machine_plan, Ok(result))) => {
+            history::record(&machine_plan, &result);
+            if args.json {
+                let logs = log_paths(&machine_plan.job, &job_metadata(Some(&result.job_id)));
+                let mut response = JsonResponse::submission(machine_plan, result);
+                response.logs = Some(logs);
+                emit_json_response(response)
+            } else {
+                info!(
+                    "Submitted batch job {} as a resubmission of job {}",
+                    result.job_id, original.submission.job_id
+                );
+                Ok(())
+            }
+        }
+        Ok((machine_plan, Err(error))) if args.json => {
+            emit_json_submission_error(machine_plan, error)
+        }
+        Err(error) if args.json => emit_json_submission_error(plan, error),
+        Ok((_, Err(error))) | Err(error) => Err(anyhow!(human_submission_error(&error))),
+    }
+}
+
+/// The distinct current states of a job and its array tasks, or UNKNOWN once Slurm no longer
+/// knows about it
+fn job_states(job_id: &str) -> Result<Vec<String>, SubmissionError> {
+    let statuses = match query_job_statuses(&[job_id.to_string()]) {
+        Ok(statuses) => statuses,
+        Err(error) if error.kind == "validation" => return Ok(vec!["UNKNOWN".to_string()]),
+        Err(error) => return Err(error),
+    };
+    let mut states: Vec<String> = Vec::new();
+    for status in statuses {
+        if !states.contains(&status.state) {
+            states.push(status.state);
+        }
+    }
+    Ok(states)
+}
+
 fn emit_json_response(response: JsonResponse) -> Result<()> {
     let output = serde_json::to_string(&response).context("Failed to render JSON response")?;
     println!("{output}");
@@ -54,7 +797,17 @@ fn emit_json_error(message: impl Into<String>) -> Result<()> {
     Err(anyhow!("{}", message))
 }
 
-fn emit_json_submission_error(plan: ssubmit::SubmissionPlan, error: SubmissionError) -> Result<()> {
+fn emit_json_failure(error: SubmissionError) -> Result<()> {
+    let message = error.message.clone();
+    let mut response = JsonResponse::error(error.kind, error.message);
+    if let Some(json_error) = response.error.as_mut() {
+        json_error.stderr = error.stderr;
+    }
+    emit_json_response(response)?;
+    Err(anyhow!("{}", message))
+}
+
+fn emit_json_submission_error(plan: SubmissionPlan, error: SubmissionError) -> Result<()> {
     let message = error.message.clone();
     if let Some(stderr) = error.stderr.as_deref() {
         eprintln!("{stderr}");
@@ -63,10 +816,7 @@ fn emit_json_submission_error(plan: ssubmit::SubmissionPlan, error: SubmissionEr
     Err(anyhow!("{}", message))
 }
 
-fn emit_json_scheduler_test_error(
-    plan: ssubmit::SubmissionPlan,
-    error: SubmissionError,
-) -> Result<()> {
+fn emit_json_scheduler_test_error(plan: SubmissionPlan, error: SubmissionError) -> Result<()> {
     let message = error.message.clone();
     if let Some(stderr) = error.stderr.as_deref() {
         eprintln!("{stderr}");
@@ -83,25 +833,23 @@ fn human_submission_error(error: &SubmissionError) -> String {
     }
 }
 
-fn handle_batch_job(args: &Cli, command: &str) -> Result<()> {
-    let plan = make_submission_plan(
-        &args.shebang,
-        &args.set,
-        &args.name,
-        &args.memory,
-        &args.time,
-        &args.error,
-        &args.output,
-        command,
-        &args.remainder,
-        &args.export,
-        args.test_only,
-    );
-
+fn handle_batch_job(args: &Cli, plan: SubmissionPlan, retries: &[(String, String)]) -> Result<()> {
     if args.json {
         let test_only = plan.slurm.arguments.iter().any(|arg| arg == "--test-only");
         if args.dry_run {
-            return emit_json_response(JsonResponse::plan(plan));
+            let mut response = JsonResponse::plan(plan.clone());
+            response.logs = Some(log_paths(&plan.job, &job_metadata(None)));
+            if !retries.is_empty() {
+                let jobs = dry_run_retries(&plan, retries)
+                    .into_iter()
+                    .map(|plan| PipelineJobResult {
+                        plan,
+                        submission: None,
+                    })
+                    .collect();
+                response.jobs = Some(jobs);
+            }
+            return emit_json_response(response);
         }
 
         if test_only {
@@ -120,23 +868,62 @@ fn handle_batch_job(args: &Cli, command: &str) -> Result<()> {
             Err(error) => return emit_json_submission_error(plan, error),
         };
         return match submit_sbatch(&machine_plan) {
-            Ok(result) => emit_json_response(JsonResponse::submission(machine_plan, result)),
+            Ok(result) => {
+                history::record(&machine_plan, &result);
+                let (jobs, mut failure) = submit_retries(&plan, &result.job_id, retries);
+                let mut outcome = None;
+                if args.wait && failure.is_none() {
+                    match wait_for_attempts(&result.job_id, &jobs, false, None) {
+                        Ok(status) => outcome = Some(status),
+                        Err(error) => failure = Some(error),
+                    }
+                }
+                let logs = log_paths(&machine_plan.job, &job_metadata(Some(&result.job_id)));
+                let mut response = JsonResponse::submission(machine_plan, result);
+                response.logs = Some(logs);
+                if !retries.is_empty() {
+                    response.jobs = Some(jobs);
+                }
+                response.outcome = outcome;
+                match failure {
+                    None => {
+                        let exit_code = response.outcome.as_ref().map(job_exit_code);
+                        emit_json_response(response)?;
+                        match exit_code {
+                            Some(code) if code != 0 => std::process::exit(code),
+                            _ => Ok(()),
+                        }
+                    }
+                    Some(error) => {
+                        let message = error.message.clone();
+                        response.ok = false;
+                        response.error = Some(JsonError {
+                            kind: error.kind,
+                            message: error.message,
+                            exit_code: error.exit_code,
+                            stderr: error.stderr,
+                        });
+                        emit_json_response(response)?;
+                        Err(anyhow!("{}", message))
+                    }
+                }
+            }
             Err(error) => emit_json_submission_error(machine_plan, error),
         };
     }
 
     if args.dry_run {
         info!("Dry run requested. Nothing submitted");
-        let sbatch_opts = plan.slurm.arguments.join(" ");
-        if sbatch_opts.is_empty() {
-            println!("sbatch <script>")
-        } else {
-            println!("sbatch {sbatch_opts} <script>")
-        }
-        println!(
-            "=====<script>=====\n{}=====<script>=====",
-            plan.slurm.script
+        let logs = log_paths(&plan.job, &job_metadata(None));
+        info!(
+            "Output would be written to {} and errors to {}",
+            logs.output.path, logs.error.path
         );
+        print_dry_run(&plan);
+        for (index, retry) in dry_run_retries(&plan, retries).iter().enumerate() {
+            println!("# retry {}", index + 1);
+            print_dry_run(retry);
+        }
     } else {
         let test_only = plan.slurm.arguments.iter().any(|arg| arg == "--test-only");
         let sbatch_output =
@@ -157,25 +944,88 @@ fn handle_batch_job(args: &Cli, command: &str) -> Result<()> {
                 }
             }
         } else {
-            info!("{}", sbatch_output.stdout.trim_end())
+            // --parsable may have been passed through to sbatch
+            let submission = parse_submitted_message(&sbatch_output.stdout)
+                .or_else(|| parse_submission_output(&sbatch_output.stdout, None).ok());
+            if let Some(submission) = &submission {
+                history::record(&plan, submission);
+            }
+            info!("{}", sbatch_output.stdout.trim_end());
+
+            let wait = args.wait || args.follow;
+            if !retries.is_empty() || wait {
+                let submission = submission.ok_or_else(|| {
+                    anyhow!(
+                        "No job ID was found in the sbatch output to submit retries or wait for"
+                    )
+                })?;
+                let (submitted, failure) = submit_retries(&plan, &submission.job_id, retries);
+                log_retries(&submitted);
+                if let Some(error) = failure {
+                    return Err(anyhow!(human_submission_error(&error)));
+                }
+                if wait {
+                    let follower = args
+                        .follow
+                        .then(|| Follower::start(&plan, &submission.job_id));
+                    let status =
+                        wait_for_attempts(&submission.job_id, &submitted, true, follower.as_ref());
+                    if let Some(follower) = follower {
+                        follower.finish();
+                    }
+                    let status = status.map_err(|error| anyhow!(human_submission_error(&error)))?;
+                    let exit_code = job_exit_code(&status);
+                    info!(
+                        "Job {} finished as {} after {} with exit code {exit_code}",
+                        status.job_id, status.state, status.elapsed
+                    );
+                    if exit_code != 0 {
+                        std::process::exit(exit_code);
+                    }
+                }
+            }
         }
     }
 
     Ok(())
 }
 
-fn handle_interactive_job(args: &Cli, command: &str) -> Result<()> {
+/// What is known about a job before it runs, for expanding its log file names
+fn job_metadata(job_id: Option<&str>) -> JobMetadata {
+    JobMetadata {
+        job_id: job_id.map(str::to_string),
+        user: current_user(),
+        ..JobMetadata::default()
+    }
+}
+
+/// Print the sbatch command and submission script that a plan would run
+fn print_dry_run(plan: &SubmissionPlan) {
+    let sbatch_opts = plan.slurm.arguments.join(" ");
+    if sbatch_opts.is_empty() {
+        println!("sbatch <script>")
+    } else {
+        println!("sbatch {sbatch_opts} <script>")
+    }
+    println!(
+        "=====<script>=====\n{}=====<script>=====",
+        plan.slurm.script
+    );
+}
+
+fn handle_interactive_job(args: &Cli, command: &str, resources: &Resources) -> Result<()> {
     let mut salloc_args = vec![
         "--job-name".to_string(),
-        args.name.clone(),
+        args.job_name().to_string(),
         "--mem".to_string(),
         args.memory.clone(),
         "--time".to_string(),
         args.time.clone(),
     ];
+    salloc_args.extend(resources.slurm_arguments());
 
-    // Add any additional options from remainder
-    salloc_args.extend(args.remainder.clone());
+    // Add any additional options from the configuration and remainder
+    salloc_args.extend(args.sbatch_arguments());
 
     // Parse the command into separate arguments for salloc
     // Split on whitespace but preserve quoted strings
@@ -215,7 +1065,7 @@ fn handle_interactive_job(args: &Cli, command: &str) -> Result<()> {
             None => return Err(anyhow!("Process terminated by signal")),
         }
     } else {
-        info!("Starting interactive job: {}", args.name);
+        info!("Starting interactive job: {}", args.job_name());
         let exit_status = Command::new("salloc")
             .args(&salloc_args)
             .status()
@@ -236,13 +1086,48 @@ mod tests {
     // Helper function to create a test CLI struct
     fn create_test_cli(export: &str, remainder: Vec<String>) -> Cli {
         Cli {
-            name: "test_job".to_string(),
+            name: Some("test_job".to_string()),
             command: Some("echo hello".to_string()),
             remainder,
+            configured: vec![],
+            configured_sbatch_args: None,
             output: "%x.out".to_string(),
             error: "%x.err".to_string(),
             memory: "1G".to_string(),
             time: "1d".to_string(),
+            partition: None,
+            account: None,
+            qos: None,
+            cpus_per_task: None,
+            ntasks: None,
+            nodes: None,
+            ntasks_per_node: None,
+            gpus: None,
+            gres: None,
+            array_file: None,
+            sample_sheet: None,
+            array_throttle: None,
+            after: vec![],
+            after_ok: vec![],
+            after_any: vec![],
+            after_notok: vec![],
+            retry: None,
+            escalate_mem: None,
+            escalate_time: None,
+            wait: false,
+            follow: false,
+            container: None,
+            bind: vec![],
+            nv: false,
+            conda_env: None,
+            module: vec![],
+            venv: None,
+            pixi: None,
+            prologue: None,
+            epilogue: None,
+            mkdir: false,
+            no_check_limits: false,
+            auto_partition: false,
             shebang: "#!/usr/bin/env bash".to_string(),
             set: "euxo pipefail".to_string(),
             dry_run: true, // Use dry_run to avoid actually running sbatch
@@ -251,6 +1136,9 @@ mod tests {
             interactive: false,
             shell: "bash".to_string(),
             export: export.to_string(),
+            profile: None,
+            show_config: false,
+            action: None,
         }
     }
 
@@ -263,11 +1151,12 @@ mod tests {
         let _script = ssubmit::make_submission_script(
             &cli.shebang,
             &cli.set,
-            &cli.name,
+            cli.job_name(),
             &cli.memory,
             &cli.time,
             &cli.error,
             &cli.output,
+            &cli.resources(),
             &_command,
         );
 
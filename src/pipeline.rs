@@ -0,0 +1,322 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::CommandFactory;
+use serde::Deserialize;
+
+use crate::cli::Cli;
+use crate::config::{Config, Layer, Source};
+
+/// A pipeline of jobs, as described in a pipeline file
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pipeline {
+    /// Defaults for every job, with the same keys as a configuration file
+    #[serde(default)]
+    pub defaults: Config,
+    pub jobs: Vec<PipelineJob>,
+}
+
+/// A job in a pipeline
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PipelineJob {
+    pub name: String,
+    pub command: String,
+    /// Jobs in the pipeline that must complete successfully before this one starts
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    /// Extra options passed to sbatch, as if given after `--`
+    #[serde(default)]
+    pub sbatch_args: Vec<String>,
+    /// Any other key is a command line option for the job, e.g. `mem = "4G"`
+    #[serde(flatten)]
+    pub options: toml::Table,
+}
+
+impl Pipeline {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read pipeline file {}", path.display()))?;
+        let pipeline: Pipeline = toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse pipeline file {}", path.display()))?;
+        if !pipeline.defaults.profiles.is_empty() {
+            bail!(
+                "The defaults in pipeline file {} cannot define profiles",
+                path.display()
+            );
+        }
+        Ok(pipeline)
+    }
+
+    /// The pipeline defaults as the highest precedence configuration layer
+    pub fn defaults_layer(&self, path: &Path) -> Layer {
+        Layer {
+            source: Source::Pipeline(PathBuf::from(path)),
+            config: self.defaults.clone(),
+        }
+    }
+
+    /// The jobs in submission order: every job comes after the jobs it depends on, and jobs
+    /// are otherwise kept in the order they are given
+    pub fn ordered_jobs(&self) -> Result<Vec<&PipelineJob>, String> {
+        if self.jobs.is_empty() {
+            return Err("The pipeline has no jobs".to_string());
+        }
+
+        let subcommands: Vec<String> = Cli::command()
+            .get_subcommands()
+            .map(|subcommand| subcommand.get_name().to_string())
+            .collect();
+        for (index, job) in self.jobs.iter().enumerate() {
+            if self.jobs[..index]
+                .iter()
+                .any(|other| other.name == job.name)
+            {
+                return Err(format!("Job {} is defined more than once", job.name));
+            }
+            if subcommands.contains(&job.name) {
+                return Err(format!(
+                    "Job {} has the same name as the ssubmit {} subcommand; rename it",
+                    job.name, job.name
+                ));
+            }
+            for dependency in &job.depends_on {
+                if !self.jobs.iter().any(|other| &other.name == dependency) {
+                    return Err(format!(
+                        "Job {} depends on {dependency}, which is not a job in the pipeline",
+                        job.name
+                    ));
+                }
+            }
+        }
+
+        let mut ordered: Vec<&PipelineJob> = Vec::with_capacity(self.jobs.len());
+        while ordered.len() < self.jobs.len() {
+            let is_ordered = |name: &str| ordered.iter().any(|job| job.name == name);
+            let next = self.jobs.iter().find(|job| {
+                !is_ordered(&job.name)
+                    && job
+                        .depends_on
+                        .iter()
+                        .all(|dependency| is_ordered(dependency))
+            });
+            match next {
+                Some(job) => ordered.push(job),
+                None => {
+                    let remaining: Vec<&str> = self
+                        .jobs
+                        .iter()
+                        .filter(|job| !is_ordered(&job.name))
+                        .map(|job| job.name.as_str())
+                        .collect();
+                    return Err(format!(
+                        "Jobs {} depend on each other in a cycle",
+                        remaining.join(", ")
+                    ));
+                }
+            }
+        }
+        Ok(ordered)
+    }
+}
+
+impl PipelineJob {
+    /// The command line that submits this job on its own, without its pipeline dependencies
+    pub fn arguments(&self) -> Result<Vec<String>, String> {
+        let mut arguments = vec!["ssubmit".to_string()];
+        for (key, value) in &self.options {
+            let value = match value {
+                // a flag is given when true, and left out when false
+                toml::Value::Boolean(true) => {
+                    arguments.push(format!("--{key}"));
+                    continue;
+                }
+                toml::Value::Boolean(false) => continue,
+                toml::Value::String(value) => value.clone(),
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Array(values) => values
+                    .iter()
+                    .map(|value| match value {
+                        toml::Value::String(value) => Ok(value.clone()),
+                        toml::Value::Integer(value) => Ok(value.to_string()),
+                        _ => Err(()),
+                    })
+                    .collect::<Result<Vec<String>, ()>>()
+                    .map_err(|_| {
+                        format!(
+                            "Job {}: {key} must be a list of strings or numbers",
+                            self.name
+                        )
+                    })?
+                    .join(","),
+                _ => {
+                    return Err(format!(
+                        "Job {}: {key} must be a string, number, boolean or list",
+                        self.name
+                    ))
+                }
+            };
+            arguments.push(format!("--{key}={value}"));
+        }
+        arguments.push(self.name.clone());
+        arguments.push(self.command.clone());
+        if !self.sbatch_args.is_empty() {
+            arguments.push("--".to_string());
+            arguments.extend(self.sbatch_args.iter().cloned());
+        }
+        Ok(arguments)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> Pipeline {
+        toml::from_str(contents).unwrap()
+    }
+
+    #[test]
+    fn test_ordered_jobs_follow_dependencies() {
+        let pipeline = parse(
+            r#"
+[[jobs]]
+name = "merge"
+command = "merge"
+depends-on = ["align", "index"]
+
+[[jobs]]
+name = "align"
+command = "align"
+depends-on = ["index"]
+
+[[jobs]]
+name = "index"
+command = "index"
+
+[[jobs]]
+name = "qc"
+command = "qc"
+"#,
+        );
+
+        let actual: Vec<&str> = pipeline
+            .ordered_jobs()
+            .unwrap()
+            .iter()
+            .map(|job| job.name.as_str())
+            .collect();
+        let expected = vec!["index", "align", "merge", "qc"];
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_ordered_jobs_rejects_cycles() {
+        let pipeline = parse(
+            r#"
+[[jobs]]
+name = "a"
+command = "a"
+depends-on = ["b"]
+
+[[jobs]]
+name = "b"
+command = "b"
+depends-on = ["a"]
+
+[[jobs]]
+name = "c"
+command = "c"
+"#,
+        );
+
+        let actual = pipeline.ordered_jobs().unwrap_err();
+        assert_eq!(actual, "Jobs a, b depend on each other in a cycle");
+    }
+
+    #[test]
+    fn test_ordered_jobs_rejects_invalid_pipelines() {
+        let unknown = parse("[[jobs]]\nname = \"a\"\ncommand = \"a\"\ndepends-on = [\"b\"]\n");
+        assert_eq!(
+            unknown.ordered_jobs().unwrap_err(),
+            "Job a depends on b, which is not a job in the pipeline"
+        );
+
+        let duplicate = parse(
+            "[[jobs]]\nname = \"a\"\ncommand = \"a\"\n[[jobs]]\nname = \"a\"\ncommand = \"b\"\n",
+        );
+        assert_eq!(
+            duplicate.ordered_jobs().unwrap_err(),
+            "Job a is defined more than once"
+        );
+
+        let subcommand = parse("[[jobs]]\nname = \"run\"\ncommand = \"a\"\n");
+        assert!(subcommand.ordered_jobs().is_err());
+
+        assert!(parse("jobs = []").ordered_jobs().is_err());
+    }
+
+    #[test]
+    fn test_job_arguments() {
+        let pipeline = parse(
+            r#"
+[[jobs]]
+name = "align"
+command = "minimap2 ref.fa reads.fq"
+mem = "16G"
+cpus-per-task = 8
+after-ok = ["index", 123]
+sbatch-args = ["--constraint=avx2"]
+"#,
+        );
+
+        let actual = pipeline.jobs[0].arguments().unwrap();
+        let expected = vec![
+            "ssubmit",
+            "--after-ok=index,123",
+            "--cpus-per-task=8",
+            "--mem=16G",
+            "align",
+            "minimap2 ref.fa reads.fq",
+            "--",
+            "--constraint=avx2",
+        ];
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_job_arguments_reject_tables() {
+        let pipeline = parse("[[jobs]]\nname = \"a\"\ncommand = \"a\"\nmem = { size = 4 }\n");
+        assert_eq!(
+            pipeline.jobs[0].arguments().unwrap_err(),
+            "Job a: mem must be a string, number, boolean or list"
+        );
+    }
+
+    #[test]
+    fn test_job_arguments_flags() {
+        let pipeline =
+            parse("[[jobs]]\nname = \"a\"\ncommand = \"a\"\nnv = true\nno-check-limits = false\n");
+
+        let actual = pipeline.jobs[0].arguments().unwrap();
+        let expected = vec!["ssubmit", "--nv", "a", "a"];
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_defaults_are_a_config() {
+        let pipeline =
+            parse("[defaults]\nmem = \"4G\"\n\n[[jobs]]\nname = \"a\"\ncommand = \"a\"\n");
+        assert_eq!(pipeline.defaults.mem.as_deref(), Some("4G"));
+
+        let result: Result<Pipeline, _> = toml::from_str(
+            "[defaults]\nmemory = \"4G\"\n\n[[jobs]]\nname = \"a\"\ncommand = \"a\"\n",
+        );
+        assert!(result.is_err());
+    }
+}
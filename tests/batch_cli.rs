@@ -51,7 +51,7 @@ exit "$SSUBMIT_FAKE_EXIT"
         fs::set_permissions(&sbatch_path, fs::Permissions::from_mode(0o755))
             .expect("make fake sbatch executable");
 
-        Self {
+        let fake = Self {
             directory,
             args_path,
             script_path,
@@ -60,7 +60,11 @@ exit "$SSUBMIT_FAKE_EXIT"
             stderr: stderr.to_string(),
             exit_code,
             terminate_by_signal: false,
-        }
+        };
+        // a cluster without partitions, so jobs are not checked against any limits
+        fake.add_query("scontrol", "");
+        fake.add_query("sinfo", "");
+        fake
     }
 
     fn terminating_by_signal(stdout: &str, stderr: &str) -> Self {
@@ -75,6 +79,7 @@ exit "$SSUBMIT_FAKE_EXIT"
         command
             .env_clear()
             .env("PATH", path)
+            .env("XDG_STATE_HOME", self.directory.join("state"))
             .env("SSUBMIT_FAKE_ARGS", &self.args_path)
             .env("SSUBMIT_FAKE_SCRIPT", &self.script_path)
             .env("SSUBMIT_FAKE_INVOKED", &self.invoked_path)
@@ -98,6 +103,90 @@ exit "$SSUBMIT_FAKE_EXIT"
     fn recorded_script(&self) -> String {
         fs::read_to_string(&self.script_path).expect("read fake sbatch script")
     }
+
+    /// Replace sbatch with one that numbers jobs from 101, keeping each submitted script as
+    /// `script.<job id>`
+    fn numbering_jobs(&self) {
+        let path = self.directory.join("sbatch");
+        let script = format!(
+            r#"#!/bin/sh
+set -eu
+count="{dir}/count"
+id=$(( $(cat "$count" 2>/dev/null || echo 100) + 1 ))
+echo "$id" > "$count"
+cat > "{dir}/script.$id"
+echo "$id"
+"#,
+            dir = self.directory.display()
+        );
+        fs::write(&path, script).expect("write numbering sbatch");
+    }
+
+    /// Add a fake scancel that records the jobs it is given in `cancelled`, and fails for
+    /// `failing_job_id`
+    fn add_scancel(&self, failing_job_id: &str) {
+        let path = self.directory.join("scancel");
+        let script = format!(
+            r#"#!/bin/sh
+if [ "$1" = "{failing_job_id}" ]; then
+    echo "scancel: error: Kill job error on job id $1: Access/permission denied" >&2
+    exit 1
+fi
+echo "$1" >> "{dir}/cancelled"
+"#,
+            dir = self.directory.display()
+        );
+        fs::write(&path, script).expect("write fake scancel");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+            .expect("make fake scancel executable");
+    }
+
+    /// Add a fake sacct that reports each job going through `states`, one per call, as
+    /// `(job id, "STATE EXITCODE")`, staying in the last one
+    fn add_job_states(&self, states: &[(&str, &[&str])]) {
+        for (job_id, job_states) in states {
+            let mut contents = job_states.join("\n");
+            contents.push('\n');
+            fs::write(self.directory.join(format!("states.{job_id}")), contents)
+                .expect("write job states");
+        }
+        let path = self.directory.join("sacct");
+        let script = format!(
+            r#"#!/bin/sh
+for arg in "$@"; do
+    case "$arg" in --jobs=*) job="${{arg#--jobs=}}" ;; esac
+done
+states="{dir}/states.$job"
+line=$(head -n 1 "$states")
+if [ "$(wc -l < "$states")" -gt 1 ]; then
+    tail -n +2 "$states" > "$states.next" && mv "$states.next" "$states"
+fi
+printf '%s|job|%s|00:00:05|node1|%s\n' "$job" "${{line% *}}" "${{line#* }}"
+"#,
+            dir = self.directory.display()
+        );
+        fs::write(&path, script).expect("write fake sacct");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+            .expect("make fake sacct executable");
+    }
+
+    /// Add a fake Slurm query command that fails with `stderr`
+    fn add_failing_query(&self, program: &str, stderr: &str, exit_code: i32) {
+        let path = self.directory.join(program);
+        let script = format!("#!/bin/sh\nprintf '%s' '{stderr}' >&2\nexit {exit_code}\n");
+        fs::write(&path, script).expect("write fake query command");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+            .expect("make fake query command executable");
+    }
+
+    /// Add a fake Slurm query command, e.g. squeue, that prints `stdout`
+    fn add_query(&self, program: &str, stdout: &str) {
+        let path = self.directory.join(program);
+        let script = format!("#!/bin/sh\nprintf '%s' '{stdout}'\n");
+        fs::write(&path, script).expect("write fake query command");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+            .expect("make fake query command executable");
+    }
 }
 
 fn parse_json(output: &Output) -> Value {
@@ -167,56 +256,19 @@ fn assert_matches_schema(response: &Value) {
                 assert!(response["test"].is_object());
             }
             "submit" => assert!(response["submission"].is_object()),
+            "run" => assert!(response["jobs"].is_array()),
+            "status" => assert!(response["statuses"].is_array()),
+            "cancel" => {
+                assert!(response["statuses"].is_array());
+                assert!(response["cancellation"].is_object());
+            }
             _ => unreachable!("operation enum was checked above"),
         }
         if response["plan"].is_object() {
-            assert_required_fields(
-                &response["plan"],
-                &schema["definitions"]["plan"]["required"],
-                "plan",
-            );
-            assert_required_string_fields(
-                &response["plan"]["job"],
-                &schema["definitions"]["job"],
-                "plan.job",
-            );
-            assert_required_fields(
-                &response["plan"]["slurm"],
-                &schema["definitions"]["slurm"]["required"],
-                "plan.slurm",
-            );
-            assert!(
-                response["plan"]["slurm"]["executable"].is_string(),
-                "plan.slurm.executable must be a string"
-            );
-            assert!(
-                response["plan"]["slurm"]["script"].is_string(),
-                "plan.slurm.script must be a string"
-            );
-            assert!(
-                response["plan"]["slurm"]["arguments"]
-                    .as_array()
-                    .expect("plan.slurm.arguments must be an array")
-                    .iter()
-                    .all(Value::is_string),
-                "plan.slurm.arguments must contain only strings"
-            );
+            assert_plan_matches_schema(&response["plan"], &schema, "plan");
         }
         if response["submission"].is_object() {
-            assert_required_fields(
-                &response["submission"],
-                &schema["definitions"]["submission"]["required"],
-                "submission",
-            );
-            assert!(
-                response["submission"]["job_id"].is_string(),
-                "submission.job_id must be a string"
-            );
-            assert!(
-                response["submission"]["cluster"].is_null()
-                    || response["submission"]["cluster"].is_string(),
-                "submission.cluster must be null or a string"
-            );
+            assert_submission_matches_schema(&response["submission"], &schema, "submission");
         }
         if response["test"].is_object() {
             assert_required_fields(
@@ -236,6 +288,155 @@ fn assert_matches_schema(response: &Value) {
             assert!(response["error"]["stderr"].is_string());
         }
     }
+
+    if let Some(jobs) = response.get("jobs") {
+        for (index, job) in jobs
+            .as_array()
+            .expect("jobs must be an array")
+            .iter()
+            .enumerate()
+        {
+            let label = format!("jobs[{index}]");
+            assert_required_fields(
+                job,
+                &schema["definitions"]["pipeline_job"]["required"],
+                &label,
+            );
+            assert_plan_matches_schema(&job["plan"], &schema, &format!("{label}.plan"));
+            if job.get("submission").is_some() {
+                assert_submission_matches_schema(
+                    &job["submission"],
+                    &schema,
+                    &format!("{label}.submission"),
+                );
+            }
+        }
+    }
+
+    if let Some(logs) = response.get("logs") {
+        assert_required_fields(logs, &schema["definitions"]["logs"]["required"], "logs");
+        for file in ["output", "error"] {
+            let label = format!("logs.{file}");
+            assert_required_string_fields(&logs[file], &schema["definitions"]["filename"], &label);
+            if let Some(unresolved) = logs[file].get("unresolved") {
+                assert!(unresolved
+                    .as_array()
+                    .expect("unresolved patterns must be an array")
+                    .iter()
+                    .all(Value::is_string));
+            }
+        }
+    }
+
+    if let Some(outcome) = response.get("outcome") {
+        assert_required_string_fields(outcome, &schema["definitions"]["status"], "outcome");
+    }
+
+    if let Some(cancellation) = response.get("cancellation") {
+        assert_cancellation_matches_schema(cancellation, &schema);
+    }
+
+    if let Some(statuses) = response.get("statuses") {
+        for (index, status) in statuses
+            .as_array()
+            .expect("statuses must be an array")
+            .iter()
+            .enumerate()
+        {
+            let label = format!("statuses[{index}]");
+            assert_required_string_fields(status, &schema["definitions"]["status"], &label);
+            for property in ["exit_code", "signal"] {
+                if status.get(property).is_some() {
+                    assert!(
+                        status[property].is_i64(),
+                        "{label}.{property} must be an integer"
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn assert_cancellation_matches_schema(cancellation: &Value, schema: &Value) {
+    assert_required_fields(
+        cancellation,
+        &schema["definitions"]["cancellation"]["required"],
+        "cancellation",
+    );
+    assert!(cancellation["cancelled"]
+        .as_array()
+        .expect("cancellation.cancelled must be an array")
+        .iter()
+        .all(Value::is_string));
+    for failure in cancellation["failed"]
+        .as_array()
+        .expect("cancellation.failed must be an array")
+    {
+        assert_required_string_fields(failure, &schema["definitions"]["error"], "failure");
+        assert!(
+            failure["job_id"].is_string(),
+            "failure.job_id must be a string"
+        );
+    }
+}
+
+fn assert_plan_matches_schema(plan: &Value, schema: &Value, label: &str) {
+    assert_required_fields(plan, &schema["definitions"]["plan"]["required"], label);
+    assert_required_string_fields(
+        &plan["job"],
+        &schema["definitions"]["job"],
+        &format!("{label}.job"),
+    );
+    assert_required_fields(
+        &plan["slurm"],
+        &schema["definitions"]["slurm"]["required"],
+        &format!("{label}.slurm"),
+    );
+    assert!(
+        plan["slurm"]["executable"].is_string(),
+        "{label}.slurm.executable must be a string"
+    );
+    assert!(
+        plan["slurm"]["script"].is_string(),
+        "{label}.slurm.script must be a string"
+    );
+    assert!(
+        plan["slurm"]["arguments"]
+            .as_array()
+            .expect("plan.slurm.arguments must be an array")
+            .iter()
+            .all(Value::is_string),
+        "{label}.slurm.arguments must contain only strings"
+    );
+    if let Some(container) = plan["job"].get("container") {
+        let label = format!("{label}.job.container");
+        assert_required_fields(
+            container,
+            &schema["definitions"]["container"]["required"],
+            &label,
+        );
+        assert!(
+            container["image"].is_string(),
+            "{label}.image must be a string"
+        );
+        assert!(container["nv"].is_boolean(), "{label}.nv must be a boolean");
+    }
+}
+
+fn assert_submission_matches_schema(submission: &Value, schema: &Value, label: &str) {
+    assert_required_fields(
+        submission,
+        &schema["definitions"]["submission"]["required"],
+        label,
+    );
+    assert!(
+        submission["job_id"].is_string(),
+        "{label}.job_id must be a string"
+    );
+    assert!(
+        submission["cluster"].is_null() || submission["cluster"].is_string(),
+        "{label}.cluster must be null or a string"
+    );
 }
 
 impl Drop for FakeSbatch {
@@ -690,3 +891,2256 @@ fn text_test_only_preserves_scheduler_feedback_and_uses_one_flag() {
     assert!(String::from_utf8_lossy(&output.stderr).contains("Job 55 to start at later"));
     assert_eq!(fake.recorded_args(), "--export=ALL\n--test-only\n");
 }
+
+#[test]
+fn json_dry_run_includes_first_class_resources() {
+    let fake = FakeSbatch::new("unexpected output", "unexpected error", 99);
+
+    let output = fake.run(&[
+        "--dry-run",
+        "--json",
+        "--cpus-per-task",
+        "8",
+        "--nodes",
+        "1",
+        "example",
+        "echo hello",
+    ]);
+
+    assert!(output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["plan"]["job"]["cpus_per_task"], json!(8));
+    assert_eq!(response["plan"]["job"]["nodes"], json!(1));
+    assert!(response["plan"]["job"].get("ntasks").is_none());
+    assert_eq!(
+        response["plan"]["slurm"]["arguments"],
+        json!(["--export=ALL"])
+    );
+    let script = response["plan"]["slurm"]["script"]
+        .as_str()
+        .expect("plan script");
+    assert!(script.contains("#SBATCH --cpus-per-task=8\n#SBATCH --nodes=1\n"));
+    assert!(!Path::new(&fake.invoked_path).exists());
+}
+
+#[test]
+fn json_resource_conflicting_with_passthrough_returns_validation_error() {
+    let fake = FakeSbatch::new("unexpected", "unexpected", 0);
+
+    let output = fake.run(&[
+        "--json",
+        "-c",
+        "8",
+        "example",
+        "echo hello",
+        "--",
+        "-c",
+        "4",
+    ]);
+
+    assert!(!output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["ok"], json!(false));
+    assert_eq!(response["error"]["kind"], json!("validation"));
+    assert!(response["error"]["message"]
+        .as_str()
+        .expect("conflict message")
+        .contains("--cpus-per-task"));
+    assert!(!Path::new(&fake.invoked_path).exists());
+}
+
+#[test]
+fn gpu_requests_are_normalised_into_the_submission_script() {
+    let fake = FakeSbatch::new("Submitted batch job 77\n", "", 0);
+
+    let output = fake.run(&[
+        "--gres",
+        "a100:2, shard:4",
+        "--gpus",
+        "a100:2",
+        "train",
+        "python train.py",
+    ]);
+
+    assert!(output.status.success());
+    assert_eq!(fake.recorded_args(), "--export=ALL\n");
+    let script = fake.recorded_script();
+    assert!(script.contains("#SBATCH --gpus=a100:2\n#SBATCH --gres=gpu:a100:2,shard:4\n"));
+}
+
+#[test]
+fn invalid_gpu_request_is_rejected_before_invoking_sbatch() {
+    let fake = FakeSbatch::new("unexpected", "unexpected", 0);
+
+    let output = fake.run(&["--gres", "gpu:a100:two", "train", "python train.py"]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid count 'two'"));
+    assert!(!Path::new(&fake.invoked_path).exists());
+}
+
+#[test]
+fn scheduling_environment_variables_are_planned_and_rendered() {
+    let fake = FakeSbatch::new("unused", "unused", 0);
+
+    let output = fake
+        .command()
+        .env("SSUBMIT_PARTITION", "short")
+        .env("SSUBMIT_ACCOUNT", "lab")
+        .env("SSUBMIT_QOS", "")
+        .args([
+            "--dry-run",
+            "--json",
+            "--qos",
+            "normal",
+            "example",
+            "echo hello",
+        ])
+        .output()
+        .expect("run ssubmit");
+
+    assert!(output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["plan"]["job"]["partition"], json!("short"));
+    assert_eq!(response["plan"]["job"]["account"], json!("lab"));
+    assert_eq!(response["plan"]["job"]["qos"], json!("normal"));
+    assert!(response["plan"]["slurm"]["script"]
+        .as_str()
+        .expect("plan script")
+        .contains("#SBATCH --partition=short\n#SBATCH --account=lab\n#SBATCH --qos=normal\n"));
+}
+
+#[test]
+fn unset_scheduling_options_are_not_rendered() {
+    let fake = FakeSbatch::new("unused", "unused", 0);
+
+    let output = fake
+        .command()
+        .env("SSUBMIT_PARTITION", "")
+        .args(["--dry-run", "--json", "example", "echo hello"])
+        .output()
+        .expect("run ssubmit");
+
+    assert!(output.status.success());
+    let response = parse_json(&output);
+    assert!(response["plan"]["job"].get("partition").is_none());
+    assert!(!response["plan"]["slurm"]["script"]
+        .as_str()
+        .expect("plan script")
+        .contains("--partition"));
+}
+
+#[test]
+fn passthrough_options_override_scheduling_environment_variables() {
+    let fake = FakeSbatch::new("unused", "unused", 0);
+
+    let output = fake
+        .command()
+        .env("SSUBMIT_PARTITION", "short")
+        .env("SSUBMIT_ACCOUNT", "lab")
+        .args([
+            "--dry-run",
+            "--json",
+            "example",
+            "echo hello",
+            "--",
+            "--partition=long",
+        ])
+        .output()
+        .expect("run ssubmit");
+
+    assert!(output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert!(response["plan"]["job"].get("partition").is_none());
+    assert_eq!(response["plan"]["job"]["account"], json!("lab"));
+    assert_eq!(
+        response["plan"]["slurm"]["arguments"][0],
+        json!("--partition=long")
+    );
+    assert!(!response["plan"]["slurm"]["script"]
+        .as_str()
+        .expect("plan script")
+        .contains("--partition"));
+
+    // given on the command line, both are the user's and conflict
+    let output = fake
+        .command()
+        .env("SSUBMIT_PARTITION", "short")
+        .args([
+            "--dry-run",
+            "-p",
+            "short",
+            "example",
+            "echo hello",
+            "--",
+            "--partition=long",
+        ])
+        .output()
+        .expect("run ssubmit");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("--partition was given both as an ssubmit option and after `--`"));
+}
+
+#[test]
+fn project_config_supplies_defaults_below_environment_and_command_line() {
+    let fake = FakeSbatch::new("unused", "unused", 0);
+    let project = fake.directory.join("project");
+    let nested = project.join("analysis");
+    fs::create_dir_all(&nested).expect("create project directory");
+    fs::write(
+        project.join(".ssubmit.toml"),
+        r#"
+mem = "4G"
+time = "2h"
+partition = "short"
+output = "logs/%x.out"
+sbatch-args = ["--mail-type=END"]
+"#,
+    )
+    .expect("write project config");
+    let user_config = fake.directory.join("xdg").join("ssubmit");
+    fs::create_dir_all(&user_config).expect("create user config directory");
+    fs::write(
+        user_config.join("config.toml"),
+        "mem = \"8G\"\naccount = \"lab\"\n",
+    )
+    .expect("write user config");
+
+    let output = fake
+        .command()
+        .current_dir(&nested)
+        .env("XDG_CONFIG_HOME", fake.directory.join("xdg"))
+        .env("SSUBMIT_TIME", "3h")
+        .args([
+            "--dry-run",
+            "--json",
+            "--mkdir",
+            "-p",
+            "long",
+            "example",
+            "echo hello",
+        ])
+        .output()
+        .expect("run ssubmit");
+
+    assert!(output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    let job = &response["plan"]["job"];
+    assert_eq!(job["memory"], json!("4000M"));
+    assert_eq!(job["time"], json!("3:0:0"));
+    assert_eq!(job["partition"], json!("long"));
+    assert_eq!(job["account"], json!("lab"));
+    assert_eq!(job["output"], json!("logs/%x.out"));
+    assert_eq!(
+        response["plan"]["slurm"]["arguments"],
+        json!(["--mail-type=END", "--export=ALL"])
+    );
+}
+
+#[test]
+fn show_config_reports_where_each_value_came_from() {
+    let fake = FakeSbatch::new("unused", "unused", 0);
+    fs::write(fake.directory.join(".ssubmit.toml"), "qos = \"normal\"\n")
+        .expect("write project config");
+
+    let output = fake
+        .command()
+        .current_dir(&fake.directory)
+        .env("SSUBMIT_MEMORY", "2G")
+        .args(["--show-config", "-c", "4"])
+        .output()
+        .expect("run ssubmit");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("mem = \"2000M\" # environment (SSUBMIT_MEMORY)\n"));
+    assert!(stdout.contains("cpus-per-task = 4 # command line\n"));
+    assert!(stdout.contains(&format!(
+        "qos = \"normal\" # project config ({})\n",
+        fake.directory.join(".ssubmit.toml").display()
+    )));
+    assert!(stdout.contains("time = \"24:0:0\" # built-in default\n"));
+    assert!(stdout.contains("# partition is not set\n"));
+    assert!(!Path::new(&fake.invoked_path).exists());
+}
+
+#[test]
+fn json_invalid_config_returns_a_structured_validation_error() {
+    let fake = FakeSbatch::new("unexpected", "unexpected", 0);
+    fs::write(fake.directory.join(".ssubmit.toml"), "mem = \"lots\"\n")
+        .expect("write project config");
+
+    let output = fake
+        .command()
+        .current_dir(&fake.directory)
+        .args(["--json", "example", "echo hello"])
+        .output()
+        .expect("run ssubmit");
+
+    assert!(!output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["error"]["kind"], json!("validation"));
+    assert!(response["error"]["message"]
+        .as_str()
+        .expect("config error message")
+        .starts_with("Invalid mem in project config"));
+    assert!(!Path::new(&fake.invoked_path).exists());
+}
+
+#[test]
+fn profile_expands_to_settings_that_command_line_options_override() {
+    let fake = FakeSbatch::new("unused", "unused", 0);
+    fs::write(
+        fake.directory.join(".ssubmit.toml"),
+        r#"
+mem = "1G"
+partition = "short"
+
+[profiles.gpu-large]
+mem = "64G"
+time = "2d"
+partition = "gpu"
+gres = "a100:2"
+sbatch-args = ["--constraint=nvlink"]
+"#,
+    )
+    .expect("write project config");
+
+    let output = fake
+        .command()
+        .current_dir(&fake.directory)
+        .env("SSUBMIT_TIME", "1h")
+        .args([
+            "--dry-run",
+            "--json",
+            "--profile",
+            "gpu-large",
+            "--mem",
+            "32G",
+            "train",
+            "python train.py",
+        ])
+        .output()
+        .expect("run ssubmit");
+
+    assert!(output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    let job = &response["plan"]["job"];
+    assert_eq!(job["profile"], json!("gpu-large"));
+    assert_eq!(job["memory"], json!("32000M"));
+    assert_eq!(job["time"], json!("48:0:0"));
+    assert_eq!(job["partition"], json!("gpu"));
+    assert_eq!(job["gres"], json!("gpu:a100:2"));
+    assert_eq!(
+        response["plan"]["slurm"]["arguments"],
+        json!(["--constraint=nvlink", "--export=ALL"])
+    );
+}
+
+#[test]
+fn config_sbatch_args_conflicts_name_the_config() {
+    let fake = FakeSbatch::new("unused", "unused", 0);
+    fs::write(
+        fake.directory.join(".ssubmit.toml"),
+        "partition = \"short\"\nsbatch-args = [\"--partition=long\", \"--cpus-per-task=2\"]\n",
+    )
+    .expect("write project config");
+    let run = |args: &[&str]| {
+        fake.command()
+            .current_dir(&fake.directory)
+            .args(["--dry-run", "--json"])
+            .args(args)
+            .args(["example", "echo hello"])
+            .output()
+            .expect("run ssubmit")
+    };
+
+    // the configured partition gives way to the configured passthrough one
+    let output = run(&[]);
+    assert!(output.status.success());
+    let response = parse_json(&output);
+    assert!(response["plan"]["job"].get("partition").is_none());
+    assert_eq!(
+        response["plan"]["slurm"]["arguments"],
+        json!(["--partition=long", "--cpus-per-task=2", "--export=ALL"])
+    );
+
+    let output = run(&["-c", "4"]);
+    assert!(!output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    let message = response["error"]["message"].as_str().expect("message");
+    assert!(message.starts_with(
+        "--cpus-per-task was given both as an ssubmit option and in the sbatch-args of project config ("
+    ));
+    assert!(message.ends_with(".ssubmit.toml); remove one of them"));
+}
+
+#[test]
+fn json_unknown_profile_returns_a_structured_validation_error() {
+    let fake = FakeSbatch::new("unexpected", "unexpected", 0);
+    fs::write(
+        fake.directory.join(".ssubmit.toml"),
+        "[profiles.short]\ntime = \"1h\"\n",
+    )
+    .expect("write project config");
+
+    let output = fake
+        .command()
+        .current_dir(&fake.directory)
+        .args(["--json", "--profile", "long", "example", "echo hello"])
+        .output()
+        .expect("run ssubmit");
+
+    assert!(!output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["error"]["kind"], json!("validation"));
+    assert_eq!(
+        response["error"]["message"],
+        json!("Unknown profile long; defined profiles are short")
+    );
+    assert!(!Path::new(&fake.invoked_path).exists());
+}
+
+#[test]
+fn json_array_file_submission_reports_the_array_job_id() {
+    let fake = FakeSbatch::new("5678\n", "", 0);
+    let array_file = fake.directory.join("cmds.txt");
+    fs::write(&array_file, "echo one\n\n# skipped\necho two\necho three\n")
+        .expect("write array file");
+
+    let output = fake.run(&[
+        "--json",
+        "--array-file",
+        array_file.to_str().expect("utf-8 path"),
+        "--array-throttle",
+        "2",
+        "example",
+    ]);
+
+    assert!(output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["submission"]["job_id"], json!("5678"));
+    let job = &response["plan"]["job"];
+    assert_eq!(job["array"], json!("1-3%2"));
+    assert_eq!(job["tasks"], json!(["echo one", "echo two", "echo three"]));
+    assert_eq!(job["output"], json!("%x_%A_%a.out"));
+    assert_eq!(job["error"], json!("%x_%A_%a.err"));
+
+    let script = fake.recorded_script();
+    assert!(script.contains("#SBATCH --output=%x_%A_%a.out\n#SBATCH --array=1-3%2\n"));
+
+    let task = Command::new("bash")
+        .arg(&fake.script_path)
+        .env("SLURM_ARRAY_TASK_ID", "2")
+        .output()
+        .expect("run submission script");
+    assert!(task.status.success());
+    assert_eq!(String::from_utf8_lossy(&task.stdout), "two\n");
+}
+
+#[test]
+fn array_file_keeps_explicit_log_patterns() {
+    let fake = FakeSbatch::new("unused", "unused", 0);
+    let array_file = fake.directory.join("cmds.txt");
+    fs::write(&array_file, "echo one\n").expect("write array file");
+
+    let output = fake.run(&[
+        "--dry-run",
+        "--json",
+        "--mkdir",
+        "--array-file",
+        array_file.to_str().expect("utf-8 path"),
+        "-o",
+        "logs/%A_%a.log",
+        "example",
+    ]);
+
+    assert!(output.status.success());
+    let response = parse_json(&output);
+    assert_eq!(response["plan"]["job"]["output"], json!("logs/%A_%a.log"));
+    assert_eq!(response["plan"]["job"]["error"], json!("%x_%A_%a.err"));
+    assert_eq!(response["plan"]["job"]["array"], json!("1-1"));
+    assert!(!Path::new(&fake.invoked_path).exists());
+}
+
+#[test]
+fn json_empty_array_file_returns_a_structured_validation_error() {
+    let fake = FakeSbatch::new("unexpected", "unexpected", 0);
+    let array_file = fake.directory.join("cmds.txt");
+    fs::write(&array_file, "\n# nothing to run\n").expect("write array file");
+
+    let output = fake.run(&[
+        "--json",
+        "--array-file",
+        array_file.to_str().expect("utf-8 path"),
+        "example",
+    ]);
+
+    assert!(!output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["error"]["kind"], json!("validation"));
+    assert_eq!(
+        response["error"]["message"],
+        json!(format!(
+            "Array file {} contains no commands",
+            array_file.display()
+        ))
+    );
+    assert!(!Path::new(&fake.invoked_path).exists());
+}
+
+#[test]
+fn json_dry_run_expands_a_sample_sheet_into_array_tasks() {
+    let fake = FakeSbatch::new("unexpected", "unexpected", 99);
+    let sample_sheet = fake.directory.join("samples.csv");
+    fs::write(&sample_sheet, "sample,reads\nA1,a1.fq\nB2,\"b 2.fq\"\n").expect("write sheet");
+
+    let output = fake.run(&[
+        "--dry-run",
+        "--json",
+        "--sample-sheet",
+        sample_sheet.to_str().expect("utf-8 path"),
+        "map",
+        "echo {sample} \"$reads\" {reads}",
+    ]);
+
+    assert!(output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    let job = &response["plan"]["job"];
+    assert_eq!(job["array"], json!("1-2"));
+    assert_eq!(
+        job["tasks"],
+        json!(["echo A1 \"$reads\" a1.fq", "echo B2 \"$reads\" 'b 2.fq'"])
+    );
+    assert_eq!(job["output"], json!("%x_%A_%a.out"));
+    assert!(!Path::new(&fake.invoked_path).exists());
+
+    let script = response["plan"]["slurm"]["script"]
+        .as_str()
+        .expect("plan script");
+    assert!(script.contains("2)\n    export sample='B2' reads='b 2.fq'\n"));
+    let task = Command::new("bash")
+        .args(["-c", script])
+        .env("SLURM_ARRAY_TASK_ID", "2")
+        .output()
+        .expect("run submission script");
+    assert!(task.status.success());
+    assert_eq!(String::from_utf8_lossy(&task.stdout), "B2 b 2.fq b 2.fq\n");
+}
+
+#[test]
+fn json_unknown_sample_sheet_placeholder_returns_a_structured_validation_error() {
+    let fake = FakeSbatch::new("unexpected", "unexpected", 0);
+    let sample_sheet = fake.directory.join("samples.tsv");
+    fs::write(&sample_sheet, "sample\treads\nA1\ta1.fq\n").expect("write sheet");
+
+    let output = fake.run(&[
+        "--json",
+        "--sample-sheet",
+        sample_sheet.to_str().expect("utf-8 path"),
+        "map",
+        "echo {smaple}",
+    ]);
+
+    assert!(!output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["error"]["kind"], json!("validation"));
+    assert_eq!(
+        response["error"]["message"],
+        json!("Unknown placeholder {smaple} in the command; the sample sheet columns are sample, reads")
+    );
+    assert!(!Path::new(&fake.invoked_path).exists());
+}
+
+#[test]
+fn json_dry_run_resolves_dependencies_by_id_and_name() {
+    let fake = FakeSbatch::new("unexpected", "unexpected", 99);
+    fake.add_query("squeue", "124\n125\n125\n");
+
+    let output = fake.run(&[
+        "--dry-run",
+        "--json",
+        "--after-ok",
+        "123,align",
+        "--after-any",
+        "99_2",
+        "merge",
+        "echo merge",
+    ]);
+
+    assert!(output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    let job = &response["plan"]["job"];
+    assert_eq!(
+        job["dependency"],
+        json!("afterok:123:124:125,afterany:99_2")
+    );
+    assert_eq!(
+        job["dependencies"],
+        json!([
+            {"type": "afterok", "job": "123", "job_ids": ["123"]},
+            {"type": "afterok", "job": "align", "job_ids": ["124", "125"]},
+            {"type": "afterany", "job": "99_2", "job_ids": ["99_2"]}
+        ])
+    );
+    let script = response["plan"]["slurm"]["script"]
+        .as_str()
+        .expect("plan script");
+    assert!(script.contains("#SBATCH --dependency=afterok:123:124:125,afterany:99_2\n"));
+    assert!(!Path::new(&fake.invoked_path).exists());
+}
+
+#[test]
+fn finished_dependency_is_found_in_accounting_records() {
+    let fake = FakeSbatch::new("Submitted batch job 300\n", "", 0);
+    fake.add_query("squeue", "");
+    fake.add_query("sacct", "200\n201_1\n201_2\n");
+
+    let output = fake.run(&["--after-notok", "align", "cleanup", "echo cleanup"]);
+
+    assert!(output.status.success());
+    assert!(fake
+        .recorded_script()
+        .contains("#SBATCH --dependency=afternotok:201\n"));
+}
+
+#[test]
+fn json_unknown_dependency_name_returns_a_structured_validation_error() {
+    let fake = FakeSbatch::new("unexpected", "unexpected", 0);
+    fake.add_query("squeue", "");
+    fake.add_query("sacct", "");
+
+    let output = fake.run(&["--json", "--after", "align", "merge", "echo merge"]);
+
+    assert!(!output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["error"]["kind"], json!("validation"));
+    assert_eq!(
+        response["error"]["message"],
+        json!("No job named align was found for the current user")
+    );
+    assert!(!Path::new(&fake.invoked_path).exists());
+}
+
+const PIPELINE: &str = r#"
+[defaults]
+mem = "2G"
+
+[[jobs]]
+name = "merge"
+command = "cat a.paf b.paf > all.paf"
+depends-on = ["align_a", "align_b"]
+
+[[jobs]]
+name = "align_a"
+command = "echo a"
+cpus-per-task = 4
+
+[[jobs]]
+name = "align_b"
+command = "echo b"
+sbatch-args = ["--constraint=avx2"]
+"#;
+
+#[test]
+fn json_pipeline_submits_jobs_in_dependency_order() {
+    let fake = FakeSbatch::new("unused", "", 0);
+    fake.numbering_jobs();
+    let pipeline = fake.directory.join("pipeline.toml");
+    fs::write(&pipeline, PIPELINE).expect("write pipeline");
+
+    let output = fake.run(&["run", "--json", pipeline.to_str().expect("utf-8 path")]);
+
+    assert!(output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["operation"], json!("run"));
+    let jobs = response["jobs"].as_array().expect("jobs");
+    let names: Vec<&Value> = jobs.iter().map(|job| &job["plan"]["job"]["name"]).collect();
+    assert_eq!(
+        names,
+        [&json!("align_a"), &json!("align_b"), &json!("merge")]
+    );
+    let ids: Vec<&Value> = jobs
+        .iter()
+        .map(|job| &job["submission"]["job_id"])
+        .collect();
+    assert_eq!(ids, [&json!("101"), &json!("102"), &json!("103")]);
+
+    assert_eq!(jobs[0]["plan"]["job"]["memory"], json!("2000M"));
+    assert_eq!(jobs[0]["plan"]["job"]["cpus_per_task"], json!(4));
+    assert_eq!(
+        jobs[1]["plan"]["slurm"]["arguments"],
+        json!(["--constraint=avx2", "--export=ALL", "--parsable"])
+    );
+    let merge = &jobs[2]["plan"]["job"];
+    assert_eq!(merge["dependency"], json!("afterok:101:102"));
+    assert_eq!(
+        merge["dependencies"],
+        json!([
+            {"type": "afterok", "job": "align_a", "job_ids": ["101"]},
+            {"type": "afterok", "job": "align_b", "job_ids": ["102"]}
+        ])
+    );
+    let script = fs::read_to_string(fake.directory.join("script.103")).expect("read script");
+    assert!(script.contains("#SBATCH --dependency=afterok:101:102\n"));
+}
+
+#[test]
+fn pipeline_dry_run_prints_every_plan_without_invoking_sbatch() {
+    let fake = FakeSbatch::new("unexpected", "unexpected", 99);
+    let pipeline = fake.directory.join("pipeline.toml");
+    fs::write(&pipeline, PIPELINE).expect("write pipeline");
+
+    let output = fake.run(&["run", "--dry-run", pipeline.to_str().expect("utf-8 path")]);
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let align_a = stdout.find("# align_a\n").expect("align_a plan");
+    let align_b = stdout.find("# align_b\n").expect("align_b plan");
+    let merge = stdout.find("# merge\n").expect("merge plan");
+    assert!(align_a < align_b && align_b < merge);
+    assert!(stdout.contains("#SBATCH --dependency=afterok:<align_a>:<align_b>\n"));
+    assert!(!Path::new(&fake.invoked_path).exists());
+}
+
+#[test]
+fn json_pipeline_with_an_unknown_option_returns_a_structured_validation_error() {
+    let fake = FakeSbatch::new("unexpected", "unexpected", 0);
+    let pipeline = fake.directory.join("pipeline.toml");
+    fs::write(
+        &pipeline,
+        "[[jobs]]\nname = \"a\"\ncommand = \"echo a\"\nmemory = \"2G\"\n",
+    )
+    .expect("write pipeline");
+
+    let output = fake.run(&["run", "--json", pipeline.to_str().expect("utf-8 path")]);
+
+    assert!(!output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["operation"], json!("run"));
+    assert_eq!(response["jobs"], json!([]));
+    assert_eq!(response["error"]["kind"], json!("validation"));
+    assert_eq!(
+        response["error"]["message"],
+        json!("Invalid options for job a: unexpected argument '--memory' found")
+    );
+    assert!(!Path::new(&fake.invoked_path).exists());
+}
+
+#[test]
+fn json_pipeline_stops_at_the_first_rejected_job() {
+    let fake = FakeSbatch::new("", "sbatch: error: invalid partition", 1);
+    let pipeline = fake.directory.join("pipeline.toml");
+    fs::write(&pipeline, PIPELINE).expect("write pipeline");
+
+    let output = fake.run(&["run", "--json", pipeline.to_str().expect("utf-8 path")]);
+
+    assert!(!output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["jobs"], json!([]));
+    assert_eq!(response["error"]["kind"], json!("slurm"));
+    assert_eq!(
+        response["error"]["message"],
+        json!("Job align_a: Failed to submit job with exit code 1")
+    );
+    assert_eq!(
+        response["error"]["stderr"],
+        json!("sbatch: error: invalid partition")
+    );
+}
+
+#[test]
+fn json_status_reports_queued_and_finished_jobs() {
+    let fake = FakeSbatch::new("unexpected", "unexpected", 0);
+    fake.add_query(
+        "squeue",
+        "130|align|RUNNING|5:02|node7|None\n131|merge|PENDING|0:00||Dependency\n",
+    );
+    fake.add_query(
+        "sacct",
+        "120|index|COMPLETED|00:10:00|node1|0:0\n130|align|RUNNING|00:05:02|node7|0:0\n",
+    );
+
+    let output = fake.run(&["status", "--json", "130", "index"]);
+
+    assert!(output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["operation"], json!("status"));
+    assert_eq!(
+        response["statuses"],
+        json!([
+            {"job_id": "130", "name": "align", "state": "RUNNING", "elapsed": "5:02", "nodes": "node7"},
+            {"job_id": "120", "name": "index", "state": "COMPLETED", "elapsed": "00:10:00", "nodes": "node1", "exit_code": 0, "signal": 0}
+        ])
+    );
+}
+
+#[test]
+fn status_prints_a_table_of_jobs() {
+    let fake = FakeSbatch::new("unexpected", "unexpected", 0);
+    fake.add_query("squeue", "131|merge|PENDING|0:00||Dependency\n");
+    fake.add_query("sacct", "");
+
+    let output = fake.run(&["status", "merge"]);
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        lines,
+        vec![
+            "JOBID  NAME   STATE    ELAPSED  NODES  EXIT  REASON",
+            "131    merge  PENDING  0:00     -      -     Dependency",
+        ]
+    );
+}
+
+#[test]
+fn json_status_of_an_unknown_job_returns_a_structured_validation_error() {
+    let fake = FakeSbatch::new("unexpected", "unexpected", 0);
+    fake.add_query("squeue", "");
+    fake.add_query("sacct", "");
+
+    let output = fake.run(&["status", "--json", "999"]);
+
+    assert!(!output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["operation"], json!("status"));
+    assert_eq!(response["error"]["kind"], json!("validation"));
+    assert_eq!(
+        response["error"]["message"],
+        json!("No job with ID 999 was found")
+    );
+}
+
+#[test]
+fn json_status_reports_a_failed_query_as_a_slurm_error() {
+    let fake = FakeSbatch::new("unexpected", "unexpected", 0);
+    fake.add_query("squeue", "");
+    fake.add_failing_query(
+        "sacct",
+        "sacct: error: Slurm accounting storage is disabled",
+        1,
+    );
+
+    let output = fake.run(&["status", "--json", "123"]);
+
+    assert!(!output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["error"]["kind"], json!("slurm"));
+    assert_eq!(
+        response["error"]["message"],
+        json!("sacct failed with exit code 1")
+    );
+    assert_eq!(response["error"]["exit_code"], json!(1));
+    assert_eq!(
+        response["error"]["stderr"],
+        json!("sacct: error: Slurm accounting storage is disabled")
+    );
+}
+
+#[test]
+fn successful_submissions_are_recorded_in_the_history() {
+    let fake = FakeSbatch::new("Submitted batch job 123 on cluster alpha\n", "", 0);
+
+    let submitted = fake.run(&["-m", "2G", "align", "echo align"]);
+    assert!(submitted.status.success());
+    let rejected = FakeSbatch::new("", "error", 1);
+    let history_file = fake.directory.join("state/ssubmit/history.jsonl");
+    let rejected_output = rejected
+        .command()
+        .env("XDG_STATE_HOME", fake.directory.join("state"))
+        .args(["merge", "echo merge"])
+        .output()
+        .expect("run ssubmit");
+    assert!(!rejected_output.status.success());
+
+    let history = fs::read_to_string(&history_file).expect("read history");
+    let records: Vec<Value> = history
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("parse history record"))
+        .collect();
+    assert_eq!(records.len(), 1);
+    assert_eq!(
+        records[0]["submission"],
+        json!({"job_id": "123", "cluster": "alpha"})
+    );
+    assert_eq!(records[0]["plan"]["job"]["name"], json!("align"));
+    assert_eq!(records[0]["plan"]["job"]["memory"], json!("2000M"));
+    assert_eq!(
+        records[0]["plan"]["slurm"]["script"].as_str(),
+        Some(fake.recorded_script().as_str())
+    );
+    assert!(records[0]["submitted_at"].is_string());
+    assert!(records[0]["directory"].is_string());
+
+    let listed = fake.run(&["history", "--name", "align"]);
+    assert!(listed.status.success());
+    let stdout = String::from_utf8_lossy(&listed.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("SUBMITTED"));
+    assert!(lines[1].contains("  123    align  "));
+
+    let shown = fake.run(&["history", "--show", "123"]);
+    assert!(shown.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&shown.stdout),
+        fake.recorded_script()
+    );
+}
+
+#[test]
+fn history_filters_by_date_and_current_state() {
+    let fake = FakeSbatch::new("", "", 0);
+    fake.add_query("squeue", "201|merge|RUNNING|1:00|node1|None\n");
+    fake.add_query("sacct", "");
+    let records = [
+        ("200", "align", "2024-05-01T10:00:00Z"),
+        ("201", "merge", "2024-05-02T10:00:00Z"),
+        ("202", "report", "2024-05-03T10:00:00Z"),
+    ];
+    let mut history = String::new();
+    for (job_id, name, submitted_at) in records {
+        let record = json!({
+            "submitted_at": submitted_at,
+            "directory": "/work",
+            "plan": {
+                "job": {
+                    "name": name, "command": "true", "memory": "1G", "time": "1:0:0",
+                    "output": "%x.out", "error": "%x.err", "export": "ALL",
+                    "shebang": "#!/bin/sh", "set": "eu"
+                },
+                "slurm": {"executable": "sbatch", "arguments": [], "script": "#!/bin/sh\n"}
+            },
+            "submission": {"job_id": job_id, "cluster": null}
+        });
+        history.push_str(&format!("{record}\n"));
+    }
+    let state = fake.directory.join("state/ssubmit");
+    fs::create_dir_all(&state).expect("create state directory");
+    fs::write(state.join("history.jsonl"), history).expect("write history");
+
+    let listed = fake
+        .command()
+        .env("TZ", "UTC")
+        .args(["history", "--since", "2024-05-02", "--until", "2024-05-03"])
+        .output()
+        .expect("run ssubmit");
+    assert!(listed.status.success());
+    let stdout = String::from_utf8_lossy(&listed.stdout);
+    let jobs: Vec<&str> = stdout
+        .lines()
+        .skip(1)
+        .map(|line| line.split_whitespace().nth(2).unwrap_or_default())
+        .collect();
+    assert_eq!(jobs, vec!["201", "202"]);
+
+    let running = fake.run(&["history", "--state", "running"]);
+    assert!(running.status.success());
+    let stdout = String::from_utf8_lossy(&running.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].ends_with("STATE"));
+    assert!(lines[1].contains(" 201 ") && lines[1].ends_with("RUNNING"));
+}
+
+#[test]
+fn resubmit_renders_the_original_job_with_new_resources() {
+    let fake = FakeSbatch::new("Submitted batch job 123\n", "", 0);
+    let submitted = fake.run(&[
+        "-m",
+        "1G",
+        "-p",
+        "short",
+        "--after-ok",
+        "99",
+        "align",
+        "echo align",
+        "--",
+        "--constraint=avx2",
+    ]);
+    assert!(submitted.status.success());
+    let original = fake.recorded_script();
+
+    let fake_resubmit = |stdout: &str| {
+        let mut command = fake.command();
+        command.env("SSUBMIT_FAKE_STDOUT", stdout);
+        command
+    };
+    let output = fake_resubmit("124\n")
+        .args(["resubmit", "--json", "align", "--mem", "4G", "-t", "2h"])
+        .output()
+        .expect("run ssubmit");
+
+    assert!(output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["operation"], json!("submit"));
+    assert_eq!(response["submission"]["job_id"], json!("124"));
+    let job = &response["plan"]["job"];
+    assert_eq!(job["resubmit_of"], json!("123"));
+    assert_eq!(job["memory"], json!("4000M"));
+    assert_eq!(job["partition"], json!("short"));
+    assert!(job.get("dependency").is_none());
+    assert_eq!(
+        fake.recorded_args(),
+        "--constraint=avx2\n--export=ALL\n--parsable\n"
+    );
+    let expected = original
+        .replace("--mem=1000M", "--mem=4000M")
+        .replace("--time=24:0:0", "--time=2:0:0")
+        .replace("#SBATCH --dependency=afterok:99\n", "");
+    assert_eq!(fake.recorded_script(), expected);
+
+    let history = fs::read_to_string(fake.directory.join("state/ssubmit/history.jsonl"))
+        .expect("read history");
+    let latest: Value =
+        serde_json::from_str(history.lines().last().expect("history record")).expect("parse");
+    assert_eq!(latest["plan"]["job"]["resubmit_of"], json!("123"));
+    assert_eq!(latest["submission"]["job_id"], json!("124"));
+}
+
+#[test]
+fn resubmit_dry_run_reports_a_missing_log_directory() {
+    let fake = FakeSbatch::new("Submitted batch job 123\n", "", 0);
+    let submitted = fake
+        .command()
+        .current_dir(&fake.directory)
+        .args(["--mkdir", "-o", "logs/%x.out", "align", "echo align"])
+        .output()
+        .expect("run ssubmit");
+    assert!(submitted.status.success());
+    fs::remove_dir(fake.directory.join("logs")).expect("remove log directory");
+
+    let output = fake
+        .command()
+        .current_dir(&fake.directory)
+        .args(["resubmit", "--dry-run", "--json", "align", "--mem", "4G"])
+        .output()
+        .expect("run ssubmit");
+
+    assert!(!output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["error"]["kind"], json!("validation"));
+    assert_eq!(
+        response["error"]["message"],
+        json!("Directory logs for output file logs/align.out does not exist. Create it or use --mkdir")
+    );
+    assert!(!fake.directory.join("logs").exists());
+}
+
+#[test]
+fn json_resubmit_of_an_unknown_job_returns_a_structured_validation_error() {
+    let fake = FakeSbatch::new("unexpected", "unexpected", 0);
+
+    let output = fake.run(&["resubmit", "--json", "align"]);
+
+    assert!(!output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["error"]["kind"], json!("validation"));
+    assert_eq!(
+        response["error"]["message"],
+        json!("No job align was found in the history")
+    );
+    assert!(!Path::new(&fake.invoked_path).exists());
+}
+
+#[test]
+fn json_retry_submits_escalated_attempts_that_depend_on_the_earlier_ones() {
+    let fake = FakeSbatch::new("unused", "", 0);
+    fake.numbering_jobs();
+
+    let output = fake.run(&[
+        "--json",
+        "-m",
+        "1G",
+        "-t",
+        "1h",
+        "--retry",
+        "2",
+        "--escalate-mem",
+        "2x",
+        "--escalate-time",
+        "1.5x",
+        "align",
+        "echo align",
+    ]);
+
+    assert!(output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["submission"]["job_id"], json!("101"));
+    let jobs = response["jobs"].as_array().expect("retries");
+    let summary: Vec<Value> = jobs
+        .iter()
+        .map(|job| {
+            json!([
+                job["submission"]["job_id"],
+                job["plan"]["job"]["resubmit_of"],
+                job["plan"]["job"]["dependency"],
+                job["plan"]["job"]["memory"],
+                job["plan"]["job"]["time"]
+            ])
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            json!(["102", "101", "afternotok:101", "2000M", "1:30:0"]),
+            json!(["103", "102", "afternotok:101:102", "4000M", "2:15:0"]),
+        ]
+    );
+    let retry = fs::read_to_string(fake.directory.join("script.103")).expect("read retry");
+    assert!(retry.contains("#SBATCH --mem=4000M\n"));
+    assert!(retry.contains("sacct --jobs=102 "));
+}
+
+#[test]
+fn retries_are_all_removed_when_the_first_attempt_completes() {
+    let fake = FakeSbatch::new("unused", "", 0);
+    fake.numbering_jobs();
+
+    let output = fake.run(&["--json", "--retry", "3", "align", "echo align"]);
+
+    assert!(output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    let jobs = response["jobs"].as_array().expect("retries");
+    let dependencies: Vec<&str> = jobs
+        .iter()
+        .map(|job| {
+            job["plan"]["job"]["dependency"]
+                .as_str()
+                .expect("dependency")
+        })
+        .collect();
+    assert_eq!(
+        dependencies,
+        vec![
+            "afternotok:101",
+            "afternotok:101:102",
+            "afternotok:101:102:103"
+        ]
+    );
+    for job in jobs {
+        let arguments = job["plan"]["slurm"]["arguments"]
+            .as_array()
+            .expect("arguments");
+        assert!(arguments.contains(&json!("--kill-on-invalid-dep=yes")));
+    }
+
+    // Slurm only starts an afternotok job once all of the listed jobs have failed, and removes
+    // it, as cancelled, once one of them completes
+    let mut states = vec![("101", "COMPLETED")];
+    for (id, dependency) in ["102", "103", "104"].iter().zip(&dependencies) {
+        let after = dependency.trim_start_matches("afternotok:").split(':');
+        let removed = after
+            .map(|job| states.iter().find(|(id, _)| *id == job).expect("earlier").1)
+            .any(|state| state == "COMPLETED");
+        states.push((id, if removed { "CANCELLED" } else { "COMPLETED" }));
+    }
+    assert_eq!(
+        states,
+        vec![
+            ("101", "COMPLETED"),
+            ("102", "CANCELLED"),
+            ("103", "CANCELLED"),
+            ("104", "CANCELLED"),
+        ]
+    );
+}
+
+#[test]
+fn retry_runs_the_command_only_after_running_out_of_memory_or_time() {
+    let fake = FakeSbatch::new("", "", 0);
+    let output = fake.run(&["--dry-run", "--json", "--retry", "1", "align", "echo ran"]);
+    assert!(output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    let retry = response["jobs"][0]["plan"]["slurm"]["script"]
+        .as_str()
+        .expect("retry script");
+    assert!(retry.contains("sacct --jobs=<attempt 1> "));
+    let script = retry.replace("<attempt 1>", "101");
+    let script_path = fake.directory.join("retry.sh");
+    fs::write(&script_path, script).expect("write retry script");
+
+    for (state, expected) in [
+        ("OUT_OF_MEMORY", "ran\n"),
+        ("TIMEOUT", "ran\n"),
+        ("FAILED", ""),
+    ] {
+        fake.add_query("sacct", &format!("{state}\n"));
+        let run = Command::new("bash")
+            .arg(&script_path)
+            .env(
+                "PATH",
+                format!("{}:/usr/bin:/bin", fake.directory.display()),
+            )
+            .output()
+            .expect("run retry script");
+        assert!(run.status.success(), "{state}");
+        assert_eq!(String::from_utf8_lossy(&run.stdout), expected, "{state}");
+    }
+}
+
+#[test]
+fn json_retry_with_a_passthrough_dependency_returns_a_structured_validation_error() {
+    let fake = FakeSbatch::new("unexpected", "unexpected", 0);
+
+    let output = fake.run(&[
+        "--json",
+        "--retry",
+        "1",
+        "align",
+        "echo align",
+        "--",
+        "--dependency=afterok:1",
+    ]);
+
+    assert!(!output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["error"]["kind"], json!("validation"));
+    assert!(!Path::new(&fake.invoked_path).exists());
+}
+
+const QUEUE: &str = "301|align_a|RUNNING|1:00|node1|None\n302|align_b|PENDING|0:00||Priority\n303|merge|PENDING|0:00||Dependency\n";
+
+#[test]
+fn json_cancel_matches_name_patterns_and_reports_cancelled_jobs() {
+    let fake = FakeSbatch::new("unexpected", "unexpected", 0);
+    fake.add_query("squeue", QUEUE);
+    fake.add_scancel("none");
+
+    let output = fake.run(&["cancel", "--json", "--yes", "--regex", "align_.*"]);
+
+    assert!(output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["operation"], json!("cancel"));
+    assert_eq!(
+        response["cancellation"],
+        json!({"cancelled": ["301", "302"], "failed": []})
+    );
+    assert_eq!(response["statuses"].as_array().map(Vec::len), Some(2));
+    let cancelled = fs::read_to_string(fake.directory.join("cancelled")).expect("cancelled");
+    assert_eq!(cancelled, "301\n302\n");
+}
+
+#[test]
+fn json_cancel_lists_jobs_that_could_not_be_cancelled() {
+    let fake = FakeSbatch::new("unexpected", "unexpected", 0);
+    fake.add_query("squeue", QUEUE);
+    fake.add_scancel("302");
+
+    let output = fake.run(&["cancel", "--json", "-y", "302", "merge"]);
+
+    assert!(!output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["ok"], json!(false));
+    assert_eq!(response["cancellation"]["cancelled"], json!(["303"]));
+    let failure = &response["cancellation"]["failed"][0];
+    assert_eq!(failure["job_id"], json!("302"));
+    assert_eq!(failure["kind"], json!("slurm"));
+    assert_eq!(failure["exit_code"], json!(1));
+    assert_eq!(
+        response["error"]["message"],
+        json!("Failed to cancel 1 of 2 jobs")
+    );
+}
+
+#[test]
+fn cancel_asks_for_confirmation_before_cancelling() {
+    let fake = FakeSbatch::new("unexpected", "unexpected", 0);
+    fake.add_query("squeue", QUEUE);
+    fake.add_scancel("none");
+
+    let unanswered = fake.run(&["cancel", "merge"]);
+    assert!(!unanswered.status.success());
+    assert!(String::from_utf8_lossy(&unanswered.stdout).contains("303    merge"));
+
+    let mut declined = fake
+        .command()
+        .args(["cancel", "merge"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .expect("run ssubmit");
+    {
+        use std::io::Write;
+        let stdin = declined.stdin.as_mut().expect("ssubmit stdin");
+        stdin.write_all(b"n\n").expect("answer");
+    }
+    assert!(declined.wait().expect("wait for ssubmit").success());
+    assert!(!fake.directory.join("cancelled").exists());
+
+    let refused = fake.run(&["cancel", "--json", "merge"]);
+    assert!(!refused.status.success());
+    let response = parse_json(&refused);
+    assert_matches_schema(&response);
+    assert_eq!(response["error"]["kind"], json!("validation"));
+
+    let unknown = fake.run(&["cancel", "--json", "--yes", "index"]);
+    assert!(!unknown.status.success());
+    let response = parse_json(&unknown);
+    assert_eq!(
+        response["error"]["message"],
+        json!("No queued or running job of the current user matches index")
+    );
+    assert!(!fake.directory.join("cancelled").exists());
+}
+
+#[test]
+fn json_wait_reports_the_outcome_and_exits_with_the_job_exit_code() {
+    let fake = FakeSbatch::new("123\n", "", 0);
+    fake.add_job_states(&[("123", &["FAILED 3:0"])]);
+
+    let output = fake.run(&["--json", "--wait", "align", "exit 3"]);
+
+    assert_eq!(output.status.code(), Some(3));
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["ok"], json!(true));
+    assert_eq!(response["submission"]["job_id"], json!("123"));
+    assert_eq!(
+        response["outcome"],
+        json!({
+            "job_id": "123", "name": "job", "state": "FAILED", "elapsed": "00:00:05",
+            "nodes": "node1", "exit_code": 3, "signal": 0
+        })
+    );
+}
+
+#[test]
+fn wait_fails_when_accounting_never_records_the_job() {
+    let fake = FakeSbatch::new("123\n", "", 0);
+    fake.add_query("sacct", "");
+    fake.add_query("squeue", "");
+
+    let output = fake.run(&["--json", "--wait", "align", "echo align"]);
+
+    assert!(!output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["submission"]["job_id"], json!("123"));
+    assert_eq!(response["error"]["kind"], json!("output"));
+    assert_eq!(
+        response["error"]["message"],
+        json!("Slurm accounting has no record of job 123 and it is not in the queue, so its outcome is unknown. Check that accounting (sacct) is enabled")
+    );
+}
+
+#[test]
+fn wait_logs_state_changes_until_the_job_finishes() {
+    let fake = FakeSbatch::new("Submitted batch job 123\n", "", 0);
+    fake.add_job_states(&[("123", &["RUNNING 0:0", "COMPLETED 0:0"])]);
+
+    let output = fake.run(&["--wait", "align", "echo align"]);
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Job 123 is RUNNING"));
+    assert!(stderr.contains("Job 123 is COMPLETED"));
+    assert!(stderr.contains("Job 123 finished as COMPLETED after 00:00:05 with exit code 0"));
+}
+
+#[test]
+fn wait_follows_a_retry_after_running_out_of_memory() {
+    let fake = FakeSbatch::new("unused", "", 0);
+    fake.numbering_jobs();
+    fake.add_job_states(&[
+        ("101", &["OUT_OF_MEMORY 0:125"]),
+        ("102", &["TIMEOUT 0:15"]),
+    ]);
+
+    let output = fake.run(&[
+        "--json",
+        "--wait",
+        "--retry",
+        "1",
+        "--escalate-mem",
+        "2x",
+        "align",
+        "echo align",
+    ]);
+
+    assert_eq!(output.status.code(), Some(128 + 15));
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["outcome"]["job_id"], json!("102"));
+    assert_eq!(response["outcome"]["state"], json!("TIMEOUT"));
+}
+
+#[test]
+fn follow_streams_the_job_log_files_until_it_finishes() {
+    let fake = FakeSbatch::new("Submitted batch job 123\n", "", 0);
+    fake.add_job_states(&[("123", &["RUNNING 0:0", "COMPLETED 0:0"])]);
+    fs::write(fake.directory.join("align_123.out"), "aligned 10 reads\n").unwrap();
+    fs::write(fake.directory.join("tester.err"), "warning: low quality").unwrap();
+
+    let output = fake
+        .command()
+        .current_dir(&fake.directory)
+        .env("USER", "tester")
+        .args([
+            "--follow",
+            "-o",
+            "%x_%j.out",
+            "-e",
+            "%u.err",
+            "align",
+            "bwa mem",
+        ])
+        .output()
+        .expect("run ssubmit");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("[out] aligned 10 reads\n"));
+    assert!(stdout.contains("[err] warning: low quality\n"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Job 123 finished as COMPLETED"));
+}
+
+#[test]
+fn follow_reads_relative_log_files_from_the_job_directory() {
+    let fake = FakeSbatch::new("Submitted batch job 123\n", "", 0);
+    fake.add_job_states(&[("123", &["RUNNING 0:0", "COMPLETED 0:0"])]);
+    let work = fake.directory.join("work");
+    fs::create_dir_all(work.join("logs")).unwrap();
+    fs::write(work.join("logs/align_123.out"), "aligned 10 reads\n").unwrap();
+    fs::write(work.join("logs/align_123.err"), "warning: low quality\n").unwrap();
+
+    let output = fake
+        .command()
+        .current_dir(&fake.directory)
+        .args([
+            "--follow",
+            "-o",
+            "logs/%x_%j.out",
+            "-e",
+            "logs/%x_%j.err",
+            "align",
+            "bwa mem",
+            "--",
+            "--chdir=work",
+        ])
+        .output()
+        .expect("run ssubmit");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("[out] aligned 10 reads\n"));
+    assert!(stdout.contains("[err] warning: low quality\n"));
+}
+
+#[test]
+fn follow_cannot_be_used_with_json() {
+    let fake = FakeSbatch::new("123\n", "", 0);
+
+    let output = fake.run(&["--json", "--follow", "align", "bwa mem"]);
+
+    assert!(!output.status.success());
+    assert!(!Path::new(&fake.invoked_path).exists());
+}
+
+#[test]
+fn json_responses_resolve_log_file_names() {
+    let fake = FakeSbatch::new("123\n", "", 0);
+
+    let plan = fake.run(&["--json", "--dry-run", "-o", "%x_%j.out", "align", "bwa mem"]);
+    let response = parse_json(&plan);
+    assert_matches_schema(&response);
+    assert_eq!(
+        response["logs"],
+        json!({
+            "output": {"path": "align_%j.out", "unresolved": ["%j"]},
+            "error": {"path": "align.err"}
+        })
+    );
+
+    let submit = fake.run(&[
+        "--json",
+        "-o",
+        "%x_%j.out",
+        "-e",
+        "%x_%5j.err",
+        "align",
+        "bwa mem",
+    ]);
+    let response = parse_json(&submit);
+    assert_matches_schema(&response);
+    assert_eq!(
+        response["logs"],
+        json!({
+            "output": {"path": "align_123.out"},
+            "error": {"path": "align_00123.err"}
+        })
+    );
+}
+
+#[test]
+fn dry_run_logs_the_resolved_log_file_names() {
+    let fake = FakeSbatch::new("", "", 0);
+
+    let output = fake
+        .command()
+        .env("USER", "tester")
+        .args([
+            "--dry-run",
+            "--mkdir",
+            "-o",
+            "%u/%x.out",
+            "align",
+            "bwa mem",
+        ])
+        .output()
+        .expect("run ssubmit");
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Output would be written to tester/align.out and errors to align.err"));
+}
+
+#[test]
+fn missing_log_directory_is_rejected_before_submission() {
+    let fake = FakeSbatch::new("123\n", "", 0);
+
+    let output = fake
+        .command()
+        .current_dir(&fake.directory)
+        .args(["-o", "logs/%x.out", "align", "bwa mem"])
+        .output()
+        .expect("run ssubmit");
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains(
+        "Directory logs for output file logs/align.out does not exist. Create it or use --mkdir"
+    ));
+    assert!(!Path::new(&fake.invoked_path).exists());
+
+    let output = fake
+        .command()
+        .current_dir(&fake.directory)
+        .args(["--json", "-e", "logs/%x.err", "align", "bwa mem"])
+        .output()
+        .expect("run ssubmit");
+
+    assert!(!output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["ok"], json!(false));
+    assert_eq!(response["error"]["kind"], json!("validation"));
+    assert!(!Path::new(&fake.invoked_path).exists());
+
+    // a dry run reports the missing directory too, without creating it
+    let output = fake
+        .command()
+        .current_dir(&fake.directory)
+        .args([
+            "--dry-run",
+            "--json",
+            "-o",
+            "logs/%x.out",
+            "align",
+            "bwa mem",
+        ])
+        .output()
+        .expect("run ssubmit");
+    assert!(!output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["error"]["kind"], json!("validation"));
+    assert_eq!(
+        response["error"]["message"],
+        json!("Directory logs for output file logs/align.out does not exist. Create it or use --mkdir")
+    );
+    assert!(!fake.directory.join("logs").exists());
+
+    // which --mkdir would create on submission
+    let output = fake
+        .command()
+        .current_dir(&fake.directory)
+        .args([
+            "--dry-run",
+            "--mkdir",
+            "-o",
+            "logs/%x.out",
+            "align",
+            "bwa mem",
+        ])
+        .output()
+        .expect("run ssubmit");
+    assert!(output.status.success());
+    assert!(!fake.directory.join("logs").exists());
+}
+
+#[test]
+fn mkdir_creates_missing_log_directories() {
+    let fake = FakeSbatch::new("Submitted batch job 123\n", "", 0);
+
+    let output = fake
+        .command()
+        .current_dir(&fake.directory)
+        .args([
+            "--mkdir",
+            "-o",
+            "logs/out/%x.out",
+            "-e",
+            "logs/%j/%x.err",
+            "align",
+            "bwa mem",
+        ])
+        .output()
+        .expect("run ssubmit");
+
+    assert!(output.status.success());
+    assert!(fake.directory.join("logs/out").is_dir());
+    // the job ID is not known before submission, so Slurm has to cope with that one
+    assert!(!fake.directory.join("logs/%j").exists());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Created directory logs/out"));
+    assert!(fake
+        .recorded_script()
+        .contains("#SBATCH --output=logs/out/%x.out"));
+}
+
+#[test]
+fn pipeline_checks_and_creates_log_directories() {
+    let fake = FakeSbatch::new("unused", "", 0);
+    fake.numbering_jobs();
+    let pipeline = fake.directory.join("pipeline.toml");
+    fs::write(
+        &pipeline,
+        "[[jobs]]\nname = \"a\"\ncommand = \"echo a\"\noutput = \"logs/%x.out\"\n",
+    )
+    .expect("write pipeline");
+    let run = |args: &[&str]| {
+        fake.command()
+            .current_dir(&fake.directory)
+            .arg("run")
+            .args(args)
+            .arg("pipeline.toml")
+            .output()
+            .expect("run ssubmit")
+    };
+
+    let output = run(&[]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Directory logs for output file"));
+    assert!(!fake.directory.join("script.101").exists());
+
+    let output = run(&["--dry-run"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Directory logs for output file"));
+    assert!(!fake.directory.join("logs").exists());
+
+    let output = run(&["--mkdir"]);
+    assert!(output.status.success());
+    assert!(fake.directory.join("logs").is_dir());
+    assert!(fake.directory.join("script.101").exists());
+}
+
+#[test]
+fn leading_double_dash_submits_a_job_named_after_a_subcommand() {
+    let fake = FakeSbatch::new("Submitted batch job 123\n", "", 0);
+
+    // without it, the name is taken to be the subcommand
+    let output = fake.run(&["run", "echo hi"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("To submit a job named run, use `ssubmit -- run <command>`"));
+    assert!(!Path::new(&fake.invoked_path).exists());
+
+    for name in ["run", "status", "history", "resubmit", "cancel"] {
+        let output = fake.run(&[
+            "--",
+            name,
+            "echo hi",
+            "--dry-run",
+            "--json",
+            "--",
+            "--constraint=avx2",
+        ]);
+        assert!(output.status.success(), "{name}");
+        let response = parse_json(&output);
+        assert_matches_schema(&response);
+        assert_eq!(response["plan"]["job"]["name"], json!(name));
+        assert_eq!(response["plan"]["job"]["command"], json!("echo hi"));
+        assert_eq!(
+            response["plan"]["slurm"]["arguments"][0],
+            json!("--constraint=avx2")
+        );
+    }
+}
+
+#[test]
+fn pipeline_jobs_set_flags_with_booleans() {
+    let fake = FakeSbatch::new("unused", "", 0);
+    fake.numbering_jobs();
+    fs::write(
+        fake.directory.join("pipeline.toml"),
+        "[[jobs]]\nname = \"a\"\ncommand = \"echo a\"\noutput = \"logs/%x.out\"\nmkdir = true\nno-check-limits = false\n",
+    )
+    .expect("write pipeline");
+
+    let output = fake
+        .command()
+        .current_dir(&fake.directory)
+        .args(["run", "pipeline.toml"])
+        .output()
+        .expect("run ssubmit");
+
+    assert!(output.status.success());
+    assert!(fake.directory.join("logs").is_dir());
+    assert!(fake.directory.join("script.101").exists());
+}
+
+const SCONTROL_PARTITIONS: &str = "PartitionName=short Default=YES MaxNodes=UNLIMITED MaxTime=04:00:00 MaxMemPerNode=64000 State=UP
+PartitionName=long Default=NO MaxNodes=UNLIMITED MaxTime=7-00:00:00 MaxMemPerNode=UNLIMITED State=UP
+";
+
+#[test]
+fn jobs_over_the_partition_limits_are_rejected_before_submission() {
+    let fake = FakeSbatch::new("123\n", "", 0);
+    fake.add_query("scontrol", SCONTROL_PARTITIONS);
+    fake.add_query("sinfo", "short*|16|64000|(null)\nlong|64|256000|(null)\n");
+
+    let output = fake.run(&["--json", "-t", "1d", "align", "bwa mem"]);
+
+    assert!(!output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["error"]["kind"], json!("validation"));
+    assert_eq!(
+        response["error"]["message"],
+        json!("Partition short allows at most 04:00:00, but a time limit of 24:0:0 was requested")
+    );
+    assert!(!Path::new(&fake.invoked_path).exists());
+
+    let output = fake.run(&[
+        "-m",
+        "8G",
+        "-t",
+        "1h",
+        "--retry",
+        "2",
+        "--escalate-mem",
+        "4x",
+        "align",
+        "bwa mem",
+    ]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains(
+        "Retry 2: Partition short allows at most 64000M of memory per node, but 128000M was requested"
+    ));
+    assert!(!Path::new(&fake.invoked_path).exists());
+
+    let output = fake.run(&["--json", "-t", "1d", "-p", "long", "align", "bwa mem"]);
+    assert!(output.status.success());
+
+    let output = fake.run(&[
+        "--json",
+        "--no-check-limits",
+        "-t",
+        "1d",
+        "align",
+        "bwa mem",
+    ]);
+    assert!(output.status.success());
+}
+
+#[test]
+fn partition_limits_are_cached_and_refreshed_before_rejecting_a_job() {
+    let fake = FakeSbatch::new("123\n", "", 0);
+    fake.add_query("scontrol", SCONTROL_PARTITIONS);
+    let cache = fake.directory.join("cache");
+    let run = |args: &[&str]| {
+        fake.command()
+            .env("XDG_CACHE_HOME", &cache)
+            .args(args)
+            .output()
+            .expect("run ssubmit")
+    };
+
+    assert!(run(&["--json", "-t", "1h", "align", "bwa mem"])
+        .status
+        .success());
+    assert!(cache.join("ssubmit/partitions.json").exists());
+
+    // the cached limits are used while the scheduler cannot be asked
+    fake.add_failing_query("scontrol", "scontrol: error: slurmctld down", 1);
+    assert!(run(&["--json", "-t", "1h", "align", "bwa mem"])
+        .status
+        .success());
+
+    // a job the cached limits reject is checked against the current limits
+    fake.add_query(
+        "scontrol",
+        "PartitionName=short Default=YES MaxTime=2-00:00:00 State=UP\n",
+    );
+    assert!(run(&["--json", "-t", "1d", "align", "bwa mem"])
+        .status
+        .success());
+}
+
+#[test]
+fn auto_partition_chooses_the_shortest_partition_that_fits() {
+    let fake = FakeSbatch::new("123\n", "", 0);
+    fake.add_query("scontrol", SCONTROL_PARTITIONS);
+    fake.add_query("sinfo", "short*|16|64000|(null)\nlong|64|256000|(null)\n");
+
+    let output = fake
+        .command()
+        .env("SSUBMIT_PARTITION", "short")
+        .args([
+            "--json",
+            "--dry-run",
+            "--auto-partition",
+            "-t",
+            "10h",
+            "align",
+            "bwa mem",
+        ])
+        .output()
+        .expect("run ssubmit");
+
+    assert!(output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["plan"]["job"]["partition"], json!("long"));
+    assert_eq!(
+        response["plan"]["job"]["partition_reason"],
+        json!("it is the only partition the job fits in, with a time limit of 7-00:00:00")
+    );
+    assert!(response["plan"]["slurm"]["script"]
+        .as_str()
+        .unwrap()
+        .contains("#SBATCH --partition=long\n"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Chose partition long, as it is"));
+
+    let output = fake.run(&["--auto-partition", "-t", "1h", "align", "bwa mem"]);
+    assert!(output.status.success());
+    assert!(fake
+        .recorded_script()
+        .contains("#SBATCH --partition=short\n"));
+
+    let output = fake.run(&[
+        "--json",
+        "--auto-partition",
+        "-p",
+        "long",
+        "align",
+        "bwa mem",
+    ]);
+    assert!(!output.status.success());
+    assert_eq!(
+        parse_json(&output)["error"]["message"],
+        json!("--auto-partition cannot be used with --partition")
+    );
+}
+
+#[test]
+fn container_wraps_the_command_and_is_recorded_in_the_plan() {
+    let fake = FakeSbatch::new("", "", 0);
+
+    let output = fake.run(&[
+        "--json",
+        "--dry-run",
+        "--mkdir",
+        "--container",
+        "docker://ubuntu:24.04",
+        "--bind",
+        "/data,/scratch:/tmp",
+        "--nv",
+        "-o",
+        "/logs/%x.out",
+        "align",
+        "bwa mem ref.fa reads.fq | samtools sort",
+    ]);
+
+    assert!(output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    let job = &response["plan"]["job"];
+    assert_eq!(
+        job["command"],
+        json!("bwa mem ref.fa reads.fq | samtools sort")
+    );
+    assert_eq!(
+        job["container"],
+        json!({"image": "docker://ubuntu:24.04", "binds": ["/data", "/scratch:/tmp"], "nv": true})
+    );
+    let script = response["plan"]["slurm"]["script"]
+        .as_str()
+        .expect("script");
+    assert!(script.ends_with(
+        "\"$container_runtime\" exec --nv --bind \"$PWD\" --bind '/logs' --bind '/data' --bind '/scratch:/tmp' 'docker://ubuntu:24.04' bash -c 'set -euxo pipefail; bwa mem ref.fa reads.fq | samtools sort'\n"
+    ));
+}
+
+#[test]
+fn container_command_keeps_the_script_shell_options() {
+    let fake = FakeSbatch::new("", "", 0);
+    // runs the command on the host, as the container would
+    let apptainer = fake.directory.join("apptainer");
+    fs::write(
+        &apptainer,
+        "#!/bin/sh\nshift\nwhile [ \"$1\" = --nv ] || [ \"$1\" = --bind ]; do\n    [ \"$1\" = --bind ] && shift\n    shift\ndone\nshift\nexec \"$@\"\n",
+    )
+    .expect("write fake apptainer");
+    fs::set_permissions(&apptainer, fs::Permissions::from_mode(0o755))
+        .expect("make fake apptainer executable");
+
+    let output = fake.run(&[
+        "--json",
+        "--dry-run",
+        "--container",
+        "docker://ubuntu:24.04",
+        "align",
+        "false | true; echo survived",
+    ]);
+    assert!(output.status.success());
+    let response = parse_json(&output);
+    let script = response["plan"]["slurm"]["script"]
+        .as_str()
+        .expect("script");
+    let script_path = fake.directory.join("container.sh");
+    fs::write(&script_path, script).expect("write script");
+
+    let run = Command::new("bash")
+        .arg(&script_path)
+        .env(
+            "PATH",
+            format!("{}:/usr/bin:/bin", fake.directory.display()),
+        )
+        .output()
+        .expect("run script");
+
+    // pipefail and errexit end the command at the failed pipeline
+    assert!(!run.status.success());
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "");
+}
+
+#[test]
+fn missing_container_image_is_rejected() {
+    let fake = FakeSbatch::new("123\n", "", 0);
+
+    let output = fake
+        .command()
+        .current_dir(&fake.directory)
+        .args(["--container", "tools.sif", "align", "bwa mem"])
+        .output()
+        .expect("run ssubmit");
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Container image tools.sif does not exist"));
+    assert!(!Path::new(&fake.invoked_path).exists());
+
+    fs::write(fake.directory.join("tools.sif"), "").expect("write image");
+    let output = fake
+        .command()
+        .current_dir(&fake.directory)
+        .args(["--container", "tools.sif", "align", "bwa mem"])
+        .output()
+        .expect("run ssubmit");
+    assert!(output.status.success());
+    assert!(fake
+        .recorded_script()
+        .contains("--bind \"$PWD\" 'tools.sif' bash -c 'set -euxo pipefail; bwa mem'\n"));
+
+    let output = fake.run(&["--dry-run", "--nv", "align", "bwa mem"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("--bind and --nv can only be used with --container"));
+}
+
+#[test]
+fn conda_env_is_activated_before_the_command() {
+    let fake = FakeSbatch::new("", "", 0);
+    fs::write(fake.directory.join(".ssubmit.toml"), "conda-env = \"qc\"\n")
+        .expect("write project config");
+
+    let output = fake
+        .command()
+        .current_dir(&fake.directory)
+        .args(["--dry-run", "-s", "eu", "qc", "fastqc reads.fq"])
+        .output()
+        .expect("run ssubmit");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(
+        "set -eu\n\nset +u\nif command -v conda >/dev/null 2>&1; then\n    eval \"$(conda shell.bash hook)\"\n    conda activate 'qc'\n"
+    ));
+    assert!(stdout.contains("fi\nset -u\n\nfastqc reads.fq\n"));
+
+    let output = fake
+        .command()
+        .current_dir(&fake.directory)
+        .args([
+            "--dry-run",
+            "--json",
+            "--conda-env",
+            "/envs/qc",
+            "qc",
+            "fastqc reads.fq",
+        ])
+        .output()
+        .expect("run ssubmit");
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["plan"]["job"]["conda_env"], json!("/envs/qc"));
+
+    let output = fake
+        .command()
+        .current_dir(&fake.directory)
+        .args([
+            "--dry-run",
+            "--container",
+            "docker://ubuntu",
+            "qc",
+            "fastqc reads.fq",
+        ])
+        .output()
+        .expect("run ssubmit");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("--conda-env cannot be used with --container"));
+}
+
+#[test]
+fn modules_are_checked_and_loaded_before_the_command() {
+    let fake = FakeSbatch::new("Submitted batch job 123\n", "", 0);
+    fake.add_query(
+        "module",
+        "/opt/modulefiles:\nsamtools/1.17\nbwa/0.7.17(default)\n",
+    );
+
+    let output = fake.run(&[
+        "--module",
+        "samtools/1.18,bwa",
+        "--module",
+        "minimap2",
+        "align",
+        "bwa mem",
+    ]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains(
+        "Unknown modules: samtools/1.18, minimap2. Run `module avail` to see the available modules"
+    ));
+    assert!(!Path::new(&fake.invoked_path).exists());
+
+    let output = fake.run(&["--module", "samtools/1.17,bwa", "align", "bwa mem"]);
+
+    assert!(output.status.success());
+    assert!(fake.recorded_script().contains(
+        "set +ux\nmodule purge\nmodule load 'samtools/1.17' 'bwa'\nset -ux\n\nbwa mem\n"
+    ));
+}
+
+#[test]
+fn venv_and_pixi_environments_are_checked_and_activated() {
+    let fake = FakeSbatch::new("", "", 0);
+
+    let output = fake
+        .command()
+        .current_dir(&fake.directory)
+        .args(["--dry-run", "--venv", ".venv", "train", "python train.py"])
+        .output()
+        .expect("run ssubmit");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains(".venv is not a virtual environment, as .venv/bin/activate does not exist"));
+
+    fs::create_dir_all(fake.directory.join(".venv/bin")).expect("create venv");
+    fs::write(fake.directory.join(".venv/bin/activate"), "").expect("write activate");
+    let output = fake
+        .command()
+        .current_dir(&fake.directory)
+        .args([
+            "--dry-run",
+            "--json",
+            "--venv",
+            ".venv",
+            "train",
+            "python train.py",
+        ])
+        .output()
+        .expect("run ssubmit");
+    assert!(output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    assert_eq!(response["plan"]["job"]["venv"], json!(".venv"));
+    assert!(response["plan"]["slurm"]["script"]
+        .as_str()
+        .expect("script")
+        .ends_with("set +ux\nsource '.venv/bin/activate'\nset -ux\n\npython train.py\n"));
+
+    fs::write(fake.directory.join("pixi.toml"), "").expect("write manifest");
+    let output = fake
+        .command()
+        .current_dir(&fake.directory)
+        .args([
+            "--dry-run",
+            "--pixi",
+            "pixi.toml",
+            "train",
+            "python train.py",
+        ])
+        .output()
+        .expect("run ssubmit");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout)
+        .contains("eval \"$(pixi shell-hook --shell bash --manifest-path 'pixi.toml')\"\n"));
+
+    fs::write(fake.directory.join(".ssubmit.toml"), "venv = \".venv\"\n")
+        .expect("write project config");
+    let output = fake
+        .command()
+        .current_dir(&fake.directory)
+        .args([
+            "--dry-run",
+            "--pixi",
+            "pixi.toml",
+            "train",
+            "python train.py",
+        ])
+        .output()
+        .expect("run ssubmit");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--venv cannot be used with --pixi"));
+
+    let output = fake
+        .command()
+        .current_dir(&fake.directory)
+        .args([
+            "--dry-run",
+            "--venv",
+            "",
+            "--pixi",
+            "pixi.toml",
+            "train",
+            "python train.py",
+        ])
+        .output()
+        .expect("run ssubmit");
+    assert!(output.status.success());
+}
+
+#[test]
+fn prologue_file_is_relative_to_the_job_directory_and_blank_hooks_are_ignored() {
+    let fake = FakeSbatch::new("", "", 0);
+    let work = fake.directory.join("work");
+    fs::create_dir_all(&work).expect("create job directory");
+    fs::write(work.join("prologue.sh"), "echo starting\n").expect("write prologue");
+
+    let output = fake
+        .command()
+        .current_dir(&fake.directory)
+        .args([
+            "--dry-run",
+            "--json",
+            "--prologue",
+            "prologue.sh",
+            "--epilogue",
+            " ",
+            "align",
+            "echo running",
+            "--",
+            "--chdir=work",
+        ])
+        .output()
+        .expect("run ssubmit");
+
+    assert!(output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    let job = &response["plan"]["job"];
+    assert_eq!(job["prologue"], json!("echo starting\n"));
+    assert!(job.get("epilogue").is_none());
+    let script = response["plan"]["slurm"]["script"]
+        .as_str()
+        .expect("script");
+    assert!(!script.contains("ssubmit_epilogue"));
+}
+
+#[test]
+fn epilogue_runs_when_the_command_fails_and_keeps_its_exit_code() {
+    let fake = FakeSbatch::new("", "", 0);
+    fs::write(
+        fake.directory.join("epilogue.sh"),
+        "echo cleaning up\nfalse\necho done\n",
+    )
+    .expect("write epilogue");
+
+    let output = fake
+        .command()
+        .current_dir(&fake.directory)
+        .args([
+            "--dry-run",
+            "--json",
+            "--prologue",
+            "echo starting",
+            "--epilogue",
+            "epilogue.sh",
+            "align",
+            "echo running; (exit 3); echo unreachable",
+        ])
+        .output()
+        .expect("run ssubmit");
+
+    assert!(output.status.success());
+    let response = parse_json(&output);
+    assert_matches_schema(&response);
+    let job = &response["plan"]["job"];
+    assert_eq!(job["prologue"], json!("echo starting"));
+    assert_eq!(
+        job["epilogue"],
+        json!("echo cleaning up\nfalse\necho done\n")
+    );
+    let script = response["plan"]["slurm"]["script"]
+        .as_str()
+        .expect("script");
+    let script_path = fake.directory.join("job.sh");
+    fs::write(&script_path, script).expect("write job script");
+
+    let run = Command::new("bash")
+        .arg(&script_path)
+        .output()
+        .expect("run job script");
+    assert_eq!(run.status.code(), Some(3));
+    assert_eq!(
+        String::from_utf8_lossy(&run.stdout),
+        "starting\nrunning\ncleaning up\ndone\n"
+    );
+}